@@ -0,0 +1,915 @@
+use crate::tagged_hash;
+
+use anyhow::{anyhow, Error as AnyErr, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use secp256k1::hashes::hash160::Hash as Hash160;
+use secp256k1::hashes::sha256::Hash as Sha256Hash;
+use secp256k1::hashes::sha256d::Hash as Sha256dHash;
+use secp256k1::hashes::{Hash, HashEngine};
+use secp256k1::{
+    schnorr, KeyPair, Message, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey,
+};
+use std::convert::TryInto;
+
+const MSG_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+const MSG_TAG: &str = "BIP0322-signed-message";
+const BASE58_CHARS: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARS: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const P2PKH_MAINNET: u8 = 0x00;
+const P2PKH_TESTNET: u8 = 0x6f;
+const SIGHASH_DEFAULT: u8 = 0x00;
+const SIGHASH_ALL: u8 = 0x01;
+const OP_RETURN: u8 = 0x6a;
+
+// The address types that messages can be signed for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    P2pkh([u8; 20]),
+    P2wpkh([u8; 20]),
+    // the tweaked output key
+    P2tr([u8; 32]),
+}
+
+// Legacy is the 65 byte `bitcoin-cli signmessage` format, P2PKH only.
+// Simple is the BIP-322 witness stack, Full the whole to_sign transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigFormat {
+    Legacy,
+    Simple,
+    Full,
+}
+
+impl Address {
+    // mainnet, testnet, signet and regtest addresses are all accepted
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(pos) = s.rfind('1') {
+            let hrp = s[..pos].to_lowercase();
+            if hrp == "bc" || hrp == "tb" || hrp == "bcrt" {
+                return segwit_address(s);
+            }
+        }
+        Ok(Address::P2pkh(decode_p2pkh(s)?))
+    }
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        match self {
+            // OP_DUP OP_HASH160 <pkh> OP_EQUALVERIFY OP_CHECKSIG
+            Address::P2pkh(pkh) => {
+                let mut s = vec![0x76, 0xa9, 0x14];
+                s.extend_from_slice(pkh);
+                s.extend_from_slice(&[0x88, 0xac]);
+                s
+            }
+            // OP_0 <pkh>
+            Address::P2wpkh(pkh) => {
+                let mut s = vec![0x00, 0x14];
+                s.extend_from_slice(pkh);
+                s
+            }
+            // OP_1 <output key>
+            Address::P2tr(key) => {
+                let mut s = vec![0x51, 0x20];
+                s.extend_from_slice(key);
+                s
+            }
+        }
+    }
+}
+
+pub fn p2pkh_address(pubkey: &PublicKey, testnet: bool) -> String {
+    let version = if testnet {
+        P2PKH_TESTNET
+    } else {
+        P2PKH_MAINNET
+    };
+    let mut data = vec![version];
+    data.extend_from_slice(&pubkey_hash(pubkey, true));
+    let checksum = Sha256dHash::hash(&data);
+    data.extend_from_slice(&checksum[..4]);
+    base58_encode(&data)
+}
+
+pub fn p2wpkh_address(pubkey: &PublicKey, testnet: bool) -> String {
+    bech32_encode(hrp(testnet), 0, &pubkey_hash(pubkey, true))
+}
+
+// a key path only taproot address, with pubkey as the internal key
+pub fn p2tr_address(pubkey: &PublicKey, testnet: bool) -> Result<String> {
+    let secp = Secp256k1::verification_only();
+    let (internal, _) = pubkey.x_only_public_key();
+    let (output, _) = internal
+        .add_tweak(&secp, &taproot_tweak(&internal)?)
+        .map_err(AnyErr::msg)?;
+    Ok(bech32_encode(hrp(testnet), 1, &output.serialize()))
+}
+
+// Sign `msg` for `address`, which must belong to secret_key
+pub fn sign(
+    msg: &[u8],
+    secret_key: &SecretKey,
+    address: &str,
+    format: SigFormat,
+) -> Result<String> {
+    let addr = Address::parse(address)?;
+    let secp = Secp256k1::new();
+    let pubkey = PublicKey::from_secret_key(&secp, secret_key);
+    let witness = match (&addr, format) {
+        (Address::P2pkh(pkh), SigFormat::Legacy) => {
+            if *pkh != pubkey_hash(&pubkey, true) {
+                return Err(anyhow!("key does not match {}", address));
+            }
+            return sign_legacy(msg, secret_key);
+        }
+        (Address::P2pkh(_), _) => {
+            return Err(anyhow!("only legacy signatures for P2PKH addresses"));
+        }
+        (_, SigFormat::Legacy) => {
+            return Err(anyhow!("legacy signatures are only for P2PKH addresses"));
+        }
+        (Address::P2wpkh(pkh), _) => {
+            if *pkh != pubkey_hash(&pubkey, true) {
+                return Err(anyhow!("key does not match {}", address));
+            }
+            let to_sign = to_sign(&to_spend(msg, &addr), Vec::new());
+            let hash = segwit_v0_sighash(&to_sign, pkh);
+            let m = Message::from_slice(&hash).map_err(AnyErr::msg)?;
+            let mut sig = secp
+                .sign_ecdsa_low_r(&m, secret_key)
+                .serialize_der()
+                .to_vec();
+            sig.push(SIGHASH_ALL);
+            vec![sig, pubkey.serialize().to_vec()]
+        }
+        (Address::P2tr(output), _) => {
+            let (internal, _) = pubkey.x_only_public_key();
+            let keypair = KeyPair::from_secret_key(&secp, secret_key)
+                .add_xonly_tweak(&secp, &taproot_tweak(&internal)?)
+                .map_err(AnyErr::msg)?;
+            if keypair.x_only_public_key().0.serialize() != *output {
+                return Err(anyhow!("key does not match {}", address));
+            }
+            let to_sign = to_sign(&to_spend(msg, &addr), Vec::new());
+            let hash = taproot_sighash(&to_sign, &addr.script_pubkey(), SIGHASH_DEFAULT);
+            let m = Message::from_slice(&hash).map_err(AnyErr::msg)?;
+            let sig = secp.sign_schnorr(&m, &keypair);
+            vec![sig.as_ref().to_vec()]
+        }
+    };
+    let ret = match format {
+        SigFormat::Full => to_sign(&to_spend(msg, &addr), witness).serialize(),
+        _ => serialize_witness(&witness),
+    };
+    Ok(STANDARD.encode(ret))
+}
+
+// Verify a legacy, simple or full signature of `msg` by `address`
+pub fn verify(msg: &[u8], sig: &str, address: &str) -> Result<()> {
+    let addr = Address::parse(address)?;
+    let bytes = STANDARD.decode(sig).map_err(AnyErr::msg)?;
+    if let Address::P2pkh(pkh) = &addr {
+        return verify_legacy(msg, &bytes, pkh, address);
+    }
+    let to_spend = to_spend(msg, &addr);
+    let to_sign = match parse_witness(&bytes) {
+        Ok(witness) => to_sign(&to_spend, witness),
+        Err(_) => {
+            let tx = Tx::deserialize(&bytes)?;
+            check_full(&tx, &to_spend)?;
+            tx
+        }
+    };
+    verify_witness(&to_sign, &addr).map_err(|e| anyhow!("message not signed by {}: {}", address, e))
+}
+
+// The tagged hash of the message, committed to by to_spend
+pub fn message_hash(msg: &[u8]) -> [u8; 32] {
+    tagged_hash(MSG_TAG, msg)
+}
+
+// bitcoin::sign_message::signed_msg_hash
+pub fn signed_msg_hash(msg: &[u8]) -> Sha256dHash {
+    let mut engine = Sha256dHash::engine();
+    engine.input(MSG_PREFIX);
+    engine.input(&varint(msg.len() as u64));
+    engine.input(msg);
+    Sha256dHash::from_engine(engine)
+}
+
+// base64 MessageSignature, as produced by `bitcoin-cli signmessage`
+pub fn sign_legacy(msg: &[u8], secret_key: &SecretKey) -> Result<String> {
+    let secp = Secp256k1::signing_only();
+    let hash = signed_msg_hash(msg);
+    let m = Message::from_slice(&hash[..]).map_err(AnyErr::msg)?;
+    let (rid, sig) = secp
+        .sign_ecdsa_recoverable(&m, secret_key)
+        .serialize_compact();
+    // 27 + 4 for compressed
+    let mut ret = vec![(rid.to_i32() + 31) as u8];
+    ret.extend_from_slice(&sig[..]);
+    Ok(STANDARD.encode(ret))
+}
+
+fn verify_legacy(msg: &[u8], sig: &[u8], pkh: &[u8; 20], address: &str) -> Result<()> {
+    if sig.len() != 65 {
+        return Err(anyhow!("message signature must be 65 bytes"));
+    }
+    let (rid, compressed) = match sig[0] {
+        27..=30 => (sig[0] - 27, false),
+        31..=34 => (sig[0] - 31, true),
+        _ => return Err(anyhow!("invalid message signature header")),
+    };
+    let id = RecoveryId::from_i32(rid as i32).map_err(AnyErr::msg)?;
+    let s = RecoverableSignature::from_compact(&sig[1..], id).map_err(AnyErr::msg)?;
+    let hash = signed_msg_hash(msg);
+    let m = Message::from_slice(&hash[..]).map_err(AnyErr::msg)?;
+    let pubkey = Secp256k1::verification_only()
+        .recover_ecdsa(&m, &s)
+        .map_err(AnyErr::msg)?;
+    if pubkey_hash(&pubkey, compressed) != *pkh {
+        return Err(anyhow!("message not signed by {}", address));
+    }
+    Ok(())
+}
+
+fn verify_witness(tx: &Tx, addr: &Address) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let witness = &tx.inputs[0].witness;
+    match addr {
+        Address::P2wpkh(pkh) => {
+            if witness.len() != 2 {
+                return Err(anyhow!("P2WPKH witness must have 2 items"));
+            }
+            let pubkey = PublicKey::from_slice(&witness[1]).map_err(AnyErr::msg)?;
+            if witness[1].len() != 33 || pubkey_hash(&pubkey, true) != *pkh {
+                return Err(anyhow!("witness pubkey does not match"));
+            }
+            let (hash_type, der) = witness[0]
+                .split_last()
+                .ok_or_else(|| anyhow!("empty signature"))?;
+            if *hash_type != SIGHASH_ALL {
+                return Err(anyhow!("sighash type must be ALL"));
+            }
+            let mut sig = Signature::from_der(der).map_err(AnyErr::msg)?;
+            sig.normalize_s();
+            let m = Message::from_slice(&segwit_v0_sighash(tx, pkh)).map_err(AnyErr::msg)?;
+            secp.verify_ecdsa(&m, &sig, &pubkey).map_err(AnyErr::msg)
+        }
+        Address::P2tr(output) => {
+            if witness.len() != 1 {
+                return Err(anyhow!("P2TR key path witness must have 1 item"));
+            }
+            let (sig, hash_type) = match witness[0].len() {
+                64 => (&witness[0][..], SIGHASH_DEFAULT),
+                65 if witness[0][64] == SIGHASH_ALL => (&witness[0][..64], SIGHASH_ALL),
+                _ => return Err(anyhow!("invalid taproot signature")),
+            };
+            let sig = schnorr::Signature::from_slice(sig).map_err(AnyErr::msg)?;
+            let key = XOnlyPublicKey::from_slice(output).map_err(AnyErr::msg)?;
+            let hash = taproot_sighash(tx, &addr.script_pubkey(), hash_type);
+            let m = Message::from_slice(&hash).map_err(AnyErr::msg)?;
+            secp.verify_schnorr(&sig, &m, &key).map_err(AnyErr::msg)
+        }
+        Address::P2pkh(_) => Err(anyhow!("P2PKH is verified as legacy")),
+    }
+}
+
+// a full signature must be the to_sign of this message, with no extra
+// inputs (proof of funds is not supported)
+fn check_full(tx: &Tx, to_spend: &Tx) -> Result<()> {
+    let expected = to_sign(to_spend, Vec::new());
+    if tx.inputs.len() != 1 {
+        return Err(anyhow!("full signature must have 1 input"));
+    }
+    let (a, b) = (&tx.inputs[0], &expected.inputs[0]);
+    if a.txid != b.txid || a.vout != b.vout || !a.script_sig.is_empty() {
+        return Err(anyhow!("full signature does not spend to_spend"));
+    }
+    if tx.outputs != expected.outputs {
+        return Err(anyhow!(
+            "full signature must have a single OP_RETURN output"
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TxIn {
+    // internal byte order
+    txid: [u8; 32],
+    vout: u32,
+    script_sig: Vec<u8>,
+    sequence: u32,
+    witness: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TxOut {
+    value: u64,
+    script: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Tx {
+    version: u32,
+    inputs: Vec<TxIn>,
+    outputs: Vec<TxOut>,
+    lock_time: u32,
+}
+
+impl Tx {
+    fn serialize(&self) -> Vec<u8> {
+        let segwit = self.inputs.iter().any(|i| !i.witness.is_empty());
+        self.serialize_inner(segwit)
+    }
+    fn txid(&self) -> [u8; 32] {
+        Sha256dHash::hash(&self.serialize_inner(false)).into_inner()
+    }
+    fn serialize_inner(&self, segwit: bool) -> Vec<u8> {
+        let mut s = self.version.to_le_bytes().to_vec();
+        if segwit {
+            s.extend_from_slice(&[0x00, 0x01]);
+        }
+        s.extend(varint(self.inputs.len() as u64));
+        for i in self.inputs.iter() {
+            s.extend_from_slice(&i.txid);
+            s.extend_from_slice(&i.vout.to_le_bytes());
+            push_bytes(&mut s, &i.script_sig);
+            s.extend_from_slice(&i.sequence.to_le_bytes());
+        }
+        s.extend(varint(self.outputs.len() as u64));
+        s.extend(serialize_outputs(&self.outputs));
+        if segwit {
+            for i in self.inputs.iter() {
+                s.extend(serialize_witness(&i.witness));
+            }
+        }
+        s.extend_from_slice(&self.lock_time.to_le_bytes());
+        s
+    }
+    fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader(bytes);
+        let version = r.u32()?;
+        let segwit = r.0.len() > 2 && r.0[0] == 0x00 && r.0[1] == 0x01;
+        if segwit {
+            r.take(2)?;
+        }
+        let mut inputs = Vec::new();
+        for _ in 0..r.varint()? {
+            inputs.push(TxIn {
+                txid: r.take(32)?.try_into().map_err(AnyErr::msg)?,
+                vout: r.u32()?,
+                script_sig: r.var_bytes()?,
+                sequence: r.u32()?,
+                witness: Vec::new(),
+            });
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..r.varint()? {
+            outputs.push(TxOut {
+                value: u64::from_le_bytes(r.take(8)?.try_into().map_err(AnyErr::msg)?),
+                script: r.var_bytes()?,
+            });
+        }
+        if segwit {
+            for i in inputs.iter_mut() {
+                i.witness = r.witness()?;
+            }
+        }
+        let lock_time = r.u32()?;
+        if !r.0.is_empty() {
+            return Err(anyhow!("trailing bytes in transaction"));
+        }
+        Ok(Self {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(anyhow!("unexpected end of data"));
+        }
+        let (a, b) = self.0.split_at(n);
+        self.0 = b;
+        Ok(a)
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().map_err(AnyErr::msg)?,
+        ))
+    }
+    fn varint(&mut self) -> Result<u64> {
+        let n = match self.take(1)?[0] {
+            0xfd => u16::from_le_bytes(self.take(2)?.try_into().map_err(AnyErr::msg)?) as u64,
+            0xfe => self.u32()? as u64,
+            0xff => u64::from_le_bytes(self.take(8)?.try_into().map_err(AnyErr::msg)?),
+            n => n as u64,
+        };
+        // nothing here is anywhere near this long
+        if n > self.0.len() as u64 {
+            return Err(anyhow!("length past the end of data"));
+        }
+        Ok(n)
+    }
+    fn var_bytes(&mut self) -> Result<Vec<u8>> {
+        let n = self.varint()? as usize;
+        Ok(self.take(n)?.to_vec())
+    }
+    fn witness(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut items = Vec::new();
+        for _ in 0..self.varint()? {
+            items.push(self.var_bytes()?);
+        }
+        Ok(items)
+    }
+}
+
+fn to_spend(msg: &[u8], addr: &Address) -> Tx {
+    // OP_0 PUSH32 <message hash>
+    let mut script_sig = vec![0x00, 0x20];
+    script_sig.extend_from_slice(&message_hash(msg));
+    Tx {
+        version: 0,
+        inputs: vec![TxIn {
+            txid: [0; 32],
+            vout: 0xffff_ffff,
+            script_sig,
+            sequence: 0,
+            witness: Vec::new(),
+        }],
+        outputs: vec![TxOut {
+            value: 0,
+            script: addr.script_pubkey(),
+        }],
+        lock_time: 0,
+    }
+}
+
+fn to_sign(to_spend: &Tx, witness: Vec<Vec<u8>>) -> Tx {
+    Tx {
+        version: 0,
+        inputs: vec![TxIn {
+            txid: to_spend.txid(),
+            vout: 0,
+            script_sig: Vec::new(),
+            sequence: 0,
+            witness,
+        }],
+        outputs: vec![TxOut {
+            value: 0,
+            script: vec![OP_RETURN],
+        }],
+        lock_time: 0,
+    }
+}
+
+// BIP-143, for input 0 spending the 0 value to_spend output
+fn segwit_v0_sighash(tx: &Tx, pkh: &[u8; 20]) -> [u8; 32] {
+    let mut prevouts = Vec::new();
+    let mut sequences = Vec::new();
+    for i in tx.inputs.iter() {
+        prevouts.extend_from_slice(&i.txid);
+        prevouts.extend_from_slice(&i.vout.to_le_bytes());
+        sequences.extend_from_slice(&i.sequence.to_le_bytes());
+    }
+    let input = &tx.inputs[0];
+    let mut p = tx.version.to_le_bytes().to_vec();
+    p.extend_from_slice(&Sha256dHash::hash(&prevouts));
+    p.extend_from_slice(&Sha256dHash::hash(&sequences));
+    p.extend_from_slice(&input.txid);
+    p.extend_from_slice(&input.vout.to_le_bytes());
+    let script_code = Address::P2pkh(*pkh).script_pubkey();
+    push_bytes(&mut p, &script_code);
+    p.extend_from_slice(&0u64.to_le_bytes());
+    p.extend_from_slice(&input.sequence.to_le_bytes());
+    p.extend_from_slice(&Sha256dHash::hash(&serialize_outputs(&tx.outputs)));
+    p.extend_from_slice(&tx.lock_time.to_le_bytes());
+    p.extend_from_slice(&(SIGHASH_ALL as u32).to_le_bytes());
+    Sha256dHash::hash(&p).into_inner()
+}
+
+// BIP-341 key path, for input 0 spending the 0 value to_spend output.
+// Only DEFAULT and ALL, which commit to the same data
+fn taproot_sighash(tx: &Tx, spent_script: &[u8], hash_type: u8) -> [u8; 32] {
+    let mut prevouts = Vec::new();
+    let mut amounts = Vec::new();
+    let mut scripts = Vec::new();
+    let mut sequences = Vec::new();
+    for i in tx.inputs.iter() {
+        prevouts.extend_from_slice(&i.txid);
+        prevouts.extend_from_slice(&i.vout.to_le_bytes());
+        amounts.extend_from_slice(&0u64.to_le_bytes());
+        push_bytes(&mut scripts, spent_script);
+        sequences.extend_from_slice(&i.sequence.to_le_bytes());
+    }
+    // epoch 0
+    let mut m = vec![0x00, hash_type];
+    m.extend_from_slice(&tx.version.to_le_bytes());
+    m.extend_from_slice(&tx.lock_time.to_le_bytes());
+    m.extend_from_slice(&Sha256Hash::hash(&prevouts));
+    m.extend_from_slice(&Sha256Hash::hash(&amounts));
+    m.extend_from_slice(&Sha256Hash::hash(&scripts));
+    m.extend_from_slice(&Sha256Hash::hash(&sequences));
+    m.extend_from_slice(&Sha256Hash::hash(&serialize_outputs(&tx.outputs)));
+    // key path, no annex, input 0
+    m.push(0x00);
+    m.extend_from_slice(&0u32.to_le_bytes());
+    tagged_hash("TapSighash", &m)
+}
+
+fn taproot_tweak(internal: &XOnlyPublicKey) -> Result<Scalar> {
+    let t = tagged_hash("TapTweak", &internal.serialize());
+    Scalar::from_be_bytes(t).map_err(|_| anyhow!("invalid taproot tweak"))
+}
+
+// without the count in front
+fn serialize_outputs(outputs: &[TxOut]) -> Vec<u8> {
+    let mut s = Vec::new();
+    for o in outputs {
+        s.extend_from_slice(&o.value.to_le_bytes());
+        push_bytes(&mut s, &o.script);
+    }
+    s
+}
+
+fn serialize_witness(witness: &[Vec<u8>]) -> Vec<u8> {
+    let mut s = varint(witness.len() as u64);
+    for item in witness {
+        push_bytes(&mut s, item);
+    }
+    s
+}
+
+fn parse_witness(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut r = Reader(bytes);
+    let witness = r.witness()?;
+    if !r.0.is_empty() {
+        return Err(anyhow!("trailing bytes in witness"));
+    }
+    Ok(witness)
+}
+
+fn push_bytes(buf: &mut Vec<u8>, b: &[u8]) {
+    buf.extend(varint(b.len() as u64));
+    buf.extend_from_slice(b);
+}
+
+fn varint(n: u64) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => {
+            let mut v = vec![0xfd];
+            v.extend_from_slice(&(n as u16).to_le_bytes());
+            v
+        }
+        0x10000..=0xffff_ffff => {
+            let mut v = vec![0xfe];
+            v.extend_from_slice(&(n as u32).to_le_bytes());
+            v
+        }
+        _ => {
+            let mut v = vec![0xff];
+            v.extend_from_slice(&n.to_le_bytes());
+            v
+        }
+    }
+}
+
+fn pubkey_hash(pubkey: &PublicKey, compressed: bool) -> [u8; 20] {
+    let h = if compressed {
+        Hash160::hash(&pubkey.serialize())
+    } else {
+        Hash160::hash(&pubkey.serialize_uncompressed())
+    };
+    h.into_inner()
+}
+
+fn hrp(testnet: bool) -> &'static str {
+    if testnet {
+        "tb"
+    } else {
+        "bc"
+    }
+}
+
+fn decode_p2pkh(address: &str) -> Result<[u8; 20]> {
+    let data = base58_decode(address)?;
+    if data.len() != 25 {
+        return Err(anyhow!("invalid P2PKH address length"));
+    }
+    let checksum = Sha256dHash::hash(&data[..21]);
+    if checksum[..4] != data[21..] {
+        return Err(anyhow!("invalid address checksum"));
+    }
+    if data[0] != P2PKH_MAINNET && data[0] != P2PKH_TESTNET {
+        return Err(anyhow!("only P2PKH addresses are supported"));
+    }
+    let mut pkh = [0u8; 20];
+    pkh.copy_from_slice(&data[1..21]);
+    Ok(pkh)
+}
+
+fn segwit_address(s: &str) -> Result<Address> {
+    let (version, program) = bech32_decode(s)?;
+    match (version, program.len()) {
+        (0, 20) => Ok(Address::P2wpkh(
+            program[..].try_into().map_err(AnyErr::msg)?,
+        )),
+        (1, 32) => Ok(Address::P2tr(program[..].try_into().map_err(AnyErr::msg)?)),
+        _ => Err(anyhow!("only P2WPKH and P2TR addresses are supported")),
+    }
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in data {
+        let mut carry = *byte as u32;
+        for d in digits.iter_mut() {
+            carry += (*d as u32) << 8;
+            *d = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    let mut ret = "1".repeat(zeros);
+    ret.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|d| BASE58_CHARS[*d as usize] as char),
+    );
+    ret
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.bytes() {
+        let mut carry = match BASE58_CHARS.iter().position(|b| *b == c) {
+            Some(i) => i as u32,
+            None => return Err(anyhow!("invalid base58 character")),
+        };
+        for b in bytes.iter_mut() {
+            carry += (*b as u32) * 58;
+            *b = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    let mut ret = vec![0u8; zeros];
+    ret.extend(bytes.iter().rev());
+    Ok(ret)
+}
+
+// BIP-173 and BIP-350
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk: u32 = 1;
+    for v in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ff_ffff) << 5 ^ *v as u32;
+        for (i, g) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    v.push(0);
+    v.extend(hrp.bytes().map(|b| b & 31));
+    v
+}
+
+fn bech32_const(version: u8) -> u32 {
+    if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    }
+}
+
+fn bech32_encode(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut data = vec![version];
+    // 8 to 5 bits with padding can't fail
+    data.extend(convert_bits(program, 8, 5, true).unwrap_or_default());
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0; 6]);
+    let pm = bech32_polymod(&values) ^ bech32_const(version);
+    for i in 0..6 {
+        data.push(((pm >> (5 * (5 - i))) & 31) as u8);
+    }
+    let mut ret = format!("{}1", hrp);
+    ret.extend(data.iter().map(|d| BECH32_CHARS[*d as usize] as char));
+    ret
+}
+
+fn bech32_decode(s: &str) -> Result<(u8, Vec<u8>)> {
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return Err(anyhow!("mixed case bech32 address"));
+    }
+    let s = s.to_lowercase();
+    let pos = s.rfind('1').ok_or_else(|| anyhow!("no bech32 separator"))?;
+    let (hrp, rest) = (&s[..pos], &s[pos + 1..]);
+    if rest.len() < 7 {
+        return Err(anyhow!("bech32 address too short"));
+    }
+    let mut data = Vec::with_capacity(rest.len());
+    for c in rest.bytes() {
+        match BECH32_CHARS.iter().position(|b| *b == c) {
+            Some(i) => data.push(i as u8),
+            None => return Err(anyhow!("invalid bech32 character")),
+        }
+    }
+    let version = data[0];
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if bech32_polymod(&values) != bech32_const(version) {
+        return Err(anyhow!("invalid bech32 checksum"));
+    }
+    let program = convert_bits(&data[1..data.len() - 6], 5, 8, false)?;
+    Ok((version, program))
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let maxv: u32 = (1 << to) - 1;
+    let mut ret = Vec::new();
+    for v in data {
+        acc = (acc << from) | *v as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & maxv) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & maxv) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & maxv != 0 {
+        return Err(anyhow!("invalid bech32 padding"));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bip322::*;
+
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    // L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k from the BIP
+    fn keys() -> (SecretKey, PublicKey) {
+        let secp = Secp256k1::new();
+        let b = hex::decode("bb051cd0dda0246f33c5a9e133ebd8e7bc02a92af6c41adc131ccd7826c5b004")
+            .unwrap();
+        let sk = SecretKey::from_slice(&b).unwrap();
+        (sk, PublicKey::from_secret_key(&secp, &sk))
+    }
+
+    fn txid_hex(tx: &Tx) -> String {
+        let mut id = tx.txid();
+        id.reverse();
+        hex::encode(id)
+    }
+
+    #[test]
+    fn test_p2pkh_address() {
+        let secp = Secp256k1::new();
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let pk = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&one).unwrap());
+        let addy = p2pkh_address(&pk, false);
+        assert_eq!(addy, "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(decode_p2pkh(&addy).unwrap(), pubkey_hash(&pk, true));
+        assert!(decode_p2pkh("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMJ").is_err());
+        assert!(decode_p2pkh("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").is_err());
+    }
+
+    #[test]
+    fn test_segwit_address() {
+        let (_, pk) = keys();
+        assert_eq!(p2wpkh_address(&pk, false), P2WPKH);
+        assert_eq!(p2tr_address(&pk, false).unwrap(), P2TR);
+        assert_eq!(
+            Address::parse(P2WPKH).unwrap(),
+            Address::P2wpkh(pubkey_hash(&pk, true))
+        );
+        assert_eq!(
+            Address::parse(&P2WPKH.to_uppercase()).unwrap(),
+            Address::parse(P2WPKH).unwrap()
+        );
+        // bad checksum, and a v0 program under the bech32m checksum
+        assert!(Address::parse("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0m").is_err());
+        assert!(Address::parse("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdqsl2ckq").is_err());
+        let testnet = p2wpkh_address(&pk, true);
+        assert!(testnet.starts_with("tb1q"));
+        assert!(Address::parse(&testnet).is_ok());
+    }
+
+    #[test]
+    fn test_bip322_vectors() {
+        assert_eq!(
+            hex::encode(message_hash(b"")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(message_hash(b"Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let addr = Address::parse(P2WPKH).unwrap();
+        let spend = to_spend(b"", &addr);
+        assert_eq!(
+            txid_hex(&spend),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            txid_hex(&to_sign(&spend, Vec::new())),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+        let spend = to_spend(b"Hello World", &addr);
+        assert_eq!(
+            txid_hex(&spend),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+        assert_eq!(
+            txid_hex(&to_sign(&spend, Vec::new())),
+            "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf"
+        );
+        let empty = "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        let hello = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        verify(b"", empty, P2WPKH).expect("empty message");
+        verify(b"Hello World", hello, P2WPKH).expect("hello world");
+        assert!(verify(b"Hello World", empty, P2WPKH).is_err());
+        let (sk, _) = keys();
+        assert_eq!(sign(b"", &sk, P2WPKH, SigFormat::Simple).unwrap(), empty);
+        assert_eq!(
+            sign(b"Hello World", &sk, P2WPKH, SigFormat::Simple).unwrap(),
+            hello
+        );
+        let tr = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        verify(b"Hello World", tr, P2TR).expect("taproot");
+        assert!(verify(b"", tr, P2TR).is_err());
+    }
+
+    #[test]
+    fn test_bip322_sign() {
+        let (sk, _) = keys();
+        let other = SecretKey::from_slice(&[2; 32]).unwrap();
+        for format in [SigFormat::Simple, SigFormat::Full] {
+            for addr in [P2WPKH, P2TR] {
+                let sig = sign(b"sphinx", &sk, addr, format).unwrap();
+                verify(b"sphinx", &sig, addr).expect("verify");
+                assert!(verify(b"sphinx!", &sig, addr).is_err());
+                assert!(sign(b"sphinx", &other, addr, format).is_err());
+            }
+        }
+        // the full format is the whole to_sign
+        let full = sign(b"sphinx", &sk, P2WPKH, SigFormat::Full).unwrap();
+        let tx = Tx::deserialize(&STANDARD.decode(full).unwrap()).unwrap();
+        assert_eq!(tx.outputs[0].script, vec![OP_RETURN]);
+        assert_eq!(tx.inputs[0].witness.len(), 2);
+        assert!(sign(b"sphinx", &sk, P2WPKH, SigFormat::Legacy).is_err());
+    }
+
+    #[test]
+    fn test_legacy() {
+        let (sk, pk) = keys();
+        let addr = p2pkh_address(&pk, false);
+        let sig = sign(b"sphinx", &sk, &addr, SigFormat::Legacy).unwrap();
+        assert_eq!(sig, sign_legacy(b"sphinx", &sk).unwrap());
+        verify(b"sphinx", &sig, &addr).expect("legacy");
+        assert!(verify(b"sphinx!", &sig, &addr).is_err());
+        assert!(sign(b"sphinx", &sk, &addr, SigFormat::Simple).is_err());
+        // a legacy sig is not good for a segwit address
+        assert!(verify(b"sphinx", &sig, P2WPKH).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{recover_pubkey, sign_message};
+
+use anyhow::{anyhow, Error as AnyErr, Result};
+use secp256k1::{PublicKey, SecretKey};
+use std::convert::TryInto;
+
+// the longest chain of certificates from the root key
+pub const MAX_CHAIN: usize = 4;
+
+// Neither byte can start a utf8 string, a token or a msgpack control msg,
+// so nothing else signed with the lightning hash can pass as a certificate
+const CERT_MARKER: u8 = 0xff;
+const MSG_MARKER: u8 = 0xfe;
+const SIG_LEN: usize = 65;
+// marker + delegate + expiry + kinds len
+const CERT_MIN_PAYLOAD: usize = 1 + 33 + 4 + 1;
+
+// The issuer lets `delegate` sign control msgs of these kinds (as in
+// ControlMessage::kind) until `expiry`. The issuer is recovered from the sig.
+// Encoded as 0xff | delegate(33) | expiry | kinds | sig(65), where kinds is
+// a count and then length prefixed strings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    pub delegate: PublicKey,
+    pub expiry: u32,
+    pub kinds: Vec<String>,
+    sig: Option<[u8; SIG_LEN]>,
+}
+
+// what a valid chain grants its last delegate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delegation {
+    pub root: PublicKey,
+    pub delegate: PublicKey,
+    pub expiry: u32,
+    pub kinds: Vec<String>,
+}
+
+impl Delegation {
+    pub fn allows(&self, kind: &str) -> bool {
+        self.kinds.iter().any(|k| k == kind)
+    }
+}
+
+impl Certificate {
+    pub fn new(delegate: PublicKey, expiry: u32, kinds: &[&str]) -> Self {
+        Self {
+            delegate,
+            expiry,
+            kinds: kinds.iter().map(|k| k.to_string()).collect(),
+            sig: None,
+        }
+    }
+    pub fn sign(mut self, issuer: &SecretKey) -> Result<Self> {
+        let sig = sign_message(&self.payload()?, issuer)?;
+        self.sig = Some(sig[..].try_into().map_err(AnyErr::msg)?);
+        Ok(self)
+    }
+    pub fn issuer(&self) -> Result<PublicKey> {
+        let sig = self.sig.ok_or_else(|| anyhow!("certificate not signed"))?;
+        recover_pubkey(&self.payload()?, &sig)
+    }
+    pub fn allows(&self, kind: &str) -> bool {
+        self.kinds.iter().any(|k| k == kind)
+    }
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let sig = self.sig.ok_or_else(|| anyhow!("certificate not signed"))?;
+        let mut ret = self.payload()?;
+        ret.extend_from_slice(&sig);
+        Ok(ret)
+    }
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < CERT_MIN_PAYLOAD + SIG_LEN || bytes[0] != CERT_MARKER {
+            return Err(anyhow!("not a certificate"));
+        }
+        let (payload, sig) = bytes.split_at(bytes.len() - SIG_LEN);
+        let delegate = PublicKey::from_slice(&payload[1..34]).map_err(AnyErr::msg)?;
+        let expiry = u32::from_be_bytes(payload[34..38].try_into().map_err(AnyErr::msg)?);
+        let mut rest = &payload[39..];
+        let mut kinds = Vec::with_capacity(payload[38] as usize);
+        for _ in 0..payload[38] {
+            let (len, r) = rest.split_first().ok_or_else(|| anyhow!("short kind"))?;
+            if r.len() < *len as usize {
+                return Err(anyhow!("short kind"));
+            }
+            let (kind, r) = r.split_at(*len as usize);
+            kinds.push(String::from_utf8(kind.to_vec()).map_err(AnyErr::msg)?);
+            rest = r;
+        }
+        if !rest.is_empty() {
+            return Err(anyhow!("trailing bytes in certificate"));
+        }
+        Ok(Self {
+            delegate,
+            expiry,
+            kinds,
+            sig: Some(sig.try_into().map_err(AnyErr::msg)?),
+        })
+    }
+    fn payload(&self) -> Result<Vec<u8>> {
+        if self.kinds.is_empty() || self.kinds.len() > u8::MAX as usize {
+            return Err(anyhow!("a certificate needs 1 to 255 kinds"));
+        }
+        let mut p = vec![CERT_MARKER];
+        p.extend_from_slice(&self.delegate.serialize());
+        p.extend_from_slice(&self.expiry.to_be_bytes());
+        p.push(self.kinds.len() as u8);
+        for kind in self.kinds.iter() {
+            if kind.len() > u8::MAX as usize {
+                return Err(anyhow!("kind too long"));
+            }
+            p.push(kind.len() as u8);
+            p.extend_from_slice(kind.as_bytes());
+        }
+        Ok(p)
+    }
+}
+
+// The first certificate is issued by `root`, each next one by the previous
+// delegate, with no more kinds and no later expiry than the one before
+pub fn verify_chain(chain: &[Certificate], root: &PublicKey, now: u32) -> Result<Delegation> {
+    if chain.is_empty() || chain.len() > MAX_CHAIN {
+        return Err(anyhow!("a chain needs 1 to {} certificates", MAX_CHAIN));
+    }
+    let mut issuer = *root;
+    let mut prev: Option<&Certificate> = None;
+    for (i, cert) in chain.iter().enumerate() {
+        if cert.issuer()? != issuer {
+            return Err(anyhow!("certificate {} has the wrong issuer", i));
+        }
+        if now >= cert.expiry {
+            return Err(anyhow!("certificate {} expired", i));
+        }
+        if let Some(p) = prev {
+            if cert.expiry > p.expiry {
+                return Err(anyhow!("certificate {} outlives its issuer", i));
+            }
+            if let Some(k) = cert.kinds.iter().find(|k| !p.allows(k)) {
+                return Err(anyhow!("certificate {} widens the kinds with {}", i, k));
+            }
+        }
+        issuer = cert.delegate;
+        prev = Some(cert);
+    }
+    // can't be empty here
+    let last = &chain[chain.len() - 1];
+    Ok(Delegation {
+        root: *root,
+        delegate: last.delegate,
+        expiry: last.expiry,
+        kinds: last.kinds.clone(),
+    })
+}
+
+pub fn is_delegated_msg(input: &[u8]) -> bool {
+    input.first() == Some(&MSG_MARKER)
+}
+
+// 0xfe | count | (len(2) | certificate)* | the delegate's signed msg
+pub fn wrap_msg(signed: &[u8], chain: &[Certificate]) -> Result<Vec<u8>> {
+    if chain.is_empty() || chain.len() > MAX_CHAIN {
+        return Err(anyhow!("a chain needs 1 to {} certificates", MAX_CHAIN));
+    }
+    let mut ret = vec![MSG_MARKER, chain.len() as u8];
+    for cert in chain {
+        let c = cert.serialize()?;
+        if c.len() > u16::MAX as usize {
+            return Err(anyhow!("certificate too long"));
+        }
+        ret.extend_from_slice(&(c.len() as u16).to_be_bytes());
+        ret.extend(c);
+    }
+    ret.extend_from_slice(signed);
+    Ok(ret)
+}
+
+// the chain, and the delegate's signed msg
+pub fn unwrap_msg(input: &[u8]) -> Result<(Vec<Certificate>, &[u8])> {
+    if !is_delegated_msg(input) || input.len() < 2 {
+        return Err(anyhow!("not a delegated msg"));
+    }
+    let count = input[1] as usize;
+    if count == 0 || count > MAX_CHAIN {
+        return Err(anyhow!("a chain needs 1 to {} certificates", MAX_CHAIN));
+    }
+    let mut rest = &input[2..];
+    let mut chain = Vec::with_capacity(count);
+    for _ in 0..count {
+        if rest.len() < 2 {
+            return Err(anyhow!("short certificate"));
+        }
+        let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        if rest.len() < 2 + len {
+            return Err(anyhow!("short certificate"));
+        }
+        chain.push(Certificate::deserialize(&rest[2..2 + len])?);
+        rest = &rest[2 + len..];
+    }
+    Ok((chain, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delegation::*;
+    use secp256k1::Secp256k1;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn keys(b: u8) -> (SecretKey, PublicKey) {
+        let sk = SecretKey::from_slice(&[b; 32]).unwrap();
+        (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))
+    }
+
+    #[test]
+    fn test_certificate() {
+        let (root_sk, root_pk) = keys(1);
+        let (_, del_pk) = keys(2);
+        let cert = Certificate::new(del_pk, NOW + 60, &["QueryAll", "UpdatePolicy"])
+            .sign(&root_sk)
+            .unwrap();
+        let bytes = cert.serialize().unwrap();
+        let cert2 = Certificate::deserialize(&bytes).unwrap();
+        assert_eq!(cert, cert2);
+        assert_eq!(cert2.issuer().unwrap(), root_pk);
+        let d = verify_chain(&[cert2], &root_pk, NOW).unwrap();
+        assert_eq!(d.delegate, del_pk);
+        assert!(d.allows("QueryAll"));
+        assert!(!d.allows("ResetAll"));
+        assert!(verify_chain(std::slice::from_ref(&cert), &root_pk, NOW + 60).is_err());
+        assert!(verify_chain(std::slice::from_ref(&cert), &del_pk, NOW).is_err());
+        assert!(Certificate::new(del_pk, NOW, &[]).sign(&root_sk).is_err());
+        let mut bad = bytes.clone();
+        bad[40] ^= 1;
+        if let Ok(c) = Certificate::deserialize(&bad) {
+            assert!(verify_chain(&[c], &root_pk, NOW).is_err());
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let (root_sk, root_pk) = keys(1);
+        let (a_sk, a_pk) = keys(2);
+        let (_, b_pk) = keys(3);
+        let a = Certificate::new(a_pk, NOW + 60, &["QueryAll", "UpdatePolicy"])
+            .sign(&root_sk)
+            .unwrap();
+        let b = Certificate::new(b_pk, NOW + 30, &["QueryAll"])
+            .sign(&a_sk)
+            .unwrap();
+        let d = verify_chain(&[a.clone(), b], &root_pk, NOW).unwrap();
+        assert_eq!(d.delegate, b_pk);
+        assert_eq!(d.expiry, NOW + 30);
+        // wider kinds, or a later expiry
+        let wide = Certificate::new(b_pk, NOW + 30, &["ResetAll"])
+            .sign(&a_sk)
+            .unwrap();
+        assert!(verify_chain(&[a.clone(), wide], &root_pk, NOW).is_err());
+        let long = Certificate::new(b_pk, NOW + 90, &["QueryAll"])
+            .sign(&a_sk)
+            .unwrap();
+        assert!(verify_chain(&[a.clone(), long], &root_pk, NOW).is_err());
+        // not issued by the previous delegate
+        let skip = Certificate::new(b_pk, NOW + 30, &["QueryAll"])
+            .sign(&keys(4).0)
+            .unwrap();
+        assert!(verify_chain(&[a.clone(), skip], &root_pk, NOW).is_err());
+        assert!(verify_chain(&[], &root_pk, NOW).is_err());
+        let msg = wrap_msg(&[1, 2, 3], std::slice::from_ref(&a)).unwrap();
+        assert!(is_delegated_msg(&msg));
+        let (chain, signed) = unwrap_msg(&msg).unwrap();
+        assert_eq!(chain, vec![a]);
+        assert_eq!(signed, &[1, 2, 3]);
+    }
+}
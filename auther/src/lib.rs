@@ -1,4 +1,10 @@
+#[cfg(feature = "std")]
+pub mod bip322;
+pub mod delegation;
 pub mod nonce;
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod schnorr;
 pub mod token;
 
 pub use secp256k1;
@@ -6,7 +12,7 @@ pub use secp256k1;
 use anyhow::{anyhow, Error as AnyErr, Result};
 use secp256k1::ecdsa::{self, Signature};
 use secp256k1::hashes::sha256::Hash as Sha256Hash;
-use secp256k1::hashes::Hash;
+use secp256k1::hashes::{Hash, HashEngine};
 use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 
 // 27 + 4 for compressed
@@ -52,3 +58,13 @@ pub fn lightning_hash(message: &[u8]) -> Result<Message> {
     let encmsg = secp256k1::Message::from_slice(&hash2[..]).map_err(AnyErr::msg)?;
     Ok(encmsg)
 }
+
+// BIP-340 tagged hash: sha256(sha256(tag) || sha256(tag) || msg)
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256Hash::hash(tag.as_bytes());
+    let mut engine = Sha256Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    engine.input(msg);
+    Sha256Hash::from_engine(engine).into_inner()
+}
@@ -1,5 +1,10 @@
-use crate::{sign_message, verify_message};
+use crate::delegation::{self, Certificate, Delegation};
+use crate::{recover_pubkey, sign_message, verify_message};
+#[cfg(feature = "std")]
+use crate::{schnorr, tagged_hash};
 use anyhow::{anyhow, Error as AnyErr};
+#[cfg(feature = "std")]
+use secp256k1::XOnlyPublicKey;
 use secp256k1::{PublicKey, SecretKey};
 use std::convert::TryInto;
 
@@ -68,9 +73,263 @@ pub fn parse_msg_no_nonce(input: &[u8], pk: &PublicKey) -> anyhow::Result<(Vec<u
     Ok((msg.to_vec(), nonce))
 }
 
+// like build_msg, signed by a delegate and carrying its certificate chain
+pub fn build_msg_delegated(
+    input: &[u8],
+    delegate_sk: &SecretKey,
+    nonce: u64,
+    chain: &[Certificate],
+) -> anyhow::Result<Vec<u8>> {
+    delegation::wrap_msg(&build_msg(input, delegate_sk, nonce)?, chain)
+}
+
+// Accepts a msg signed by pk, or by a delegate with a certificate chain from
+// pk that is valid at `now`. The Delegation is set in that case, and the
+// caller has to check that the msg is one of its kinds
+pub fn parse_msg_delegated(
+    input: &[u8],
+    pk: &PublicKey,
+    now: u32,
+) -> anyhow::Result<(Vec<u8>, u64, Option<Delegation>)> {
+    if !delegation::is_delegated_msg(input) {
+        let (msg, nonce) = parse_msg_no_nonce(input, pk)?;
+        return Ok((msg, nonce, None));
+    }
+    let (chain, signed) = delegation::unwrap_msg(input)?;
+    let d = delegation::verify_chain(&chain, pk, now)?;
+    let (msg, nonce) = parse_msg_no_nonce(signed, &d.delegate)?;
+    Ok((msg, nonce, Some(d)))
+}
+
+// like parse_msg_recover, but the key is the root of a delegated msg
+pub fn parse_msg_recover_delegated(
+    input: &[u8],
+    now: u32,
+) -> anyhow::Result<(Vec<u8>, u64, PublicKey, Option<Delegation>)> {
+    if !delegation::is_delegated_msg(input) {
+        let (msg, nonce, pk) = parse_msg_recover(input)?;
+        return Ok((msg, nonce, pk, None));
+    }
+    let (chain, _) = delegation::unwrap_msg(input)?;
+    let root = chain[0].issuer()?;
+    let (msg, nonce, d) = parse_msg_delegated(input, &root, now)?;
+    Ok((msg, nonce, root, d))
+}
+
+// like parse_msg_no_nonce, but returns the pubkey that signed the msg
+pub fn parse_msg_recover(input: &[u8]) -> anyhow::Result<(Vec<u8>, u64, PublicKey)> {
+    if input.len() < SIG_LEN + 8 + 1 {
+        return Err(anyhow!("msg too short"));
+    }
+    let msg_sig = input.split_at(input.len() - SIG_LEN);
+    let sig: [u8; SIG_LEN] = msg_sig.1.try_into().map_err(AnyErr::msg)?;
+    let pk = recover_pubkey(msg_sig.0, &sig)?;
+    let (msg, nonce) = parse_msg_no_nonce(input, &pk)?;
+    Ok((msg, nonce, pk))
+}
+
+// Schnorr build_msg, under the control tag: input | nonce | sig(64).
+// A FROST group signs control_hash(input | nonce) the same way.
+// glyph's Controller takes these wrapped by wrap_msg_schnorr
+#[cfg(feature = "std")]
+pub fn build_msg_schnorr(input: &[u8], sk: &SecretKey, nonce: u64) -> anyhow::Result<Vec<u8>> {
+    let mut d = input.to_vec();
+    d.extend_from_slice(&nonce.to_be_bytes());
+    let sig = schnorr::sign_tagged(schnorr::CONTROL_TAG, &d, sk)?;
+    d.extend_from_slice(&sig);
+    Ok(d)
+}
+
+// the BIP-340 message that build_msg_schnorr signs
+#[cfg(feature = "std")]
+pub fn control_hash(msg_with_nonce: &[u8]) -> [u8; 32] {
+    tagged_hash(schnorr::CONTROL_TAG, msg_with_nonce)
+}
+
+#[cfg(feature = "std")]
+pub fn parse_msg_schnorr(
+    input: &[u8],
+    pk: &XOnlyPublicKey,
+    last_nonce: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let (msg, nonce) = parse_msg_no_nonce_schnorr(input, pk)?;
+    if nonce <= last_nonce {
+        return Err(anyhow!("bad nonce"));
+    }
+    Ok(msg)
+}
+
+#[cfg(feature = "std")]
+pub fn parse_msg_no_nonce_schnorr(
+    input: &[u8],
+    pk: &XOnlyPublicKey,
+) -> anyhow::Result<(Vec<u8>, u64)> {
+    if input.len() < schnorr::SIG_LEN + 8 + 1 {
+        return Err(anyhow!("msg too short"));
+    }
+    let msg_sig = input.split_at(input.len() - schnorr::SIG_LEN);
+    let sig: [u8; schnorr::SIG_LEN] = msg_sig.1.try_into().map_err(AnyErr::msg)?;
+    let msg_nonce = msg_sig.0.split_at(msg_sig.0.len() - 8);
+    let nonce_bytes: [u8; 8] = msg_nonce.1.try_into().map_err(AnyErr::msg)?;
+    schnorr::verify_tagged(schnorr::CONTROL_TAG, msg_sig.0, &sig, pk)?;
+    Ok((msg_nonce.0.to_vec(), u64::from_be_bytes(nonce_bytes)))
+}
+
+// Like delegation's markers, this can't start a control msg, so a Schnorr
+// msg is never read as an ECDSA one or the other way around
+#[cfg(feature = "std")]
+const SCHNORR_MARKER: u8 = 0xfd;
+
+// A Schnorr sig can't recover its key, so the msg carries it:
+// 0xfd | pubkey(32) | the build_msg_schnorr msg
+#[cfg(feature = "std")]
+pub fn wrap_msg_schnorr(signed: &[u8], pk: &XOnlyPublicKey) -> Vec<u8> {
+    let mut ret = vec![SCHNORR_MARKER];
+    ret.extend_from_slice(&pk.serialize());
+    ret.extend_from_slice(signed);
+    ret
+}
+
+#[cfg(feature = "std")]
+pub fn is_schnorr_msg(input: &[u8]) -> bool {
+    input.first() == Some(&SCHNORR_MARKER)
+}
+
+// like parse_msg_recover, for a wrap_msg_schnorr msg
+#[cfg(feature = "std")]
+pub fn parse_msg_recover_schnorr(input: &[u8]) -> anyhow::Result<(Vec<u8>, u64, XOnlyPublicKey)> {
+    if !is_schnorr_msg(input) || input.len() < 33 {
+        return Err(anyhow!("not a schnorr msg"));
+    }
+    let pk = XOnlyPublicKey::from_slice(&input[1..33]).map_err(AnyErr::msg)?;
+    let (msg, nonce) = parse_msg_no_nonce_schnorr(&input[33..], &pk)?;
+    Ok((msg, nonce, pk))
+}
+
+// like parse_msg, but nonces can arrive out of order within the window
+pub fn parse_msg_with_window(
+    input: &[u8],
+    pk: &PublicKey,
+    window: &mut NonceWindow,
+) -> anyhow::Result<Vec<u8>> {
+    let (msg, nonce) = parse_msg_no_nonce(input, pk)?;
+    window.accept(nonce)?;
+    Ok(msg)
+}
+
+pub const DEFAULT_WINDOW: u32 = 64;
+
+// Anti-replay window, like IPsec (RFC 4303). `top` is the highest nonce
+// accepted so far. Bit i of `seen` is set once `top - i` has been used.
+// A nonce above `top` slides the window up, one inside it is accepted once,
+// and anything `size` or more below `top` is too old.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonceWindow {
+    top: u64,
+    size: u32,
+    seen: Vec<u64>,
+}
+
+impl NonceWindow {
+    // nonce 0 is never valid
+    pub fn new(size: u32) -> Self {
+        Self::from_nonce(0, size)
+    }
+    // every nonce up to `last` counts as used, like the strict check did
+    pub fn from_nonce(last: u64, size: u32) -> Self {
+        let size = size.max(1);
+        let mut seen = vec![u64::MAX; words(size)];
+        mask(&mut seen, size);
+        Self {
+            top: last,
+            size,
+            seen,
+        }
+    }
+    // load a persisted window, under a possibly different size
+    pub fn from_parts(top: u64, size: u32, mut seen: Vec<u64>) -> Self {
+        let size = size.max(1);
+        seen.resize(words(size), 0);
+        mask(&mut seen, size);
+        // top itself is always used
+        seen[0] |= 1;
+        Self { top, size, seen }
+    }
+    pub fn top(&self) -> u64 {
+        self.top
+    }
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    pub fn seen(&self) -> &[u64] {
+        &self.seen
+    }
+    pub fn check(&self, nonce: u64) -> anyhow::Result<()> {
+        if nonce > self.top {
+            return Ok(());
+        }
+        let offset = self.top - nonce;
+        if offset >= self.size as u64 {
+            return Err(anyhow!("nonce too old"));
+        }
+        if self.is_seen(offset as usize) {
+            return Err(anyhow!("nonce replayed"));
+        }
+        Ok(())
+    }
+    pub fn accept(&mut self, nonce: u64) -> anyhow::Result<()> {
+        self.check(nonce)?;
+        if nonce > self.top {
+            self.shift(nonce - self.top);
+            self.top = nonce;
+        }
+        let offset = (self.top - nonce) as usize;
+        self.seen[offset / 64] |= 1 << (offset % 64);
+        Ok(())
+    }
+    fn is_seen(&self, offset: usize) -> bool {
+        self.seen[offset / 64] & (1 << (offset % 64)) != 0
+    }
+    // move every bit up by n, dropping what falls out of the window
+    fn shift(&mut self, n: u64) {
+        if n >= self.size as u64 {
+            self.seen.iter_mut().for_each(|w| *w = 0);
+            return;
+        }
+        let word_shift = (n / 64) as usize;
+        let bit_shift = (n % 64) as u32;
+        for i in (0..self.seen.len()).rev() {
+            let mut w = 0;
+            if i >= word_shift {
+                w = self.seen[i - word_shift] << bit_shift;
+                if bit_shift > 0 && i > word_shift {
+                    w |= self.seen[i - word_shift - 1] >> (64 - bit_shift);
+                }
+            }
+            self.seen[i] = w;
+        }
+        mask(&mut self.seen, self.size);
+    }
+}
+
+fn words(size: u32) -> usize {
+    (size as usize).div_ceil(64)
+}
+
+// clear the bits past the end of the window
+fn mask(seen: &mut [u64], size: u32) {
+    let rem = size % 64;
+    if rem > 0 {
+        if let Some(last) = seen.last_mut() {
+            *last &= (1u64 << rem) - 1;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::nonce::*;
+    use crate::token::now;
     use secp256k1::{PublicKey, Secp256k1, SecretKey};
 
     fn secret_key() -> SecretKey {
@@ -89,4 +348,167 @@ mod tests {
         let parsed = cont.parse_msg_with_nonce(&msg, 0).expect("couldnt verify");
         assert_eq!(input, parsed, "unequal");
     }
+
+    #[test]
+    fn test_nonce_schnorr() {
+        let sk = secret_key();
+        let pk = schnorr::x_only(&sk);
+        let input = vec![1, 2, 3];
+        let msg = build_msg_schnorr(&input, &sk, 5).expect("couldnt sign");
+        assert_eq!(msg.len(), input.len() + 8 + schnorr::SIG_LEN);
+        let parsed = parse_msg_schnorr(&msg, &pk, 4).expect("couldnt verify");
+        assert_eq!(input, parsed);
+        assert!(parse_msg_schnorr(&msg, &pk, 5).is_err());
+        let (_, nonce) = parse_msg_no_nonce_schnorr(&msg, &pk).expect("couldnt verify");
+        assert_eq!(nonce, 5);
+        // signed by an outside signer, over control_hash
+        let secp = Secp256k1::new();
+        let keypair = secp256k1::KeyPair::from_secret_key(&secp, &sk);
+        let mut d = input.clone();
+        d.extend_from_slice(&6u64.to_be_bytes());
+        let m = secp256k1::Message::from_slice(&control_hash(&d)).unwrap();
+        d.extend_from_slice(secp.sign_schnorr(&m, &keypair).as_ref());
+        assert_eq!(parse_msg_schnorr(&d, &pk, 5).expect("external sig"), input);
+        // an ecdsa control msg is not a schnorr one
+        let ecdsa = build_msg(&input, &sk, 7).expect("couldnt sign");
+        assert!(parse_msg_schnorr(&ecdsa, &pk, 0).is_err());
+    }
+
+    #[test]
+    fn test_wrap_msg_schnorr() {
+        let sk = secret_key();
+        let pk = schnorr::x_only(&sk);
+        let input = vec![1, 2, 3];
+        let signed = build_msg_schnorr(&input, &sk, 5).expect("couldnt sign");
+        let msg = wrap_msg_schnorr(&signed, &pk);
+        assert!(is_schnorr_msg(&msg));
+        let (parsed, nonce, key) = parse_msg_recover_schnorr(&msg).expect("couldnt verify");
+        assert_eq!(parsed, input);
+        assert_eq!(nonce, 5);
+        assert_eq!(key, pk);
+        // the named key has to be the signer
+        let other = schnorr::x_only(&SecretKey::from_slice(&[0xab; 32]).unwrap());
+        assert!(parse_msg_recover_schnorr(&wrap_msg_schnorr(&signed, &other)).is_err());
+        // neither kind passes as the other
+        let ecdsa = build_msg(&input, &sk, 5).expect("couldnt sign");
+        assert!(!is_schnorr_msg(&ecdsa));
+        assert!(parse_msg_recover_schnorr(&ecdsa).is_err());
+        assert!(parse_msg_recover(&msg).is_err());
+    }
+
+    #[test]
+    fn test_parse_msg_delegated() {
+        let secp = Secp256k1::new();
+        let root_sk = secret_key();
+        let root_pk = PublicKey::from_secret_key(&secp, &root_sk);
+        let del_sk = SecretKey::from_slice(&[0xab; 32]).unwrap();
+        let del_pk = PublicKey::from_secret_key(&secp, &del_sk);
+        let cert = Certificate::new(del_pk, now() + 60, &["QueryAll"])
+            .sign(&root_sk)
+            .unwrap();
+        let input = vec![1, 2, 3];
+        let msg = build_msg_delegated(&input, &del_sk, 3, std::slice::from_ref(&cert)).unwrap();
+        let (parsed, nonce, d) = parse_msg_delegated(&msg, &root_pk, now()).expect("delegated");
+        assert_eq!((parsed, nonce), (input.clone(), 3));
+        assert_eq!(d.unwrap().kinds, ["QueryAll"]);
+        let (_, _, root, d) = parse_msg_recover_delegated(&msg, now()).unwrap();
+        assert_eq!(root, root_pk);
+        assert_eq!(d.unwrap().delegate, del_pk);
+        // the plain parsers only take the root key itself
+        assert!(parse_msg_no_nonce(&msg, &root_pk).is_err());
+        assert!(parse_msg(&msg, &root_pk, 2).is_err());
+        let plain = build_msg(&input, &root_sk, 4).unwrap();
+        let (_, _, d) = parse_msg_delegated(&plain, &root_pk, now()).unwrap();
+        assert!(d.is_none());
+        // only under the root key, and only until the expiry
+        assert!(parse_msg_delegated(&msg, &del_pk, now()).is_err());
+        assert!(parse_msg_delegated(&msg, &root_pk, now() + 60).is_err());
+        // signed by someone else than the delegate
+        let other = build_msg_delegated(&input, &root_sk, 3, &[cert]).unwrap();
+        assert!(parse_msg_delegated(&other, &root_pk, now()).is_err());
+    }
+
+    #[test]
+    fn test_parse_msg_recover() {
+        let secp = Secp256k1::new();
+        let sk = secret_key();
+        let public_key = PublicKey::from_secret_key(&secp, &sk);
+        let input = vec![1, 2, 3];
+        let msg = build_msg(&input, &sk, 7).expect("couldnt sign");
+        let (parsed, nonce, pk) = parse_msg_recover(&msg).expect("couldnt recover");
+        assert_eq!(input, parsed);
+        assert_eq!(nonce, 7);
+        assert_eq!(pk, public_key);
+        let mut bad = msg.clone();
+        bad[0] = 9;
+        // a tampered msg recovers some other key, or none
+        if let Ok((_, _, pk)) = parse_msg_recover(&bad) {
+            assert_ne!(pk, public_key);
+        }
+        assert!(parse_msg_recover(&msg[..20]).is_err());
+    }
+
+    #[test]
+    fn test_nonce_window() {
+        let mut w = NonceWindow::new(8);
+        assert!(w.accept(0).is_err());
+        // out of order within the window
+        w.accept(3).expect("3");
+        w.accept(1).expect("1");
+        w.accept(2).expect("2");
+        assert!(w.accept(2).is_err());
+        assert!(w.accept(3).is_err());
+        w.accept(10).expect("10");
+        // 2 is now 8 below the top
+        assert!(w.check(2).is_err());
+        w.accept(4).expect("4");
+        assert!(w.accept(4).is_err());
+        assert_eq!(w.top(), 10);
+        // a big jump clears the window
+        w.accept(100).expect("100");
+        w.accept(93).expect("93");
+        assert!(w.check(92).is_err());
+        assert!(w.check(100).is_err());
+    }
+
+    #[test]
+    fn test_nonce_window_words() {
+        let mut w = NonceWindow::new(130);
+        w.accept(200).expect("200");
+        w.accept(71).expect("71");
+        assert!(w.check(70).is_err());
+        w.accept(135).expect("135");
+        w.accept(136).expect("136");
+        // spans a word boundary
+        w.accept(265).expect("265");
+        assert!(w.check(135).is_err());
+        assert!(w.check(136).is_err());
+        assert!(w.check(200).is_err());
+        w.accept(137).expect("137");
+        let w2 = NonceWindow::from_parts(w.top(), w.size(), w.seen().to_vec());
+        assert_eq!(w, w2);
+        // shrinking keeps the top of the window
+        let mut w3 = NonceWindow::from_parts(w.top(), 64, w.seen().to_vec());
+        assert!(w3.check(200).is_err());
+        assert!(w3.check(201).is_err());
+        w3.accept(202).expect("202");
+    }
+
+    #[test]
+    fn test_nonce_window_from_nonce() {
+        let mut w = NonceWindow::from_nonce(5, DEFAULT_WINDOW);
+        for n in 0..=5 {
+            assert!(w.check(n).is_err());
+        }
+        w.accept(7).expect("7");
+        w.accept(6).expect("6");
+        let secp = Secp256k1::new();
+        let sk = secret_key();
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+        let msg = build_msg(&[1, 2, 3], &sk, 9).expect("couldnt sign");
+        let early = build_msg(&[4], &sk, 8).expect("couldnt sign");
+        parse_msg_with_window(&msg, &pk, &mut w).expect("9");
+        parse_msg_with_window(&early, &pk, &mut w).expect("8");
+        assert!(parse_msg_with_window(&msg, &pk, &mut w).is_err());
+    }
 }
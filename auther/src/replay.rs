@@ -0,0 +1,233 @@
+use crate::token::{now, parse_token, AnyToken, Token, TokenV2};
+
+use anyhow::{anyhow, Result};
+use secp256k1::hashes::sha256::Hash as Sha256Hash;
+use secp256k1::hashes::{Hash, HashEngine};
+use secp256k1::PublicKey;
+use std::collections::HashMap;
+
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+// Remembers accepted tokens until they expire
+pub trait ReplayStore: Send {
+    // keep `key` until `expiry`. Ok(false) if it was already there, or if
+    // the store can no longer tell (see MemReplayStore)
+    fn insert(&mut self, key: [u8; 32], expiry: u32) -> Result<bool>;
+    // forget everything that expired at or before `now`
+    fn prune(&mut self, now: u32) -> Result<()>;
+}
+
+// In memory store with at most `max` entries. When full, the entry closest
+// to expiry is dropped, and `floor` is raised to its expiry: a token that
+// expires at or before the floor is refused from then on, since it might
+// have been the one dropped.
+pub struct MemReplayStore {
+    entries: HashMap<[u8; 32], u32>,
+    max: usize,
+    floor: u32,
+}
+
+impl MemReplayStore {
+    pub fn new(max: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max: max.max(1),
+            floor: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    fn evict(&mut self) {
+        let oldest = self.entries.iter().min_by_key(|(_, exp)| **exp);
+        if let Some((key, exp)) = oldest.map(|(k, e)| (*k, *e)) {
+            self.entries.remove(&key);
+            self.floor = self.floor.max(exp);
+        }
+    }
+}
+
+impl Default for MemReplayStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl ReplayStore for MemReplayStore {
+    fn insert(&mut self, key: [u8; 32], expiry: u32) -> Result<bool> {
+        if expiry <= self.floor || self.entries.contains_key(&key) {
+            return Ok(false);
+        }
+        if self.entries.len() >= self.max {
+            self.evict();
+        }
+        self.entries.insert(key, expiry);
+        Ok(true)
+    }
+    fn prune(&mut self, now: u32) -> Result<()> {
+        self.entries.retain(|_, exp| *exp > now);
+        Ok(())
+    }
+}
+
+// Accepts each token once. v1 tokens are good for `window` seconds after
+// their timestamp and are keyed by their signature. v2 tokens must be for
+// `audience`, can't live longer than `window`, and are keyed by signer and
+// jti. Tokens from more than FUTURE_SKEW seconds ahead are refused.
+pub struct TokenVerifier<S: ReplayStore> {
+    store: S,
+    window: u32,
+    audience: String,
+    accept_v1: bool,
+}
+
+impl TokenVerifier<MemReplayStore> {
+    pub fn new(audience: &str, window: u32) -> Self {
+        Self::with_store(MemReplayStore::default(), audience, window)
+    }
+}
+
+impl<S: ReplayStore> TokenVerifier<S> {
+    pub fn with_store(store: S, audience: &str, window: u32) -> Self {
+        Self {
+            store,
+            window,
+            audience: audience.to_string(),
+            accept_v1: true,
+        }
+    }
+    // v1 tokens have no audience, so they work against any service
+    pub fn accept_v1(mut self, accept: bool) -> Self {
+        self.accept_v1 = accept;
+        self
+    }
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+    // verify a base64 token of either version, and remember it
+    pub fn verify(&mut self, token: &str) -> Result<PublicKey> {
+        self.verify_at(token, now())
+    }
+    pub fn verify_at(&mut self, token: &str, now: u32) -> Result<PublicKey> {
+        self.store.prune(now)?;
+        let (pk, key, expiry) = match parse_token(token)? {
+            AnyToken::V1(t) => {
+                if !self.accept_v1 {
+                    return Err(anyhow!("v1 tokens are not accepted"));
+                }
+                self.check_v1(&t, now)?
+            }
+            AnyToken::V2(t) => self.check_v2(&t, now)?,
+        };
+        if !self.store.insert(key, expiry)? {
+            return Err(anyhow!("token replayed"));
+        }
+        Ok(pk)
+    }
+    fn check_v1(&self, t: &Token, now: u32) -> Result<(PublicKey, [u8; 32], u32)> {
+        let pk = t.recover_within_at(self.window, now)?;
+        let sig = t.sig().ok_or_else(|| anyhow!("no sig"))?;
+        // the r value: s can be flipped without the key
+        let key = replay_key(&[&t.timestamp().to_be_bytes(), &sig[1..33]]);
+        // still accepted at timestamp + window
+        let expiry = t.timestamp().saturating_add(self.window).saturating_add(1);
+        Ok((pk, key, expiry))
+    }
+    fn check_v2(&self, t: &TokenV2, now: u32) -> Result<(PublicKey, [u8; 32], u32)> {
+        t.check(&self.audience, now)?;
+        if t.expiry > now.saturating_add(self.window) {
+            return Err(anyhow!("token outlives the verifier window"));
+        }
+        let pk = t.recover()?;
+        let key = replay_key(&[&pk.serialize(), &t.jti]);
+        Ok((pk, key, t.expiry))
+    }
+}
+
+fn replay_key(parts: &[&[u8]]) -> [u8; 32] {
+    let mut engine = Sha256Hash::engine();
+    for p in parts {
+        engine.input(p);
+    }
+    Sha256Hash::from_engine(engine).into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::*;
+    use crate::token::AUDIENCE_BROKER;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_slice(&[0xcd; 32]).expect("32 bytes, within curve order")
+    }
+
+    #[test]
+    fn test_replay_v1() {
+        let sk = secret_key();
+        let pk = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let mut v = TokenVerifier::new(AUDIENCE_BROKER, 60);
+        let tok = Token::new_with_time(NOW).sign_to_base64(&sk).unwrap();
+        assert_eq!(v.verify_at(&tok, NOW + 1).unwrap(), pk);
+        assert!(v.verify_at(&tok, NOW + 2).is_err());
+        // a different timestamp is a different token
+        let tok2 = Token::new_with_time(NOW + 1).sign_to_base64(&sk).unwrap();
+        v.verify_at(&tok2, NOW + 2).unwrap();
+        assert_eq!(v.store().len(), 2);
+        // still remembered at the edge of the window
+        assert!(v.verify_at(&tok, NOW + 60).is_err());
+        // expired, and forgotten
+        assert!(v.verify_at(&tok, NOW + 61).is_err());
+        assert_eq!(v.store().len(), 1);
+        // too far in the future
+        let tok3 = Token::new_with_time(NOW + 1_000)
+            .sign_to_base64(&sk)
+            .unwrap();
+        assert!(v.verify_at(&tok3, NOW).is_err());
+        let mut strict = TokenVerifier::new(AUDIENCE_BROKER, 60).accept_v1(false);
+        assert!(strict.verify_at(&tok2, NOW + 2).is_err());
+    }
+
+    #[test]
+    fn test_replay_v2() {
+        let sk = secret_key();
+        let pk = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let mut v = TokenVerifier::new(AUDIENCE_BROKER, 60);
+        let tok = TokenV2::new_with_time(AUDIENCE_BROKER, NOW, NOW + 30)
+            .sign_to_base64(&sk)
+            .unwrap();
+        assert_eq!(v.verify_at(&tok, NOW).unwrap(), pk);
+        assert!(v.verify_at(&tok, NOW + 1).is_err());
+        // longer than the window
+        let long = TokenV2::new_with_time(AUDIENCE_BROKER, NOW, NOW + 120)
+            .sign_to_base64(&sk)
+            .unwrap();
+        assert!(v.verify_at(&long, NOW).is_err());
+        let mut v = TokenVerifier::new(AUDIENCE_BROKER, 300);
+        let future = TokenV2::new_with_time(AUDIENCE_BROKER, NOW + 100, NOW + 110)
+            .sign_to_base64(&sk)
+            .unwrap();
+        assert!(v.verify_at(&future, NOW).is_err());
+        v.verify_at(&future, NOW + 40).unwrap();
+    }
+
+    #[test]
+    fn test_mem_store_bounded() {
+        let mut store = MemReplayStore::new(2);
+        assert!(store.insert([1; 32], 10).unwrap());
+        assert!(store.insert([2; 32], 20).unwrap());
+        assert!(!store.insert([2; 32], 20).unwrap());
+        // drops [1; 32], so nothing expiring by 10 gets in any more
+        assert!(store.insert([3; 32], 30).unwrap());
+        assert_eq!(store.len(), 2);
+        assert!(!store.insert([1; 32], 10).unwrap());
+        assert!(!store.insert([4; 32], 5).unwrap());
+        store.prune(20).unwrap();
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::tagged_hash;
+use crate::token::{base64_decode, base64_encode, bytes_to_u32, now, u32_to_bytes, FUTURE_SKEW};
+
+use anyhow::{anyhow, Error as AnyErr, Result};
+use secp256k1::schnorr::Signature;
+use secp256k1::{KeyPair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use std::convert::TryInto;
+
+// each kind of signed data has its own tag, so a signature over one can
+// never pass as another, unlike the lightning hash where a 4 byte message
+// is also a token
+pub const MESSAGE_TAG: &str = "sphinx-auther/message";
+pub const TOKEN_TAG: &str = "sphinx-auther/token";
+pub const CONTROL_TAG: &str = "sphinx-auther/control";
+
+pub const SIG_LEN: usize = 64;
+const TOKEN_LEN: usize = 4 + 32 + SIG_LEN;
+
+// BIP-340 signature of the tagged hash of `message`
+pub fn sign_tagged(tag: &str, message: &[u8], secret_key: &SecretKey) -> Result<[u8; SIG_LEN]> {
+    let secp = Secp256k1::signing_only();
+    let keypair = KeyPair::from_secret_key(&secp, secret_key);
+    let m = Message::from_slice(&tagged_hash(tag, message)).map_err(AnyErr::msg)?;
+    Ok(*secp.sign_schnorr(&m, &keypair).as_ref())
+}
+
+pub fn verify_tagged(
+    tag: &str,
+    message: &[u8],
+    sig: &[u8; SIG_LEN],
+    public_key: &XOnlyPublicKey,
+) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let m = Message::from_slice(&tagged_hash(tag, message)).map_err(AnyErr::msg)?;
+    let s = Signature::from_slice(sig).map_err(AnyErr::msg)?;
+    secp.verify_schnorr(&s, &m, public_key).map_err(AnyErr::msg)
+}
+
+// the Schnorr sign_message. There is no recovery, so the verifier needs the key
+pub fn sign_message(message: &[u8], secret_key: &SecretKey) -> Result<[u8; SIG_LEN]> {
+    sign_tagged(MESSAGE_TAG, message, secret_key)
+}
+
+pub fn verify_message(
+    message: &[u8],
+    sig: &[u8; SIG_LEN],
+    public_key: &XOnlyPublicKey,
+) -> Result<()> {
+    verify_tagged(MESSAGE_TAG, message, sig, public_key)
+}
+
+pub fn x_only(secret_key: &SecretKey) -> XOnlyPublicKey {
+    let secp = Secp256k1::signing_only();
+    KeyPair::from_secret_key(&secp, secret_key)
+        .x_only_public_key()
+        .0
+}
+
+// Like Token, but with the x-only key in it, since it can't be recovered.
+// Encoded as timestamp(4) | pubkey(32) | sig(64)
+#[derive(Debug)]
+pub struct SchnorrToken(u32, Option<(XOnlyPublicKey, [u8; SIG_LEN])>);
+
+impl SchnorrToken {
+    /// Creates a new token with current timestamp
+    pub fn new() -> Self {
+        Self(now(), None)
+    }
+    pub fn new_with_time(d: u32) -> Self {
+        Self(d, None)
+    }
+    pub fn timestamp(&self) -> u32 {
+        self.0
+    }
+    pub fn from_base64(s: &str) -> Result<Self> {
+        let bytes = base64_decode(s)?;
+        if bytes.len() != TOKEN_LEN {
+            return Err(anyhow!("wrong length".to_string()));
+        }
+        let ts: [u8; 4] = bytes[..4].try_into().map_err(AnyErr::msg)?;
+        let pk = XOnlyPublicKey::from_slice(&bytes[4..36]).map_err(AnyErr::msg)?;
+        let sig: [u8; SIG_LEN] = bytes[36..].try_into().map_err(AnyErr::msg)?;
+        Ok(Self(bytes_to_u32(ts), Some((pk, sig))))
+    }
+    /// Sign a schnorr token
+    pub fn sign(&self, secret_key: &SecretKey) -> Result<Vec<u8>> {
+        let ts = u32_to_bytes(self.0);
+        let sig = sign_tagged(TOKEN_TAG, &ts, secret_key)?;
+        let mut ret = ts.to_vec();
+        ret.extend_from_slice(&x_only(secret_key).serialize());
+        ret.extend_from_slice(&sig);
+        Ok(ret)
+    }
+    /// Sign a schnorr token
+    pub fn sign_to_base64(&self, secret_key: &SecretKey) -> Result<String> {
+        let s = self.sign(secret_key)?;
+        Ok(base64_encode(&s))
+    }
+    /// Verify signed token against a known key
+    pub fn verify(&self, public_key: &XOnlyPublicKey) -> Result<()> {
+        let (pk, _) = self.1.ok_or_else(|| anyhow!("no sig".to_string()))?;
+        if pk != *public_key {
+            return Err(anyhow!("wrong pubkey".to_string()));
+        }
+        self.signer().map(|_| ())
+    }
+    /// The key in the token, once its sig checks out
+    pub fn signer(&self) -> Result<XOnlyPublicKey> {
+        let (pk, sig) = self.1.ok_or_else(|| anyhow!("no sig".to_string()))?;
+        verify_tagged(TOKEN_TAG, &u32_to_bytes(self.0), &sig, &pk)?;
+        Ok(pk)
+    }
+    /// The key in the token, and check timestamp
+    pub fn signer_within(&self, secs: u32) -> Result<XOnlyPublicKey> {
+        let now = now();
+        if self.0 < now.saturating_sub(secs) {
+            return Err(anyhow!("expired".to_string()));
+        }
+        if self.0 > now.saturating_add(FUTURE_SKEW) {
+            return Err(anyhow!("timestamp in the future".to_string()));
+        }
+        self.signer()
+    }
+}
+
+impl Default for SchnorrToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schnorr::*;
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_slice(&[0xcd; 32]).expect("32 bytes, within curve order")
+    }
+
+    fn h32(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    // (seckey, pubkey, aux_rand, msg, sig) from the BIP-340 test vectors
+    const SIGN_VECTORS: [(&str, &str, &str, &str, &str); 4] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+        ),
+        (
+            "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A",
+        ),
+        (
+            "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9",
+            "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+            "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906",
+            "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7",
+        ),
+        (
+            "0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710",
+            "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3",
+        ),
+    ];
+
+    #[test]
+    fn test_bip340_vectors() {
+        let secp = Secp256k1::new();
+        for (sk, pk, aux, msg, sig) in SIGN_VECTORS.iter() {
+            let keypair = KeyPair::from_seckey_slice(&secp, &h32(sk)).unwrap();
+            let pubkey = XOnlyPublicKey::from_slice(&h32(pk)).unwrap();
+            assert_eq!(keypair.x_only_public_key().0, pubkey);
+            let m = Message::from_slice(&h32(msg)).unwrap();
+            let s = secp.sign_schnorr_with_aux_rand(&m, &keypair, &h32(aux));
+            assert_eq!(hex::encode_upper(s.as_ref()), *sig);
+            secp.verify_schnorr(&s, &m, &pubkey).unwrap();
+        }
+        // index 4 verifies, 5 has a pubkey off the curve, 6 an odd R
+        let pk = "D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9";
+        let m = Message::from_slice(&h32(
+            "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+        ))
+        .unwrap();
+        let s = Signature::from_slice(&hex::decode("00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C6376AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4").unwrap()).unwrap();
+        let pubkey = XOnlyPublicKey::from_slice(&h32(pk)).unwrap();
+        secp.verify_schnorr(&s, &m, &pubkey).unwrap();
+        let off_curve = "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34";
+        assert!(XOnlyPublicKey::from_slice(&h32(off_curve)).is_err());
+        let pubkey = XOnlyPublicKey::from_slice(&h32(SIGN_VECTORS[1].1)).unwrap();
+        let m = Message::from_slice(&h32(SIGN_VECTORS[1].3)).unwrap();
+        let s = Signature::from_slice(&hex::decode("FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A14602975563CC27944640AC607CD107AE10923D9EF7A73C643E166BE5EBEAFA34B1AC553E2").unwrap()).unwrap();
+        assert!(secp.verify_schnorr(&s, &m, &pubkey).is_err());
+    }
+
+    #[test]
+    fn test_schnorr_message() {
+        let sk = secret_key();
+        let pk = x_only(&sk);
+        let sig = sign_message(b"hello", &sk).unwrap();
+        verify_message(b"hello", &sig, &pk).expect("failed to verify");
+        assert!(verify_message(b"hello!", &sig, &pk).is_err());
+        // the same bytes signed as a token don't pass as a message
+        let tok = sign_tagged(TOKEN_TAG, b"hello", &sk).unwrap();
+        assert!(verify_message(b"hello", &tok, &pk).is_err());
+    }
+
+    #[test]
+    fn test_schnorr_token() {
+        let sk = secret_key();
+        let pk = x_only(&sk);
+        let token = SchnorrToken::new().sign_to_base64(&sk).unwrap();
+        let t = SchnorrToken::from_base64(&token).expect("couldnt parse base64");
+        t.verify(&pk).expect("failed to verify");
+        assert_eq!(t.signer_within(10).unwrap(), pk);
+        let other = x_only(&SecretKey::from_slice(&[0xab; 32]).unwrap());
+        assert!(t.verify(&other).is_err());
+        // a message sig of the timestamp is not a token
+        let mut bytes = base64_decode(&token).unwrap();
+        let msg_sig = sign_message(&bytes[..4], &sk).unwrap();
+        bytes[36..].copy_from_slice(&msg_sig);
+        let t = SchnorrToken::from_base64(&base64_encode(&bytes)).unwrap();
+        assert!(t.signer().is_err());
+        let old = SchnorrToken::new_with_time(1_000)
+            .sign_to_base64(&sk)
+            .unwrap();
+        let t = SchnorrToken::from_base64(&old).unwrap();
+        t.signer().expect("valid sig");
+        assert!(t.signer_within(10).is_err());
+    }
+}
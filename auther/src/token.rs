@@ -2,13 +2,51 @@ use crate::{recover_pubkey, sign_message, verify_message};
 
 use anyhow::{anyhow, Error as AnyErr, Result};
 use base64::{engine::general_purpose::URL_SAFE, Engine};
+#[cfg(feature = "std")]
+use secp256k1::rand::{rngs::OsRng, RngCore};
 use secp256k1::{PublicKey, SecretKey};
 use std::convert::TryInto;
 
+/// Audiences of the sphinx services
+pub const AUDIENCE_BROKER: &str = "broker";
+pub const AUDIENCE_MEDIA: &str = "media";
+pub const AUDIENCE_TRIBES: &str = "tribes";
+
+const V1_LEN: usize = 69;
+const SIG_LEN: usize = 65;
+const V2_VERSION: u8 = 2;
+const JTI_LEN: usize = 16;
+// version + issued + expiry + jti + audience len + scopes len
+const V2_MIN_PAYLOAD: usize = 1 + 4 + 4 + JTI_LEN + 1 + 1;
+
 #[derive(Debug)]
 pub struct Token(u32, Option<[u8; 65]>);
 
-fn now() -> u32 {
+/// A token for one audience, with an explicit expiry and a random jti.
+/// Encoded as sig(65) | 2 | issued | expiry | jti(16) | audience | scopes,
+/// where the strings are length prefixed with one byte, and the sig is a
+/// lightning signature of everything after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenV2 {
+    pub issued: u32,
+    pub expiry: u32,
+    pub jti: [u8; JTI_LEN],
+    pub audience: String,
+    pub scopes: Vec<String>,
+    sig: Option<[u8; 65]>,
+}
+
+/// Either token version, as parsed by `parse_token`
+#[derive(Debug)]
+pub enum AnyToken {
+    V1(Token),
+    V2(TokenV2),
+}
+
+/// How far ahead of the verifier's clock a token may be
+pub const FUTURE_SKEW: u32 = 60;
+
+pub(crate) fn now() -> u32 {
     use std::time::{SystemTime, UNIX_EPOCH};
     let start = SystemTime::now();
     let since_the_epoch = start
@@ -88,15 +126,28 @@ impl Token {
     }
     /// Recover pubkey from signed token, and check timestamp
     pub fn recover_within(&self, secs: u32) -> Result<PublicKey> {
+        self.recover_within_at(secs, now())
+    }
+    /// Like recover_within, at the time `now`
+    pub fn recover_within_at(&self, secs: u32, now: u32) -> Result<PublicKey> {
         if self.1.is_none() {
             return Err(anyhow!("no sig".to_string()));
         }
-        if self.0 < now() - secs {
+        if self.0 < now.saturating_sub(secs) {
             return Err(anyhow!("expired".to_string()));
         }
+        if self.0 > now.saturating_add(FUTURE_SKEW) {
+            return Err(anyhow!("timestamp in the future".to_string()));
+        }
         let msg = u32_to_bytes(self.0);
         recover_pubkey(msg.as_ref(), &self.1.unwrap())
     }
+    pub fn timestamp(&self) -> u32 {
+        self.0
+    }
+    pub fn sig(&self) -> Option<&[u8; 65]> {
+        self.1.as_ref()
+    }
 }
 
 impl Default for Token {
@@ -105,6 +156,196 @@ impl Default for Token {
     }
 }
 
+impl TokenV2 {
+    /// Creates a new token for the audience, valid for ttl seconds from now
+    #[cfg(feature = "std")]
+    pub fn new(audience: &str, ttl: u32) -> Self {
+        let issued = now();
+        Self::new_with_time(audience, issued, issued.saturating_add(ttl))
+    }
+    #[cfg(feature = "std")]
+    pub fn new_with_time(audience: &str, issued: u32, expiry: u32) -> Self {
+        let mut jti = [0u8; JTI_LEN];
+        OsRng.fill_bytes(&mut jti);
+        Self::new_with_jti(audience, issued, expiry, jti)
+    }
+    /// Without an OS rng, the caller provides the random jti
+    pub fn new_with_jti(audience: &str, issued: u32, expiry: u32, jti: [u8; JTI_LEN]) -> Self {
+        Self {
+            issued,
+            expiry,
+            jti,
+            audience: audience.to_string(),
+            scopes: Vec::new(),
+            sig: None,
+        }
+    }
+    pub fn with_scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+    pub fn set_sig(&mut self, sig: [u8; 65]) {
+        self.sig = Some(sig)
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+    pub fn jti_hex(&self) -> String {
+        self.jti.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+    // the signed bytes, everything after the sig
+    fn payload(&self) -> Result<Vec<u8>> {
+        if self.expiry <= self.issued {
+            return Err(anyhow!("expiry must be after issued"));
+        }
+        if self.scopes.len() > u8::MAX as usize {
+            return Err(anyhow!("too many scopes"));
+        }
+        let mut p = vec![V2_VERSION];
+        p.extend_from_slice(&u32_to_bytes(self.issued));
+        p.extend_from_slice(&u32_to_bytes(self.expiry));
+        p.extend_from_slice(&self.jti);
+        push_str(&mut p, &self.audience)?;
+        p.push(self.scopes.len() as u8);
+        for scope in self.scopes.iter() {
+            push_str(&mut p, scope)?;
+        }
+        Ok(p)
+    }
+    fn from_payload(payload: &[u8], sig: [u8; 65]) -> Result<Self> {
+        if payload.len() < V2_MIN_PAYLOAD {
+            return Err(anyhow!("token too short"));
+        }
+        if payload[0] != V2_VERSION {
+            return Err(anyhow!("unknown token version {}", payload[0]));
+        }
+        let issued = bytes_to_u32(payload[1..5].try_into().map_err(AnyErr::msg)?);
+        let expiry = bytes_to_u32(payload[5..9].try_into().map_err(AnyErr::msg)?);
+        let jti: [u8; JTI_LEN] = payload[9..9 + JTI_LEN].try_into().map_err(AnyErr::msg)?;
+        let mut rest = &payload[9 + JTI_LEN..];
+        let audience = take_str(&mut rest)?;
+        let (n, mut rest) = rest.split_first().ok_or_else(|| anyhow!("no scopes"))?;
+        let mut scopes = Vec::with_capacity(*n as usize);
+        for _ in 0..*n {
+            scopes.push(take_str(&mut rest)?);
+        }
+        if !rest.is_empty() {
+            return Err(anyhow!("trailing bytes in token"));
+        }
+        Ok(Self {
+            issued,
+            expiry,
+            jti,
+            audience,
+            scopes,
+            sig: Some(sig),
+        })
+    }
+    pub fn from_base64(s: &str) -> Result<Self> {
+        let bytes = base64_decode(s)?;
+        if bytes.len() < SIG_LEN + V2_MIN_PAYLOAD {
+            return Err(anyhow!("wrong length".to_string()));
+        }
+        let sig: [u8; 65] = bytes[..SIG_LEN].try_into().map_err(AnyErr::msg)?;
+        Self::from_payload(&bytes[SIG_LEN..], sig)
+    }
+    /// Sign a v2 token
+    pub fn sign(&self, secret_key: &SecretKey) -> Result<Vec<u8>> {
+        let payload = self.payload()?;
+        let mut ret = sign_message(&payload, secret_key)?;
+        ret.extend(payload);
+        Ok(ret)
+    }
+    /// Sign a v2 token
+    pub fn sign_to_base64(&self, secret_key: &SecretKey) -> Result<String> {
+        let s = self.sign(secret_key)?;
+        Ok(base64_encode(&s))
+    }
+    /// Verify the signature only
+    pub fn verify(&self, public_key: &PublicKey) -> Result<()> {
+        let sig = self.sig.ok_or_else(|| anyhow!("no sig".to_string()))?;
+        verify_message(&self.payload()?, &sig, public_key)
+    }
+    /// Recover pubkey from the signature only
+    pub fn recover(&self) -> Result<PublicKey> {
+        let sig = self.sig.ok_or_else(|| anyhow!("no sig".to_string()))?;
+        recover_pubkey(&self.payload()?, &sig)
+    }
+    /// Check the audience and expiry at the time `now`
+    pub fn check(&self, audience: &str, now: u32) -> Result<()> {
+        if self.audience != audience {
+            return Err(anyhow!("wrong audience {}", self.audience));
+        }
+        if now >= self.expiry {
+            return Err(anyhow!("expired".to_string()));
+        }
+        if self.issued > now.saturating_add(FUTURE_SKEW) {
+            return Err(anyhow!("issued in the future".to_string()));
+        }
+        Ok(())
+    }
+    /// Verify a token meant for this audience, that has not expired
+    pub fn verify_for(&self, public_key: &PublicKey, audience: &str) -> Result<()> {
+        self.check(audience, now())?;
+        self.verify(public_key)
+    }
+    /// Recover pubkey from a token meant for this audience, that has not expired
+    pub fn recover_for(&self, audience: &str) -> Result<PublicKey> {
+        self.check(audience, now())?;
+        self.recover()
+    }
+}
+
+/// Parse a base64 token of either version. 69 bytes is a v1 token
+pub fn parse_token(s: &str) -> Result<AnyToken> {
+    let bytes = base64_decode(s)?;
+    if bytes.len() == V1_LEN {
+        return Ok(AnyToken::V1(Token::from_base64(s)?));
+    }
+    Ok(AnyToken::V2(TokenV2::from_base64(s)?))
+}
+
+/// Recover the pubkey of a token for this audience. v1 tokens have no
+/// audience, so they are only accepted if `v1_within` is set, and then
+/// only within that many seconds
+pub fn recover_token(s: &str, audience: &str, v1_within: Option<u32>) -> Result<PublicKey> {
+    match parse_token(s)? {
+        AnyToken::V1(t) => match v1_within {
+            Some(secs) => t.recover_within(secs),
+            None => Err(anyhow!("v1 tokens are not accepted")),
+        },
+        AnyToken::V2(t) => t.recover_for(audience),
+    }
+}
+
+/// True if a lightning signature of `msg` could be used as a token.
+/// Anything that signs arbitrary messages with a node key should refuse these
+pub fn is_token_msg(msg: &[u8]) -> bool {
+    msg.len() == V1_LEN - SIG_LEN || msg.first() == Some(&V2_VERSION)
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.len() > u8::MAX as usize {
+        return Err(anyhow!("token string too long"));
+    }
+    buf.push(s.len() as u8);
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn take_str(input: &mut &[u8]) -> Result<String> {
+    let (len, rest) = input
+        .split_first()
+        .ok_or_else(|| anyhow!("token too short"))?;
+    let len = *len as usize;
+    if rest.len() < len {
+        return Err(anyhow!("token too short"));
+    }
+    let s = String::from_utf8(rest[..len].to_vec()).map_err(AnyErr::msg)?;
+    *input = &rest[len..];
+    Ok(s)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::token::*;
@@ -185,6 +426,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_check_future_timestamp() {
+        let sk = secret_key();
+        let t = Token::new_with_time(1_000);
+        let token = t.sign_to_base64(&sk).expect("couldnt make token");
+        let t = Token::from_base64(&token).expect("couldnt parse base64");
+        // a window longer than the epoch does not underflow
+        t.recover_within_at(u32::MAX, 100)
+            .expect_err("too far ahead");
+        t.recover_within_at(u32::MAX, 1_000 - FUTURE_SKEW)
+            .expect("within the skew");
+        t.recover_within_at(u32::MAX, 2_000).expect("in the window");
+        assert!(t.recover_within(u32::MAX).is_ok());
+        let t2 = TokenV2::new_with_time(AUDIENCE_BROKER, 1_000, 1_100);
+        assert!(t2.check(AUDIENCE_BROKER, 1_000 - FUTURE_SKEW - 1).is_err());
+        t2.check(AUDIENCE_BROKER, 1_000 - FUTURE_SKEW)
+            .expect("within the skew");
+    }
+
     #[test]
     fn test_tribe() {
         let pk = hex::decode("02290714deafd0cb33d2be3b634fc977a98a9c9fa1dd6c53cf17d99b350c08c67b")
@@ -211,4 +471,91 @@ mod tests {
         let t = Token::from_base64(tok).expect("couldnt parse base64");
         t.verify(&public_key).expect("failed to verify");
     }
+
+    #[test]
+    fn test_token_v2() {
+        let sk = secret_key();
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::from_secret_key(&secp, &sk);
+        let t1 = TokenV2::new(AUDIENCE_MEDIA, 60).with_scopes(&["upload", "download"]);
+        let token = t1.sign_to_base64(&sk).expect("couldnt make token");
+        let t = TokenV2::from_base64(&token).expect("couldnt parse base64");
+        assert_eq!(t.audience, AUDIENCE_MEDIA);
+        assert_eq!(t.jti, t1.jti);
+        assert!(t.has_scope("upload"));
+        assert!(!t.has_scope("admin"));
+        t.verify_for(&public_key, AUDIENCE_MEDIA)
+            .expect("failed to verify");
+        let pk2 = t.recover_for(AUDIENCE_MEDIA).expect("failed to recover");
+        assert_eq!(public_key, pk2);
+        // not for the other services
+        assert!(t.recover_for(AUDIENCE_BROKER).is_err());
+        assert!(t.verify_for(&public_key, AUDIENCE_TRIBES).is_err());
+        // a fresh jti each time
+        assert_ne!(TokenV2::new(AUDIENCE_MEDIA, 60).jti, t1.jti);
+        let mut jti = [0u8; 16];
+        jti[0] = 0xab;
+        jti[15] = 0x01;
+        let t3 = TokenV2::new_with_jti(AUDIENCE_MEDIA, 1_000, 1_060, jti);
+        assert_eq!(t3.jti_hex(), "ab000000000000000000000000000001");
+    }
+
+    #[test]
+    fn test_token_v2_expiry() {
+        let sk = secret_key();
+        let t1 = TokenV2::new_with_time(AUDIENCE_BROKER, 1_000, 1_060);
+        let token = t1.sign_to_base64(&sk).expect("couldnt make token");
+        let t = TokenV2::from_base64(&token).expect("couldnt parse base64");
+        t.check(AUDIENCE_BROKER, 1_059).expect("not expired yet");
+        assert!(t.check(AUDIENCE_BROKER, 1_060).is_err());
+        assert!(t.recover_for(AUDIENCE_BROKER).is_err());
+        let t2 = TokenV2::new_with_time(AUDIENCE_BROKER, 1_000, 1_000);
+        assert!(t2.sign(&sk).is_err());
+    }
+
+    #[test]
+    fn test_token_v2_tamper() {
+        let sk = secret_key();
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::from_secret_key(&secp, &sk);
+        let t1 = TokenV2::new(AUDIENCE_TRIBES, 60);
+        let mut bytes = t1.sign(&sk).expect("couldnt make token");
+        // swap the audience for "broker"
+        let aud = bytes.len() - 1 - AUDIENCE_TRIBES.len();
+        bytes.truncate(aud - 1);
+        bytes.push(AUDIENCE_BROKER.len() as u8);
+        bytes.extend_from_slice(AUDIENCE_BROKER.as_bytes());
+        bytes.push(0);
+        let t = TokenV2::from_base64(&base64_encode(&bytes)).expect("couldnt parse");
+        assert!(t.verify_for(&public_key, AUDIENCE_BROKER).is_err());
+        assert_ne!(t.recover().expect("recover"), public_key);
+        // trailing bytes
+        let mut bytes = t1.sign(&sk).expect("couldnt make token");
+        bytes.push(0);
+        assert!(TokenV2::from_base64(&base64_encode(&bytes)).is_err());
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let sk = secret_key();
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::from_secret_key(&secp, &sk);
+        let v1 = Token::new()
+            .sign_to_base64(&sk)
+            .expect("couldnt make token");
+        assert!(matches!(parse_token(&v1), Ok(AnyToken::V1(_))));
+        let pk2 = recover_token(&v1, AUDIENCE_BROKER, Some(10)).expect("v1 failed");
+        assert_eq!(public_key, pk2);
+        assert!(recover_token(&v1, AUDIENCE_BROKER, None).is_err());
+        let v2 = TokenV2::new(AUDIENCE_BROKER, 60)
+            .sign_to_base64(&sk)
+            .expect("couldnt make token");
+        assert!(matches!(parse_token(&v2), Ok(AnyToken::V2(_))));
+        let pk3 = recover_token(&v2, AUDIENCE_BROKER, None).expect("v2 failed");
+        assert_eq!(public_key, pk3);
+        assert!(recover_token(&v2, AUDIENCE_MEDIA, Some(10)).is_err());
+        assert!(is_token_msg(&[0, 0, 0, 1]));
+        assert!(is_token_msg(&[V2_VERSION, 1, 2]));
+        assert!(!is_token_msg(b"hello"));
+    }
 }
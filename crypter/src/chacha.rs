@@ -41,10 +41,44 @@ pub fn decrypt(payload: [u8; PAYLOAD_LEN], key: [u8; KEY_LEN]) -> anyhow::Result
     Ok(ret)
 }
 
+// like encrypt, for a plaintext of any length: ciphertext | tag | nonce
+pub fn encrypt_vec(
+    plaintext: &[u8],
+    key: [u8; KEY_LEN],
+    nonce: [u8; NONCE_LEN],
+) -> anyhow::Result<std::vec::Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new_from_slice(&key).unwrap();
+    let nonce = Nonce::from_slice(&nonce);
+    let mut ret = plaintext.to_vec();
+    let tag = cipher
+        .encrypt_in_place_detached(nonce, b"", &mut ret)
+        .or(Err(Error::msg("Failed to encrypt")))?;
+    ret.extend_from_slice(&tag);
+    ret.extend_from_slice(nonce);
+    Ok(ret)
+}
+
+pub fn decrypt_vec(payload: &[u8], key: [u8; KEY_LEN]) -> anyhow::Result<std::vec::Vec<u8>> {
+    if payload.len() < TAG_LEN + NONCE_LEN {
+        return Err(Error::msg("Payload too short"));
+    }
+    let (rest, nonce) = payload.split_at(payload.len() - NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let cipher = ChaCha20Poly1305::new_from_slice(&key).unwrap();
+    let mut ret = ciphertext.to_vec();
+    cipher
+        .decrypt_in_place_detached(Nonce::from_slice(nonce), b"", &mut ret, tag.into())
+        .or(Err(Error::msg("Failed to decrypt")))?;
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::chacha::{decrypt, encrypt, KEY_LEN, MSG_LEN, NONCE_LEN};
+    use crate::chacha::{
+        decrypt, decrypt_vec, encrypt, encrypt_vec, KEY_LEN, MSG_LEN, NONCE_LEN, PAYLOAD_LEN,
+    };
     use rand::{rngs::OsRng, RngCore};
+    use std::convert::TryInto;
 
     #[test]
     fn test_chacha() -> anyhow::Result<()> {
@@ -57,4 +91,25 @@ mod tests {
         assert_eq!(plaintext, plain);
         Ok(())
     }
+
+    #[test]
+    fn test_chacha_vec() -> anyhow::Result<()> {
+        let key = [9; KEY_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        for plaintext in [vec![], vec![7; 5], vec![3; 300]] {
+            let cipher = encrypt_vec(&plaintext, key, nonce)?;
+            assert_eq!(decrypt_vec(&cipher, key)?, plaintext);
+        }
+        // same layout as encrypt
+        let plaintext = [1; MSG_LEN];
+        let cipher = encrypt_vec(&plaintext, key, nonce)?;
+        let fixed: [u8; PAYLOAD_LEN] = cipher.clone().try_into().unwrap();
+        assert_eq!(decrypt(fixed, key)?, plaintext);
+        let mut bad = cipher;
+        bad[0] ^= 1;
+        assert!(decrypt_vec(&bad, key).is_err());
+        assert!(decrypt_vec(&[0; 10], key).is_err());
+        Ok(())
+    }
 }
@@ -1,17 +1,51 @@
 pub use crate::ser::*;
 pub use crate::types::*;
+
+use crate::error::{Error as GlyphError, ErrorCode};
+use crate::ota::OtaVerifier;
 use anyhow::Result;
+use sphinx_auther::delegation::{Certificate, Delegation};
 use sphinx_auther::nonce;
-use sphinx_auther::secp256k1::{PublicKey, SecretKey};
+use sphinx_auther::secp256k1::ecdsa::Signature;
+use sphinx_auther::secp256k1::hashes::sha256::Hash as Sha256Hash;
+use sphinx_auther::secp256k1::hashes::{Hash, HashEngine};
+use sphinx_auther::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 use sphinx_auther::token::Token;
+#[cfg(feature = "std")]
+use sphinx_crypter::{chacha, ecdh};
 use std::sync::{Arc, Mutex};
 
-// u64 is the nonce. Each signature must have a higher nonce
-pub struct Controller(SecretKey, PublicKey, u64, Arc<Mutex<dyn ControlPersist>>);
+// the most entries returned by one QueryAudit
+pub const AUDIT_PAGE_MAX: u32 = 100;
+
+// u64 is the nonce. Each signature must have a higher nonce,
+// or an unused one within the last u32 nonces
+pub struct Controller(
+    SecretKey,
+    PublicKey,
+    u64,
+    Arc<Mutex<dyn ControlPersist>>,
+    Option<OtaVerifier>,
+    u32,
+    Option<SecretKey>,
+    // the node key and signer features, for QueryInfo
+    Option<PublicKey>,
+    Vec<String>,
+);
 
 impl Controller {
     pub fn new(sk: SecretKey, pk: PublicKey, nonce: u64) -> Self {
-        Self(sk, pk, nonce, Arc::new(Mutex::new(DummyPersister)))
+        Self(
+            sk,
+            pk,
+            nonce,
+            Arc::new(Mutex::new(DummyPersister)),
+            None,
+            nonce::DEFAULT_WINDOW,
+            None,
+            None,
+            default_features(),
+        )
     }
     pub fn new_with_persister(
         sk: SecretKey,
@@ -21,7 +55,41 @@ impl Controller {
         let store1 = per.clone();
         let store = store1.lock().unwrap();
         let nonce = store.read_nonce().unwrap_or(0);
-        Self(sk, pk, nonce, per)
+        Self(
+            sk,
+            pk,
+            nonce,
+            per,
+            None,
+            nonce::DEFAULT_WINDOW,
+            None,
+            None,
+            default_features(),
+        )
+    }
+    // Ota messages are checked against this release-signing address,
+    // and refused if there is none
+    pub fn set_ota_verifier(&mut self, verifier: OtaVerifier) {
+        self.4 = Some(verifier)
+    }
+    // how far below the highest nonce a msg can still arrive
+    pub fn set_nonce_window(&mut self, size: u32) {
+        self.5 = size
+    }
+    // InjectSeed msgs are encrypted to this key, or else to the controller key
+    pub fn set_provisioning_key(&mut self, sk: SecretKey) {
+        self.6 = Some(sk)
+    }
+    // QueryInfo answers with this node key and the features of the signer
+    pub fn set_node_info(&mut self, node_id: PublicKey, features: Vec<String>) {
+        self.7 = Some(node_id);
+        self.8 = features;
+    }
+    pub fn provisioning_pubkey(&self) -> PublicKey {
+        match &self.6 {
+            Some(sk) => PublicKey::from_secret_key(&Secp256k1::signing_only(), sk),
+            None => self.1,
+        }
     }
     pub fn make_auth_token(&self) -> Result<String> {
         let t = Token::new();
@@ -48,6 +116,37 @@ impl Controller {
         serialize_controlresponse(&mut buff, &msg)?;
         Ok(buff.into_vec())
     }
+    // The response, the sha256 of the request, its nonce and a signature by
+    // the controller key, so it can't be forged or swapped with the response
+    // to another request, even one from another admin with the same nonce.
+    // It starts with the plain response, so parse_control_response still works
+    pub fn build_signed_response(
+        &self,
+        msg: ControlResponse,
+        request: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (_, request_nonce) = control_msg_signer(request)?;
+        let mut buff = ByteBuf::new();
+        serialize_controlresponse(&mut buff, &msg)?;
+        let mut payload = buff.into_vec();
+        payload.extend_from_slice(&request_hash(request));
+        nonce::build_msg(&payload, &self.0, request_nonce)
+    }
+    // a signed response, encrypted to the admin that sent the request
+    #[cfg(feature = "std")]
+    pub fn build_encrypted_response(
+        &self,
+        msg: ControlResponse,
+        request: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        use sphinx_auther::secp256k1::rand::{rngs::OsRng, RngCore};
+        let (admin, _) = control_msg_signer(request)?;
+        let signed = self.build_signed_response(msg, request)?;
+        let secret = ecdh::derive_shared_secret(&admin, &self.0).secret_bytes();
+        let mut n = [0u8; chacha::NONCE_LEN];
+        OsRng.fill_bytes(&mut n);
+        chacha::encrypt_vec(&signed, secret, n)
+    }
     pub fn parse_msg(&mut self, input: &[u8]) -> anyhow::Result<ControlMessage> {
         let msg = nonce::parse_msg(input, &self.1, self.2)?;
         let mut bytes = Bytes::new(&msg);
@@ -61,35 +160,259 @@ impl Controller {
         let ret = deserialize_controlmessage(&mut bytes)?;
         Ok((ret, nonce))
     }
+    // like parse_msg_no_nonce, but also takes a msg from a delegate of the
+    // controller key, if the msg is one of the kinds it was certified for
+    pub fn parse_msg_delegated(
+        &mut self,
+        input: &[u8],
+    ) -> anyhow::Result<(ControlMessage, u64, Option<Delegation>)> {
+        let (msg, nonce, delegation) =
+            nonce::parse_msg_delegated(input, &self.1, now_secs() as u32)?;
+        let mut bytes = Bytes::new(&msg);
+        let ret = deserialize_controlmessage(&mut bytes)?;
+        if let Some(d) = &delegation {
+            check_delegation(d, &ret)?;
+        }
+        Ok((ret, nonce, delegation))
+    }
     pub fn parse_response(&self, input: &[u8]) -> anyhow::Result<ControlResponse> {
         let mut bytes = Bytes::new(input);
         deserialize_controlresponse(&mut bytes)
     }
     // return the OG message for further processing
     pub fn handle(&mut self, input: &[u8]) -> anyhow::Result<(ControlMessage, ControlResponse)> {
-        let msg_nonce = self.parse_msg_no_nonce(input)?;
-        let msg = msg_nonce.0;
+        self.handle_checked(input, |_| Ok(()))
+    }
+    // like handle, but `check` can refuse the msg before anything is applied,
+    // so the store is left as it was. The nonce is still used up
+    // ECDSA msgs recover their signer. A Schnorr msg names its x-only key,
+    // which stands for the owner or the admin with that key
+    #[allow(clippy::type_complexity)]
+    fn parse_signed(
+        &self,
+        input: &[u8],
+        admins: &[Admin],
+    ) -> anyhow::Result<(Vec<u8>, u64, PublicKey, Option<Delegation>)> {
+        #[cfg(feature = "std")]
+        if nonce::is_schnorr_msg(input) {
+            let (msg, msg_nonce, xpk) = nonce::parse_msg_recover_schnorr(input)?;
+            let keys = admins
+                .iter()
+                .filter_map(|a| hex::decode(&a.pubkey).ok())
+                .filter_map(|b| PublicKey::from_slice(&b).ok());
+            return match core::iter::once(self.1)
+                .chain(keys)
+                .find(|k| k.x_only_public_key().0 == xpk)
+            {
+                Some(signer) => Ok((msg, msg_nonce, signer, None)),
+                None => Err(anyhow::anyhow!("unknown admin {}", xpk)),
+            };
+        }
+        nonce::parse_msg_recover_delegated(input, now_secs() as u32)
+    }
+    pub fn handle_checked<F>(
+        &mut self,
+        input: &[u8],
+        check: F,
+    ) -> anyhow::Result<(ControlMessage, ControlResponse)>
+    where
+        F: FnOnce(&ControlMessage) -> anyhow::Result<()>,
+    {
         // handle on store
         let mut store = self.3.lock().unwrap();
+        let mut admins = store.read_admins().unwrap_or_default();
+        // signer is the root of a delegated msg, so the delegate gets its role
+        let (msg_bytes, msg_nonce, signer, delegation) = self.parse_signed(input, &admins)?;
+        // the key that signed the msg itself
+        let sender = delegation.as_ref().map_or(signer, |d| d.delegate);
+        // the controller key is the owner, other admins are in the store.
+        // Nothing from an unknown key gets to the msgpack parser
+        let admin_idx = if signer == self.1 {
+            None
+        } else {
+            let pk = hex::encode(signer.serialize());
+            match admins.iter().position(|a| a.pubkey == pk) {
+                Some(i) => Some(i),
+                None => return Err(anyhow::anyhow!("unknown admin {}", pk)),
+            }
+        };
+        let mut bytes = Bytes::new(&msg_bytes);
+        let msg = deserialize_controlmessage(&mut bytes)?;
+        if let Some(d) = &delegation {
+            check_delegation(d, &msg)?;
+        }
+        let role = admin_idx.map_or(Role::Owner, |i| admins[i].role);
+        if !role.allows(&msg) {
+            return Err(anyhow::Error::msg(GlyphError::with_code(
+                ErrorCode::Control,
+                &format!(
+                    "{} can't send a msg that needs {}",
+                    role.as_str(),
+                    msg.required_role().as_str()
+                ),
+            )));
+        }
+        // each admin has its own nonce, and a delegate only has its window
+        let pk = hex::encode(sender.serialize());
+        let mut windows = store.read_nonce_windows().unwrap_or_default();
+        let last_nonce = match &delegation {
+            Some(_) => windows.iter().find(|w| w.pubkey == pk).map_or(0, |w| w.top),
+            None => admin_idx.map_or(self.2, |i| admins[i].nonce),
+        };
         // increment the nonce EXCEPT for Nonce requests
-        match msg {
-            ControlMessage::Nonce => (),
+        // a Batch consumes one nonce, even if it is rolled back
+        let current_nonce = match msg {
+            ControlMessage::Nonce => last_nonce,
             _ => {
-                // nonce must be higher each time
-                // keep sanity and don't increment by more than 20 at a time
-                if msg_nonce.1 <= self.2 || msg_nonce.1 > self.2 + 20 {
-                    return Err(anyhow::anyhow!("invalid nonce"));
+                let mut window = load_window(&windows, &pk, last_nonce, self.5);
+                check_nonce(msg_nonce, &mut window)?;
+                let top = window.top();
+                match (&delegation, admin_idx) {
+                    (Some(_), _) => (),
+                    (None, Some(i)) => {
+                        admins[i].nonce = top;
+                        store.write_admins(admins.clone())?;
+                    }
+                    (None, None) => {
+                        self.2 = top;
+                        store.set_nonce(self.2)?;
+                    }
+                }
+                let state = NonceWindowState {
+                    pubkey: pk,
+                    top,
+                    seen: window.seen().to_vec(),
+                };
+                match windows.iter_mut().find(|w| w.pubkey == state.pubkey) {
+                    Some(w) => *w = state,
+                    None => windows.push(state),
+                }
+                store.write_nonce_windows(windows)?;
+                top
+            }
+        };
+        check(&msg)?;
+        // a msg, or a whole Batch, is rolled back if it or its audit entry
+        // fails, so the log and the state always agree. What an auditor
+        // can send changes nothing
+        let snapshot = match msg.required_role() {
+            Role::Auditor => None,
+            _ => Some(Snapshot::take(&*store)),
+        };
+        let res = self
+            .apply_all(&mut *store, &mut admins, current_nonce, msg.clone())
+            .and_then(|res| {
+                // Nonce requests change nothing, so they are not recorded
+                let kinds = match &msg {
+                    ControlMessage::Nonce => vec![],
+                    ControlMessage::Batch(msgs) => msgs.iter().map(|m| m.kind()).collect(),
+                    m => vec![m.kind()],
+                };
+                for kind in kinds {
+                    self.audit(&mut *store, kind, msg_nonce, &sender)
+                        .map_err(|e| e.context("audit log failed"))?;
+                }
+                Ok(res)
+            });
+        match res {
+            Ok(res) => Ok((msg, res)),
+            Err(e) => match snapshot.map_or(Ok(()), |s| s.restore(&mut *store)) {
+                Ok(()) => Err(e),
+                Err(re) => Err(e.context(format!("rollback failed: {}", re))),
+            },
+        }
+    }
+    // apply a msg, or each msg of a Batch in order
+    fn apply_all(
+        &self,
+        store: &mut dyn ControlPersist,
+        admins: &mut Vec<Admin>,
+        nonce: u64,
+        msg: ControlMessage,
+    ) -> anyhow::Result<ControlResponse> {
+        match msg {
+            ControlMessage::Batch(msgs) => {
+                let mut ress = Vec::with_capacity(msgs.len());
+                for (i, m) in msgs.into_iter().enumerate() {
+                    let r = self
+                        .apply(store, admins, nonce, m)
+                        .map_err(|e| e.context(format!("batch msg {} failed", i)))?;
+                    ress.push(r);
                 }
-                self.2 = msg_nonce.1;
-                store.set_nonce(self.2)?;
+                Ok(ControlResponse::Batch(ress))
             }
+            m => self.apply(store, admins, nonce, m),
         }
-        let res = match msg.clone() {
-            ControlMessage::Nonce => ControlResponse::Nonce(self.2),
+    }
+    // append to the hash chain, signed with the control key
+    fn audit(
+        &self,
+        store: &mut dyn ControlPersist,
+        kind: &str,
+        nonce: u64,
+        signer: &PublicKey,
+    ) -> anyhow::Result<()> {
+        let last = store.read_last_audit()?;
+        let mut entry = AuditEntry {
+            seq: last.as_ref().map_or(0, |e| e.seq + 1),
+            nonce,
+            kind: kind.to_string(),
+            admin: hex::encode(signer.serialize()),
+            timestamp: now_secs(),
+            prev_hash: last.map_or_else(|| hex::encode([0u8; 32]), |e| e.hash),
+            ..Default::default()
+        };
+        let hash = audit_hash(&entry);
+        let m = Message::from_slice(&hash).map_err(anyhow::Error::msg)?;
+        let sig = Secp256k1::signing_only().sign_ecdsa(&m, &self.0);
+        entry.hash = hex::encode(hash);
+        entry.sig = hex::encode(sig.serialize_compact());
+        store.append_audit(entry)
+    }
+    // apply one msg to the store. nonce is the signer's current nonce
+    fn apply(
+        &self,
+        store: &mut dyn ControlPersist,
+        admins: &mut Vec<Admin>,
+        nonce: u64,
+        msg: ControlMessage,
+    ) -> anyhow::Result<ControlResponse> {
+        let res = match msg {
+            ControlMessage::Nonce => ControlResponse::Nonce(nonce),
             ControlMessage::ResetWifi => {
                 store.remove_config()?;
                 ControlResponse::ResetWifi
             }
+            ControlMessage::QueryConfig => {
+                let c = store.read_config().unwrap_or_default();
+                ControlResponse::ConfigCurrent(c.redacted())
+            }
+            ControlMessage::UpdateConfig(mut nc) => {
+                if nc.broker.is_empty() || nc.ssid.is_empty() {
+                    return Err(anyhow::anyhow!("config needs a broker and ssid"));
+                }
+                // a redacted password from QueryConfig keeps the stored one
+                if nc.pass == REDACTED {
+                    nc.pass = store.read_config().unwrap_or_default().pass;
+                }
+                store.write_config(nc.clone())?;
+                ControlResponse::ConfigUpdated(nc.redacted())
+            }
+            ControlMessage::QueryInfo => {
+                ControlResponse::InfoCurrent(Info {
+                    signer_id: store.read_id().map(hex::encode).unwrap_or_default(),
+                    node_id: self
+                        .7
+                        .map(|pk| hex::encode(pk.serialize()))
+                        .unwrap_or_default(),
+                    network: store.read_config().unwrap_or_default().network,
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    nonce,
+                    features: self.8.clone(),
+                    // 0 means no OTA has been installed
+                    ota_version: store.read_ota_version().ok().filter(|v| *v > 0),
+                })
+            }
             ControlMessage::ResetKeys => {
                 store.remove_seed()?;
                 ControlResponse::ResetKeys
@@ -98,6 +421,14 @@ impl Controller {
                 store.remove_config()?;
                 store.remove_seed()?;
                 store.remove_policy()?;
+                store.remove_policy_filter()?;
+                store.write_allowlist(Vec::new())?;
+                store.write_admins(Vec::new())?;
+                // nonce windows are kept, so msgs from before can't be
+                // replayed. A delegate's window can't be dropped even once
+                // its certificate expired: its signed msg could be wrapped
+                // in a newer certificate for the same key
+                store.write_proxy_policy(ProxyPolicy::default())?;
                 store.set_nonce(0)?;
                 ControlResponse::ResetAll
             }
@@ -113,23 +444,263 @@ impl Controller {
                 let v = store.read_velocity().ok();
                 ControlResponse::VelocityCurrent(v)
             }
-            ControlMessage::QueryAllowlist => ControlResponse::AllowlistCurrent(vec![]),
-            ControlMessage::UpdateAllowlist(na) => ControlResponse::AllowlistUpdated(na),
-            ControlMessage::Ota(params) => ControlResponse::OtaConfirm(params),
+            ControlMessage::QueryAllowlist => {
+                let al = store.read_allowlist().unwrap_or_default();
+                ControlResponse::AllowlistCurrent(al)
+            }
+            ControlMessage::UpdateAllowlist(na) => {
+                store.write_allowlist(na.clone())?;
+                ControlResponse::AllowlistUpdated(na)
+            }
+            ControlMessage::AllowlistAdd(add) => {
+                check_allowlist(&add)?;
+                let mut al = store.read_allowlist().unwrap_or_default();
+                for entry in add {
+                    if !al.contains(&entry) {
+                        al.push(entry);
+                    }
+                }
+                store.write_allowlist(al.clone())?;
+                ControlResponse::AllowlistUpdated(al)
+            }
+            ControlMessage::AllowlistRemove(rm) => {
+                check_allowlist(&rm)?;
+                let mut al = store.read_allowlist().unwrap_or_default();
+                al.retain(|entry| !rm.contains(entry));
+                store.write_allowlist(al.clone())?;
+                ControlResponse::AllowlistUpdated(al)
+            }
+            ControlMessage::Ota(params) => {
+                let verifier = self
+                    .4
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("no ota verifier, OTA is disabled"))?;
+                let current = store.read_ota_version().unwrap_or(0);
+                verifier.verify(&params, current)?;
+                // confirmed: this version, or anything older, can't be sent again
+                store.write_ota_version(params.version)?;
+                ControlResponse::OtaConfirm(params)
+            }
             ControlMessage::QueryAll => {
                 let policy = store.read_policy().unwrap_or_default();
                 let velocity = store.read_velocity().ok();
+                let allowlist = store.read_allowlist().unwrap_or_default();
                 ControlResponse::AllCurrent(All {
                     policy,
                     velocity,
-                    allowlist: Vec::new(),
+                    allowlist,
                 })
             }
+            ControlMessage::QueryPolicyFilter => {
+                let f = store.read_policy_filter().unwrap_or_default();
+                ControlResponse::PolicyFilterCurrent(f)
+            }
+            ControlMessage::UpdatePolicyFilter(nf) => {
+                store.write_policy_filter(nf.clone())?;
+                ControlResponse::PolicyFilterUpdated(nf)
+            }
+            // the pending queue lives in the approver
+            ControlMessage::ListPending => ControlResponse::PendingList(vec![]),
+            ControlMessage::ApprovePending(id) => ControlResponse::PendingApproved(id),
+            ControlMessage::DeclinePending(id) => ControlResponse::PendingDeclined(id),
+            // the clock lives in the signer
+            ControlMessage::SetTime(secs) | ControlMessage::ForceTime(secs) => {
+                ControlResponse::TimeSet(secs)
+            }
+            ControlMessage::ListAdmins => ControlResponse::AdminList(admins.clone()),
+            ControlMessage::AddAdmin(mut na) => {
+                let pk = hex::decode(&na.pubkey).map_err(anyhow::Error::msg)?;
+                let pk = PublicKey::from_slice(&pk).map_err(anyhow::Error::msg)?;
+                if pk == self.1 {
+                    return Err(anyhow::anyhow!("the owner key can't be an admin"));
+                }
+                na.pubkey = hex::encode(pk.serialize());
+                // The nonce never comes from the msg. An admin that was
+                // removed starts from the top of its kept nonce window, so
+                // its old msgs can't be replayed
+                let windows = store.read_nonce_windows().unwrap_or_default();
+                let top = windows
+                    .iter()
+                    .find(|w| w.pubkey == na.pubkey)
+                    .map_or(0, |w| w.top);
+                match admins.iter_mut().find(|a| a.pubkey == na.pubkey) {
+                    Some(a) => {
+                        a.role = na.role;
+                        na.nonce = a.nonce.max(top);
+                        a.nonce = na.nonce;
+                    }
+                    None => {
+                        na.nonce = top;
+                        admins.push(na.clone());
+                    }
+                }
+                store.write_admins(admins.clone())?;
+                ControlResponse::AdminAdded(na)
+            }
+            ControlMessage::RemoveAdmin(pk) => {
+                let len = admins.len();
+                admins.retain(|a| a.pubkey != pk);
+                if admins.len() == len {
+                    return Err(anyhow::anyhow!("no admin {}", pk));
+                }
+                // its nonce window is kept, in case it is added again
+                store.write_admins(admins.clone())?;
+                ControlResponse::AdminRemoved(pk)
+            }
+            ControlMessage::InjectSeed(params) => {
+                // a seed is never overwritten, ResetKeys first
+                if store.read_seed().is_ok() {
+                    return Err(anyhow::anyhow!("a seed is already installed"));
+                }
+                let seed = self.open_seed(&params)?;
+                store.write_seed(seed)?;
+                ControlResponse::SeedInjected(params.commitment)
+            }
+            ControlMessage::QueryProxyPolicy => {
+                let p = store.read_proxy_policy().unwrap_or_default();
+                ControlResponse::ProxyPolicyCurrent(p)
+            }
+            ControlMessage::UpdateProxyPolicy(np) => {
+                store.write_proxy_policy(np.clone())?;
+                ControlResponse::ProxyPolicyUpdated(np)
+            }
+            ControlMessage::QueryAudit { since, limit } => {
+                let entries = store.read_audit(since, limit.min(AUDIT_PAGE_MAX))?;
+                ControlResponse::AuditLog(entries)
+            }
+            ControlMessage::Batch(_) => return Err(anyhow::anyhow!("nested Batch")),
         };
-        Ok((msg, res))
+        Ok(res)
+    }
+    #[cfg(feature = "std")]
+    fn open_seed(&self, params: &SeedParams) -> anyhow::Result<[u8; 32]> {
+        open_seed(params, self.6.as_ref().unwrap_or(&self.0))
+    }
+    #[cfg(not(feature = "std"))]
+    fn open_seed(&self, _params: &SeedParams) -> anyhow::Result<[u8; 32]> {
+        Err(anyhow::anyhow!("InjectSeed needs the std feature"))
+    }
+}
+
+// everything a msg can change, put back if it or its audit entry fails
+struct Snapshot {
+    nonce: u64,
+    config: Option<Config>,
+    seed: Option<[u8; 32]>,
+    policy: Option<Policy>,
+    velocity: Option<Velocity>,
+    filter: Option<PolicyFilter>,
+    allowlist: Vec<String>,
+    ota_version: u64,
+    admins: Vec<Admin>,
+    nonce_windows: Vec<NonceWindowState>,
+    proxy_policy: ProxyPolicy,
+}
+
+impl Snapshot {
+    fn take(store: &dyn ControlPersist) -> Self {
+        Self {
+            nonce: store.read_nonce().unwrap_or(0),
+            config: store.read_config().ok(),
+            seed: store.read_seed().ok(),
+            policy: store.read_policy().ok(),
+            velocity: store.read_velocity().ok(),
+            filter: store.read_policy_filter().ok(),
+            allowlist: store.read_allowlist().unwrap_or_default(),
+            ota_version: store.read_ota_version().unwrap_or(0),
+            admins: store.read_admins().unwrap_or_default(),
+            nonce_windows: store.read_nonce_windows().unwrap_or_default(),
+            proxy_policy: store.read_proxy_policy().unwrap_or_default(),
+        }
+    }
+    // keeps going after a failed write, and returns the first error
+    fn restore(self, store: &mut dyn ControlPersist) -> anyhow::Result<()> {
+        let results = vec![
+            store.set_nonce(self.nonce),
+            match self.config {
+                Some(c) => store.write_config(c),
+                None => store.remove_config(),
+            },
+            match self.seed {
+                Some(s) => store.write_seed(s),
+                None => store.remove_seed(),
+            },
+            match self.policy {
+                Some(p) => store.write_policy(p),
+                None => store.remove_policy(),
+            },
+            // there is no velocity until the signer writes one
+            match self.velocity {
+                Some(v) => store.write_velocity(v),
+                None => Ok(()),
+            },
+            match self.filter {
+                Some(f) => store.write_policy_filter(f),
+                None => store.remove_policy_filter(),
+            },
+            store.write_allowlist(self.allowlist),
+            store.write_ota_version(self.ota_version),
+            store.write_admins(self.admins),
+            store.write_nonce_windows(self.nonce_windows),
+            store.write_proxy_policy(self.proxy_policy),
+        ];
+        results.into_iter().collect()
     }
 }
 
+// each nonce can be used once, and may arrive out of order within the window
+// keep sanity and don't increment by more than 20 at a time
+fn check_nonce(nonce: u64, window: &mut nonce::NonceWindow) -> anyhow::Result<()> {
+    if nonce > window.top() + 20 {
+        return Err(anyhow::Error::msg(GlyphError::with_code(
+            ErrorCode::BadNonce,
+            "invalid nonce",
+        )));
+    }
+    window
+        .accept(nonce)
+        .map_err(|e| anyhow::Error::msg(GlyphError::with_code(ErrorCode::BadNonce, &e.to_string())))
+}
+
+// A delegate can only send the kinds in its certificate, each msg of a Batch
+// included. Expiry was checked against the clock, so there has to be one
+fn check_delegation(d: &Delegation, msg: &ControlMessage) -> anyhow::Result<()> {
+    if now_secs() == 0 {
+        return Err(anyhow::anyhow!("no clock to check the delegation expiry"));
+    }
+    let mut kinds = vec![msg.kind()];
+    if let ControlMessage::Batch(msgs) = msg {
+        kinds.extend(msgs.iter().map(|m| m.kind()));
+    }
+    match kinds.into_iter().find(|k| !d.allows(k)) {
+        Some(k) => Err(anyhow::Error::msg(GlyphError::with_code(
+            ErrorCode::Control,
+            &format!("delegate can't send {}", k),
+        ))),
+        None => Ok(()),
+    }
+}
+
+// a nonce stored without a window (older firmware) marks everything below it as used
+fn load_window(
+    windows: &[NonceWindowState],
+    pubkey: &str,
+    last: u64,
+    size: u32,
+) -> nonce::NonceWindow {
+    match windows.iter().find(|w| w.pubkey == pubkey) {
+        Some(w) if w.top >= last => nonce::NonceWindow::from_parts(w.top, size, w.seen.clone()),
+        _ => nonce::NonceWindow::from_nonce(last, size),
+    }
+}
+
+pub fn check_allowlist(entries: &[String]) -> anyhow::Result<()> {
+    for entry in entries {
+        check_allowlist_entry(entry).map_err(anyhow::Error::msg)?;
+    }
+    Ok(())
+}
+
 pub fn build_control_msg(
     msg: ControlMessage,
     nonce: u64,
@@ -141,12 +712,180 @@ pub fn build_control_msg(
     Ok(ret)
 }
 
+// build_control_msg for a Schnorr key, which Controller matches by its x-only key
+#[cfg(feature = "std")]
+pub fn build_control_msg_schnorr(
+    msg: ControlMessage,
+    nonce: u64,
+    secret: &SecretKey,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buff = ByteBuf::new();
+    serialize_controlmessage(&mut buff, &msg)?;
+    let signed = nonce::build_msg_schnorr(buff.as_slice(), secret, nonce)?;
+    let pk = sphinx_auther::schnorr::x_only(secret);
+    Ok(nonce::wrap_msg_schnorr(&signed, &pk))
+}
+
+// a msg signed by a delegate, with the certificate chain from the root admin
+pub fn build_delegated_control_msg(
+    msg: ControlMessage,
+    nonce: u64,
+    delegate: &SecretKey,
+    chain: &[Certificate],
+) -> anyhow::Result<Vec<u8>> {
+    let mut buff = ByteBuf::new();
+    serialize_controlmessage(&mut buff, &msg)?;
+    nonce::build_msg_delegated(buff.as_slice(), delegate, nonce, chain)
+}
+
 pub fn parse_control_response(input: &[u8]) -> anyhow::Result<ControlResponse> {
     let mut bytes = Bytes::new(input);
     let res: ControlResponse = deserialize_controlresponse(&mut bytes)?;
     Ok(res)
 }
 
+// sha256 of every AuditEntry field but the hash and sig
+pub fn audit_hash(entry: &AuditEntry) -> [u8; 32] {
+    let mut engine = Sha256Hash::engine();
+    engine.input(&entry.seq.to_be_bytes());
+    engine.input(&entry.nonce.to_be_bytes());
+    for s in [&entry.kind, &entry.admin, &entry.prev_hash] {
+        engine.input(&(s.len() as u64).to_be_bytes());
+        engine.input(s.as_bytes());
+    }
+    engine.input(&entry.timestamp.to_be_bytes());
+    Sha256Hash::from_engine(engine).into_inner()
+}
+
+// check the hashes, signatures and links of consecutive entries from QueryAudit.
+// signer is the control pubkey of the signer that wrote the log
+pub fn verify_audit_log(entries: &[AuditEntry], signer: &PublicKey) -> anyhow::Result<()> {
+    let secp = Secp256k1::verification_only();
+    let mut prev: Option<&AuditEntry> = None;
+    for e in entries {
+        let hash = audit_hash(e);
+        if hex::encode(hash) != e.hash {
+            return Err(anyhow::anyhow!("audit entry {} hash mismatch", e.seq));
+        }
+        let sig = hex::decode(&e.sig).map_err(anyhow::Error::msg)?;
+        let sig = Signature::from_compact(&sig).map_err(anyhow::Error::msg)?;
+        let m = Message::from_slice(&hash).map_err(anyhow::Error::msg)?;
+        secp.verify_ecdsa(&m, &sig, signer)
+            .map_err(|_| anyhow::anyhow!("audit entry {} bad signature", e.seq))?;
+        let linked = match prev {
+            Some(p) => e.seq == p.seq + 1 && e.prev_hash == p.hash,
+            None => e.seq > 0 || e.prev_hash == hex::encode([0u8; 32]),
+        };
+        if !linked {
+            return Err(anyhow::anyhow!("audit entry {} breaks the chain", e.seq));
+        }
+        prev = Some(e);
+    }
+    Ok(())
+}
+
+// until set_node_info, the features glyph was built with
+fn default_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "no-std") {
+        features.push("no-std".to_string());
+    }
+    features
+}
+
+#[cfg(feature = "std")]
+fn now_secs() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+#[cfg(not(feature = "std"))]
+fn now_secs() -> u64 {
+    0
+}
+
+// hex sha256, for SeedParams.commitment
+pub fn seed_commitment(seed: &[u8; 32]) -> String {
+    hex::encode(Sha256Hash::hash(seed).into_inner())
+}
+
+// encrypt a seed to the signer's provisioning pubkey, with a one-time key
+#[cfg(feature = "std")]
+pub fn seal_seed(seed: &[u8; 32], signer: &PublicKey) -> anyhow::Result<SeedParams> {
+    use sphinx_auther::secp256k1::rand::{rngs::OsRng, RngCore};
+    let (sk, pk) = Secp256k1::new().generate_keypair(&mut OsRng);
+    let secret = ecdh::derive_shared_secret(signer, &sk).secret_bytes();
+    let mut n = [0u8; chacha::NONCE_LEN];
+    OsRng.fill_bytes(&mut n);
+    let cipher = chacha::encrypt(*seed, secret, n)?;
+    Ok(SeedParams {
+        pubkey: hex::encode(pk.serialize()),
+        cipher: hex::encode(cipher),
+        commitment: seed_commitment(seed),
+    })
+}
+
+// decrypt the seed, and check it against the commitment
+#[cfg(feature = "std")]
+pub fn open_seed(params: &SeedParams, sk: &SecretKey) -> anyhow::Result<[u8; 32]> {
+    let pk = hex::decode(&params.pubkey).map_err(anyhow::Error::msg)?;
+    let pk = PublicKey::from_slice(&pk).map_err(anyhow::Error::msg)?;
+    let cipher = hex::decode(&params.cipher).map_err(anyhow::Error::msg)?;
+    let cipher: [u8; chacha::PAYLOAD_LEN] = std::convert::TryInto::try_into(cipher)
+        .map_err(|_| anyhow::anyhow!("seed cipher must be {} bytes", chacha::PAYLOAD_LEN))?;
+    let secret = ecdh::derive_shared_secret(&pk, sk).secret_bytes();
+    let seed = chacha::decrypt(cipher, secret)?;
+    if seed_commitment(&seed) != params.commitment.to_lowercase() {
+        return Err(anyhow::anyhow!("seed does not match the commitment"));
+    }
+    Ok(seed)
+}
+
+// who signed a control msg, and its nonce, to bind the response to.
+// For a delegated msg that is the delegate
+pub fn control_msg_signer(input: &[u8]) -> anyhow::Result<(PublicKey, u64)> {
+    let (_, nonce, signer, delegation) =
+        nonce::parse_msg_recover_delegated(input, now_secs() as u32)?;
+    Ok((delegation.map_or(signer, |d| d.delegate), nonce))
+}
+
+// the hash a signed response is bound to
+fn request_hash(request: &[u8]) -> [u8; 32] {
+    Sha256Hash::hash(request).into_inner()
+}
+
+// check a response from build_signed_response against the request it answers
+pub fn verify_control_response(
+    input: &[u8],
+    signer: &PublicKey,
+    request: &[u8],
+) -> anyhow::Result<ControlResponse> {
+    let (payload, _) = nonce::parse_msg_no_nonce(input, signer)?;
+    if payload.len() < 32 {
+        return Err(anyhow::anyhow!("response too short"));
+    }
+    let (msg, hash) = payload.split_at(payload.len() - 32);
+    if hash != request_hash(request) {
+        return Err(anyhow::anyhow!("response to another request"));
+    }
+    parse_control_response(msg)
+}
+
+// decrypt and check a response from build_encrypted_response
+#[cfg(feature = "std")]
+pub fn decrypt_control_response(
+    input: &[u8],
+    signer: &PublicKey,
+    admin: &SecretKey,
+    request: &[u8],
+) -> anyhow::Result<ControlResponse> {
+    let secret = ecdh::derive_shared_secret(signer, admin).secret_bytes();
+    let signed = chacha::decrypt_vec(input, secret)?;
+    verify_control_response(&signed, signer, request)
+}
+
 pub fn parse_control_response_to_json(input: &[u8]) -> anyhow::Result<String> {
     let res = parse_control_response(input)?;
     serde_json::to_string(&res).map_err(anyhow::Error::msg)
@@ -165,6 +904,12 @@ pub enum FlashKey {
     Nonce,
     Policy,
     Velocity,
+    PolicyFilter,
+    Allowlist,
+    OtaVersion,
+    Admins,
+    NonceWindows,
+    ProxyPolicy,
 }
 impl FlashKey {
     pub fn as_str(&self) -> &'static str {
@@ -175,6 +920,12 @@ impl FlashKey {
             FlashKey::Nonce => "nonce",
             FlashKey::Policy => "policy",
             FlashKey::Velocity => "velocity",
+            FlashKey::PolicyFilter => "filter",
+            FlashKey::Allowlist => "allowlist",
+            FlashKey::OtaVersion => "otaversion",
+            FlashKey::Admins => "admins",
+            FlashKey::NonceWindows => "noncewindows",
+            FlashKey::ProxyPolicy => "proxypolicy",
         }
     }
 }
@@ -195,6 +946,27 @@ pub trait ControlPersist: Sync + Send {
     fn remove_policy(&mut self) -> Result<()>;
     fn read_velocity(&self) -> Result<Velocity>;
     fn write_velocity(&mut self, v: Velocity) -> Result<()>;
+    fn read_policy_filter(&self) -> Result<PolicyFilter>;
+    fn write_policy_filter(&mut self, f: PolicyFilter) -> Result<()>;
+    fn remove_policy_filter(&mut self) -> Result<()>;
+    fn read_allowlist(&self) -> Result<Vec<String>>;
+    fn write_allowlist(&mut self, al: Vec<String>) -> Result<()>;
+    // the version of the installed firmware, set after a successful OTA
+    fn read_ota_version(&self) -> Result<u64>;
+    fn write_ota_version(&mut self, version: u64) -> Result<()>;
+    // admins other than the controller key, with their last nonce
+    fn read_admins(&self) -> Result<Vec<Admin>>;
+    fn write_admins(&mut self, admins: Vec<Admin>) -> Result<()>;
+    // the replay window of each signer, on top of its nonce
+    fn read_nonce_windows(&self) -> Result<Vec<NonceWindowState>>;
+    fn write_nonce_windows(&mut self, windows: Vec<NonceWindowState>) -> Result<()>;
+    fn read_proxy_policy(&self) -> Result<ProxyPolicy>;
+    fn write_proxy_policy(&mut self, p: ProxyPolicy) -> Result<()>;
+    // the audit log is append only, and survives ResetAll
+    fn append_audit(&mut self, entry: AuditEntry) -> Result<()>;
+    // entries with seq >= since, oldest first
+    fn read_audit(&self, since: u64, limit: u32) -> Result<Vec<AuditEntry>>;
+    fn read_last_audit(&self) -> Result<Option<AuditEntry>>;
 }
 
 pub struct DummyPersister;
@@ -215,8 +987,9 @@ impl ControlPersist for DummyPersister {
     fn remove_config(&mut self) -> Result<()> {
         Ok(())
     }
+    // nothing is kept, so there is never a seed to read
     fn read_seed(&self) -> Result<[u8; 32]> {
-        Ok([0; 32])
+        Err(anyhow::anyhow!("no seed"))
     }
     fn write_seed(&mut self, _s: [u8; 32]) -> Result<()> {
         Ok(())
@@ -245,6 +1018,54 @@ impl ControlPersist for DummyPersister {
     fn write_velocity(&mut self, _s: Velocity) -> Result<()> {
         Ok(())
     }
+    fn read_policy_filter(&self) -> Result<PolicyFilter> {
+        Ok(Default::default())
+    }
+    fn write_policy_filter(&mut self, _f: PolicyFilter) -> Result<()> {
+        Ok(())
+    }
+    fn remove_policy_filter(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn read_allowlist(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+    fn write_allowlist(&mut self, _al: Vec<String>) -> Result<()> {
+        Ok(())
+    }
+    fn read_ota_version(&self) -> Result<u64> {
+        Ok(0u64)
+    }
+    fn write_ota_version(&mut self, _version: u64) -> Result<()> {
+        Ok(())
+    }
+    fn read_admins(&self) -> Result<Vec<Admin>> {
+        Ok(Vec::new())
+    }
+    fn write_admins(&mut self, _admins: Vec<Admin>) -> Result<()> {
+        Ok(())
+    }
+    fn read_nonce_windows(&self) -> Result<Vec<NonceWindowState>> {
+        Ok(Vec::new())
+    }
+    fn write_nonce_windows(&mut self, _windows: Vec<NonceWindowState>) -> Result<()> {
+        Ok(())
+    }
+    fn read_proxy_policy(&self) -> Result<ProxyPolicy> {
+        Ok(Default::default())
+    }
+    fn write_proxy_policy(&mut self, _p: ProxyPolicy) -> Result<()> {
+        Ok(())
+    }
+    fn append_audit(&mut self, _entry: AuditEntry) -> Result<()> {
+        Ok(())
+    }
+    fn read_audit(&self, _since: u64, _limit: u32) -> Result<Vec<AuditEntry>> {
+        Ok(Vec::new())
+    }
+    fn read_last_audit(&self) -> Result<Option<AuditEntry>> {
+        Ok(None)
+    }
 }
 
 // cargo test controller::tests::test_ctrl_json -- --exact
@@ -265,6 +1086,12 @@ mod tests {
 
         let msg = "{\"UpdatePolicy\":{\"htlc_limit_msat\":0, \"interval\":\"hourly\", \"msat_per_interval\":10}}";
         control_msg_from_json(msg.as_bytes()).expect("UpdatePolicy failed");
+
+        let msg = "{\"UpdatePolicy\":{\"htlc_limit_msat\":0, \"interval\":{\"custom\":600}, \"msat_per_interval\":10}}";
+        control_msg_from_json(msg.as_bytes()).expect("UpdatePolicy custom failed");
+
+        let msg = "{\"UpdatePolicyFilter\":[{\"tag\":\"policy-\", \"action\":\"warn\", \"is_prefix\":true}]}";
+        control_msg_from_json(msg.as_bytes()).expect("UpdatePolicyFilter failed");
     }
 
     #[test]
@@ -296,4 +1123,795 @@ mod tests {
         let built_res = ctrlr.build_response(res).expect("failed to build res");
         let _r = parse_control_response(&built_res).expect("cant parse res");
     }
+
+    #[test]
+    fn test_controller_config() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let mut ctrlr = Controller::new(secret_key, public_key, 0);
+        let config = Config {
+            broker: "broker.sphinx.chat:8883".to_string(),
+            ssid: "sphinx".to_string(),
+            pass: "password".to_string(),
+            network: "bitcoin".to_string(),
+        };
+        let m = build_control_msg(ControlMessage::UpdateConfig(config.clone()), 1, &secret_key)
+            .expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("failed to handle");
+        assert_eq!(res, ControlResponse::ConfigUpdated(config.redacted()));
+        if let ControlResponse::ConfigUpdated(c) = res {
+            assert_eq!(c.pass, REDACTED);
+            assert_eq!(c.broker, config.broker);
+        }
+        let m = build_control_msg(ControlMessage::QueryInfo, 2, &secret_key).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("failed to handle");
+        match res {
+            ControlResponse::InfoCurrent(info) => {
+                assert_eq!(info.nonce, 2);
+                assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+                assert_eq!(info.ota_version, None);
+                assert_eq!(info.node_id, "");
+            }
+            _ => panic!("wrong response"),
+        }
+        // the node key, not the controller key
+        let (_, node_pk) = secp.generate_keypair(&mut OsRng);
+        let features = vec!["lowmemory".to_string(), "no-native".to_string()];
+        ctrlr.set_node_info(node_pk, features.clone());
+        let m = build_control_msg(ControlMessage::QueryInfo, 3, &secret_key).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("failed to handle");
+        match res {
+            ControlResponse::InfoCurrent(info) => {
+                assert_eq!(info.node_id, hex::encode(node_pk.serialize()));
+                assert_eq!(info.features, features);
+            }
+            _ => panic!("wrong response"),
+        }
+        let bad = Config::default();
+        let m = build_control_msg(ControlMessage::UpdateConfig(bad), 4, &secret_key).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+    }
+
+    #[test]
+    fn test_controller_bad_nonce() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let mut ctrlr = Controller::new(secret_key, public_key, 5);
+        let m = build_control_msg(ControlMessage::QueryPolicy, 5, &secret_key).expect("FAIL");
+        let e = ctrlr.handle(&m).expect_err("replayed nonce");
+        let code = GlyphError::from_anyhow(&e, ErrorCode::Control).code;
+        assert_eq!(code, ErrorCode::BadNonce);
+    }
+
+    // only keeps the nonce, policy, allowlist, admins, nonce windows, seed and audit log
+    #[cfg(test)]
+    #[derive(Default)]
+    struct MemPersister {
+        nonce: u64,
+        policy: Option<crate::types::Policy>,
+        allowlist: Vec<String>,
+        admins: Vec<crate::types::Admin>,
+        windows: Vec<crate::types::NonceWindowState>,
+        seed: Option<[u8; 32]>,
+        audit: Vec<crate::types::AuditEntry>,
+        ota_version: u64,
+        // append_audit fails while this is set
+        fail_audit: bool,
+    }
+
+    #[cfg(test)]
+    impl crate::control::ControlPersist for MemPersister {
+        fn read_nonce(&self) -> anyhow::Result<u64> {
+            Ok(self.nonce)
+        }
+        fn set_nonce(&mut self, nonce: u64) -> anyhow::Result<()> {
+            self.nonce = nonce;
+            Ok(())
+        }
+        fn read_config(&self) -> anyhow::Result<crate::types::Config> {
+            Ok(Default::default())
+        }
+        fn write_config(&mut self, _conf: crate::types::Config) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn remove_config(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn read_seed(&self) -> anyhow::Result<[u8; 32]> {
+            self.seed.ok_or_else(|| anyhow::anyhow!("no seed"))
+        }
+        fn write_seed(&mut self, s: [u8; 32]) -> anyhow::Result<()> {
+            self.seed = Some(s);
+            Ok(())
+        }
+        fn remove_seed(&mut self) -> anyhow::Result<()> {
+            self.seed = None;
+            Ok(())
+        }
+        fn read_id(&self) -> anyhow::Result<[u8; 16]> {
+            Ok(Default::default())
+        }
+        fn write_id(&mut self, _id: [u8; 16]) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn read_policy(&self) -> anyhow::Result<crate::types::Policy> {
+            self.policy
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no policy"))
+        }
+        fn write_policy(&mut self, s: crate::types::Policy) -> anyhow::Result<()> {
+            self.policy = Some(s);
+            Ok(())
+        }
+        fn remove_policy(&mut self) -> anyhow::Result<()> {
+            self.policy = None;
+            Ok(())
+        }
+        fn read_velocity(&self) -> anyhow::Result<crate::types::Velocity> {
+            Ok(Default::default())
+        }
+        fn write_velocity(&mut self, _s: crate::types::Velocity) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn read_policy_filter(&self) -> anyhow::Result<crate::types::PolicyFilter> {
+            Ok(Default::default())
+        }
+        fn write_policy_filter(&mut self, _f: crate::types::PolicyFilter) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn remove_policy_filter(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn read_allowlist(&self) -> anyhow::Result<Vec<String>> {
+            Ok(self.allowlist.clone())
+        }
+        fn write_allowlist(&mut self, al: Vec<String>) -> anyhow::Result<()> {
+            self.allowlist = al;
+            Ok(())
+        }
+        fn read_ota_version(&self) -> anyhow::Result<u64> {
+            Ok(self.ota_version)
+        }
+        fn write_ota_version(&mut self, version: u64) -> anyhow::Result<()> {
+            self.ota_version = version;
+            Ok(())
+        }
+        fn read_admins(&self) -> anyhow::Result<Vec<crate::types::Admin>> {
+            Ok(self.admins.clone())
+        }
+        fn write_admins(&mut self, admins: Vec<crate::types::Admin>) -> anyhow::Result<()> {
+            self.admins = admins;
+            Ok(())
+        }
+        fn read_nonce_windows(&self) -> anyhow::Result<Vec<crate::types::NonceWindowState>> {
+            Ok(self.windows.clone())
+        }
+        fn write_nonce_windows(
+            &mut self,
+            windows: Vec<crate::types::NonceWindowState>,
+        ) -> anyhow::Result<()> {
+            self.windows = windows;
+            Ok(())
+        }
+        fn read_proxy_policy(&self) -> anyhow::Result<crate::types::ProxyPolicy> {
+            Ok(Default::default())
+        }
+        fn write_proxy_policy(&mut self, _p: crate::types::ProxyPolicy) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn append_audit(&mut self, entry: crate::types::AuditEntry) -> anyhow::Result<()> {
+            if self.fail_audit {
+                return Err(anyhow::anyhow!("audit store full"));
+            }
+            self.audit.push(entry);
+            Ok(())
+        }
+        fn read_audit(
+            &self,
+            since: u64,
+            limit: u32,
+        ) -> anyhow::Result<Vec<crate::types::AuditEntry>> {
+            let entries = self.audit.iter().filter(|e| e.seq >= since);
+            Ok(entries.take(limit as usize).cloned().collect())
+        }
+        fn read_last_audit(&self) -> anyhow::Result<Option<crate::types::AuditEntry>> {
+            Ok(self.audit.last().cloned())
+        }
+    }
+
+    #[test]
+    fn test_controller_schnorr() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (owner_sk, owner_pk) = secp.generate_keypair(&mut OsRng);
+        let (auditor_sk, auditor_pk) = secp.generate_keypair(&mut OsRng);
+        let (stranger_sk, _) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(owner_sk, owner_pk, store);
+        // the owner signs with Schnorr
+        let policy = Policy {
+            htlc_limit_msat: 1_000,
+            ..Default::default()
+        };
+        let msg = ControlMessage::UpdatePolicy(policy.clone());
+        let m = build_control_msg_schnorr(msg, 1, &owner_sk).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("schnorr owner msg");
+        assert_eq!(res, ControlResponse::PolicyUpdated(policy));
+        // it shares the owner nonce with ECDSA msgs
+        assert_eq!(ctrlr.nonce(), 1);
+        let m = build_control_msg(ControlMessage::QueryAll, 1, &owner_sk).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        // an admin with the role of its key
+        let auditor = Admin {
+            pubkey: hex::encode(auditor_pk.serialize()),
+            role: Role::Auditor,
+            nonce: 0,
+        };
+        let m = build_control_msg(ControlMessage::AddAdmin(auditor), 2, &owner_sk).expect("FAIL");
+        ctrlr.handle(&m).expect("failed to add admin");
+        let m = build_control_msg_schnorr(ControlMessage::QueryAll, 1, &auditor_sk).expect("FAIL");
+        ctrlr.handle(&m).expect("schnorr auditor QueryAll");
+        let m = build_control_msg_schnorr(ControlMessage::ResetAll, 2, &auditor_sk).expect("FAIL");
+        let e = ctrlr.handle(&m).expect_err("auditor can't write");
+        let code = GlyphError::from_anyhow(&e, ErrorCode::Unidentified).code;
+        assert_eq!(code, ErrorCode::Control);
+        // unknown keys are rejected
+        let m = build_control_msg_schnorr(ControlMessage::QueryAll, 1, &stranger_sk).expect("FAIL");
+        let e = ctrlr.handle(&m).expect_err("stranger msg");
+        assert!(e.to_string().starts_with("unknown admin"));
+        // and so is a msg that names the owner key but is signed by another
+        let mut buff = ByteBuf::new();
+        serialize_controlmessage(&mut buff, &ControlMessage::QueryAll).expect("FAIL");
+        let signed = nonce::build_msg_schnorr(buff.as_slice(), &stranger_sk, 3).expect("FAIL");
+        let owner_x = sphinx_auther::schnorr::x_only(&owner_sk);
+        let m = nonce::wrap_msg_schnorr(&signed, &owner_x);
+        assert!(ctrlr.handle(&m).is_err());
+        // an ECDSA sig with the Schnorr marker is not read as either
+        let ecdsa = build_control_msg(ControlMessage::QueryAll, 3, &owner_sk).expect("FAIL");
+        assert!(ctrlr
+            .handle(&nonce::wrap_msg_schnorr(&ecdsa, &owner_x))
+            .is_err());
+        assert_eq!(ctrlr.nonce(), 2);
+    }
+
+    #[test]
+    fn test_controller_admins() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (owner_sk, owner_pk) = secp.generate_keypair(&mut OsRng);
+        let (auditor_sk, auditor_pk) = secp.generate_keypair(&mut OsRng);
+        let (stranger_sk, _) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(owner_sk, owner_pk, store);
+        let auditor = Admin {
+            pubkey: hex::encode(auditor_pk.serialize()),
+            role: Role::Auditor,
+            nonce: 0,
+        };
+        let m = build_control_msg(ControlMessage::AddAdmin(auditor.clone()), 1, &owner_sk)
+            .expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("failed to add admin");
+        assert_eq!(res, ControlResponse::AdminAdded(auditor.clone()));
+        // the auditor can read
+        let m = build_control_msg(ControlMessage::QueryAll, 1, &auditor_sk).expect("FAIL");
+        ctrlr.handle(&m).expect("auditor QueryAll");
+        let m = build_control_msg(ControlMessage::Nonce, 0, &auditor_sk).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("auditor Nonce");
+        assert_eq!(res, ControlResponse::Nonce(1));
+        // but not write
+        for msg in [
+            ControlMessage::ResetAll,
+            ControlMessage::UpdatePolicy(Policy::default()),
+        ] {
+            let m = build_control_msg(msg, 2, &auditor_sk).expect("FAIL");
+            let e = ctrlr.handle(&m).expect_err("auditor can't write");
+            let code = GlyphError::from_anyhow(&e, ErrorCode::Unidentified).code;
+            assert_eq!(code, ErrorCode::Control);
+        }
+        // unknown keys are rejected
+        let m = build_control_msg(ControlMessage::QueryAll, 1, &stranger_sk).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        // before their msg is parsed: a Batch of 0x7fffffff msgs, and an
+        // unknown variant
+        let bomb = [
+            0x81, 0xa5, b'B', b'a', b't', b'c', b'h', 0xdd, 0x7f, 0xff, 0xff, 0xff,
+        ];
+        let bogus = [0xa5, b'B', b'o', b'g', b'u', b's'];
+        for raw in [&bomb[..], &bogus[..]] {
+            let m = nonce::build_msg(raw, &stranger_sk, 1).expect("FAIL");
+            let e = ctrlr.handle(&m).expect_err("stranger msg");
+            assert!(e.to_string().starts_with("unknown admin"));
+        }
+        // an unknown variant is an error, not a panic
+        let m = nonce::build_msg(&bogus, &owner_sk, 2).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        // the owner nonce is separate
+        assert_eq!(ctrlr.nonce(), 1);
+        let m = build_control_msg(ControlMessage::ListAdmins, 2, &owner_sk).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("list admins");
+        let mut listed = auditor.clone();
+        listed.nonce = 1;
+        assert_eq!(res, ControlResponse::AdminList(vec![listed.clone()]));
+        let m = build_control_msg(
+            ControlMessage::RemoveAdmin(auditor.pubkey.clone()),
+            3,
+            &owner_sk,
+        )
+        .expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("remove admin");
+        assert_eq!(res, ControlResponse::AdminRemoved(auditor.pubkey.clone()));
+        let m = build_control_msg(ControlMessage::QueryAll, 2, &auditor_sk).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        // added again, with a nonce of 0 in the msg: it picks up where it
+        // left off, so its old msgs can't be replayed
+        let m = build_control_msg(ControlMessage::AddAdmin(auditor.clone()), 4, &owner_sk)
+            .expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("add admin again");
+        assert_eq!(res, ControlResponse::AdminAdded(listed));
+        let m = build_control_msg(ControlMessage::QueryAll, 1, &auditor_sk).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        let m = build_control_msg(ControlMessage::QueryAll, 2, &auditor_sk).expect("FAIL");
+        ctrlr.handle(&m).expect("auditor QueryAll after re-add");
+    }
+
+    #[test]
+    fn test_controller_batch() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        let policy = Policy {
+            msat_per_interval: 21_000,
+            interval: Interval::Daily,
+            htlc_limit_msat: 1_000,
+        };
+        let addy = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string();
+        let batch = ControlMessage::Batch(vec![
+            ControlMessage::UpdatePolicy(policy.clone()),
+            ControlMessage::AllowlistAdd(vec![addy.clone()]),
+            ControlMessage::Nonce,
+        ]);
+        let m = build_control_msg(batch, 1, &secret_key).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("failed to handle batch");
+        assert_eq!(
+            res,
+            ControlResponse::Batch(vec![
+                ControlResponse::PolicyUpdated(policy.clone()),
+                ControlResponse::AllowlistUpdated(vec![addy.clone()]),
+                ControlResponse::Nonce(1),
+            ])
+        );
+        // one nonce for the whole batch
+        assert_eq!(ctrlr.nonce(), 1);
+        assert_eq!(store.lock().unwrap().nonce, 1);
+        // the last msg fails, so the policy and allowlist are rolled back
+        let batch = ControlMessage::Batch(vec![
+            ControlMessage::UpdatePolicy(Policy::default()),
+            ControlMessage::UpdateAllowlist(vec![]),
+            ControlMessage::ResetAll,
+            ControlMessage::AllowlistAdd(vec!["garbage".to_string()]),
+        ]);
+        let m = build_control_msg(batch, 2, &secret_key).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        {
+            let s = store.lock().unwrap();
+            assert_eq!(s.policy, Some(policy.clone()));
+            assert_eq!(s.allowlist, vec![addy.clone()]);
+            // the nonce is still consumed
+            assert_eq!(s.nonce, 2);
+        }
+        let m = build_control_msg(ControlMessage::Batch(vec![]), 3, &secret_key).expect("FAIL");
+        let (_, res) = ctrlr.handle(&m).expect("empty batch");
+        assert_eq!(res, ControlResponse::Batch(vec![]));
+        let nested = ControlMessage::Batch(vec![ControlMessage::Batch(vec![])]);
+        let m = build_control_msg(nested, 4, &secret_key).expect("FAIL");
+        assert!(ctrlr.handle(&m).is_err());
+        // refused by the check, nothing is applied
+        let batch = ControlMessage::Batch(vec![ControlMessage::UpdatePolicy(Policy::default())]);
+        let m = build_control_msg(batch, 5, &secret_key).expect("FAIL");
+        let refuse = |_: &ControlMessage| Err(anyhow::anyhow!("refused"));
+        assert!(ctrlr.handle_checked(&m, refuse).is_err());
+        let s = store.lock().unwrap();
+        assert_eq!(s.policy, Some(policy));
+        assert_eq!(s.nonce, 5);
+    }
+
+    #[test]
+    fn test_controller_nonce_window() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        let msg = |n| build_control_msg(ControlMessage::QueryPolicy, n, &secret_key).unwrap();
+        // reordered msgs are accepted once each
+        ctrlr.handle(&msg(3)).expect("3");
+        ctrlr.handle(&msg(1)).expect("1");
+        ctrlr.handle(&msg(2)).expect("2");
+        assert_eq!(ctrlr.nonce(), 3);
+        let e = ctrlr.handle(&msg(2)).expect_err("replayed");
+        let code = GlyphError::from_anyhow(&e, ErrorCode::Control).code;
+        assert_eq!(code, ErrorCode::BadNonce);
+        assert!(ctrlr.handle(&msg(24)).is_err());
+        // the window survives a restart
+        ctrlr.handle(&msg(5)).expect("5");
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store);
+        assert!(ctrlr.handle(&msg(5)).is_err());
+        ctrlr.handle(&msg(4)).expect("4");
+        // a smaller window
+        ctrlr.set_nonce_window(2);
+        ctrlr.handle(&msg(10)).expect("10");
+        ctrlr.handle(&msg(9)).expect("9");
+        assert!(ctrlr.handle(&msg(8)).is_err());
+    }
+
+    #[test]
+    fn test_signed_response() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+        use sphinx_auther::secp256k1::Secp256k1;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (admin_sk, admin_pk) = secp.generate_keypair(&mut OsRng);
+        let (other_sk, other_pk) = secp.generate_keypair(&mut OsRng);
+        let ctrlr = Controller::new(secret_key, public_key, 0);
+        let req = build_control_msg(ControlMessage::QueryPolicy, 4, &admin_sk).expect("FAIL");
+        let (signer, nonce) = control_msg_signer(&req).expect("signer");
+        assert_eq!(signer, admin_pk);
+        assert_eq!(nonce, 4);
+        let res = ControlResponse::PolicyCurrent(Policy::default());
+        let signed = ctrlr
+            .build_signed_response(res.clone(), &req)
+            .expect("sign");
+        assert_eq!(
+            verify_control_response(&signed, &public_key, &req).expect("verify"),
+            res
+        );
+        // older clients can still read it
+        assert_eq!(parse_control_response(&signed).expect("parse"), res);
+        // swapped with the response to another request
+        let req5 = build_control_msg(ControlMessage::QueryPolicy, 5, &admin_sk).expect("FAIL");
+        assert!(verify_control_response(&signed, &public_key, &req5).is_err());
+        // or to another admin's request with the same nonce
+        let other_req = build_control_msg(ControlMessage::QueryPolicy, 4, &other_sk).expect("FAIL");
+        assert!(verify_control_response(&signed, &public_key, &other_req).is_err());
+        // forged by another key
+        let forger = Controller::new(other_sk, other_pk, 0);
+        let forged = forger
+            .build_signed_response(res.clone(), &req)
+            .expect("sign");
+        assert!(verify_control_response(&forged, &public_key, &req).is_err());
+        let mut tampered = signed.clone();
+        tampered[1] ^= 1;
+        assert!(verify_control_response(&tampered, &public_key, &req).is_err());
+
+        let enc = ctrlr
+            .build_encrypted_response(res.clone(), &req)
+            .expect("encrypt");
+        let dec = decrypt_control_response(&enc, &public_key, &admin_sk, &req).expect("decrypt");
+        assert_eq!(dec, res);
+        assert!(decrypt_control_response(&enc, &public_key, &other_sk, &req).is_err());
+        assert!(decrypt_control_response(&enc, &public_key, &admin_sk, &req5).is_err());
+    }
+
+    #[test]
+    fn test_controller_inject_seed() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (prov_sk, prov_pk) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        assert_eq!(ctrlr.provisioning_pubkey(), public_key);
+        ctrlr.set_provisioning_key(prov_sk);
+        assert_eq!(ctrlr.provisioning_pubkey(), prov_pk);
+        let seed = [7u8; 32];
+        // sealed to the wrong key
+        let wrong = seal_seed(&seed, &public_key).expect("seal");
+        let m = build_control_msg(ControlMessage::InjectSeed(wrong), 1, &secret_key).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        // a commitment to another seed
+        let mut bad = seal_seed(&seed, &prov_pk).expect("seal");
+        bad.commitment = seed_commitment(&[8u8; 32]);
+        let m = build_control_msg(ControlMessage::InjectSeed(bad), 2, &secret_key).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        assert_eq!(store.lock().unwrap().seed, None);
+
+        let params = seal_seed(&seed, &ctrlr.provisioning_pubkey()).expect("seal");
+        let commitment = params.commitment.clone();
+        let m =
+            build_control_msg(ControlMessage::InjectSeed(params.clone()), 3, &secret_key).unwrap();
+        let (_, res) = ctrlr.handle(&m).expect("inject");
+        assert_eq!(res, ControlResponse::SeedInjected(commitment));
+        assert_eq!(store.lock().unwrap().seed, Some(seed));
+        // an installed seed is not overwritten
+        let other = seal_seed(&[9u8; 32], &prov_pk).expect("seal");
+        let m =
+            build_control_msg(ControlMessage::InjectSeed(other.clone()), 4, &secret_key).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        let batch = ControlMessage::Batch(vec![
+            ControlMessage::ResetKeys,
+            ControlMessage::InjectSeed(other),
+        ]);
+        let m = build_control_msg(batch, 5, &secret_key).unwrap();
+        ctrlr.handle(&m).expect("replace seed");
+        assert_eq!(store.lock().unwrap().seed, Some([9u8; 32]));
+
+        // the DummyPersister has no seed installed
+        let mut dummy = Controller::new(secret_key, public_key, 0);
+        let params = seal_seed(&seed, &public_key).expect("seal");
+        let m = build_control_msg(ControlMessage::InjectSeed(params), 1, &secret_key).unwrap();
+        dummy.handle(&m).expect("inject with dummy");
+    }
+
+    #[test]
+    fn test_controller_audit() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (auditor_sk, auditor_pk) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        let auditor = Admin {
+            pubkey: hex::encode(auditor_pk.serialize()),
+            role: Role::Auditor,
+            nonce: 0,
+        };
+        let batch = ControlMessage::Batch(vec![
+            ControlMessage::UpdatePolicy(Policy::default()),
+            ControlMessage::AllowlistAdd(vec!["bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".into()]),
+        ]);
+        for (i, msg) in [ControlMessage::AddAdmin(auditor), batch]
+            .iter()
+            .enumerate()
+        {
+            let m = build_control_msg(msg.clone(), i as u64 + 1, &secret_key).unwrap();
+            ctrlr.handle(&m).expect("handle");
+        }
+        // Nonce and rejected msgs are not recorded
+        let m = build_control_msg(ControlMessage::Nonce, 0, &secret_key).unwrap();
+        ctrlr.handle(&m).expect("nonce");
+        let m = build_control_msg(ControlMessage::ResetAll, 1, &auditor_sk).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        let query = ControlMessage::QueryAudit {
+            since: 0,
+            limit: 10,
+        };
+        let m = build_control_msg(query, 1, &auditor_sk).unwrap();
+        let (_, res) = ctrlr.handle(&m).expect("query audit");
+        let entries = match res {
+            ControlResponse::AuditLog(entries) => entries,
+            _ => panic!("wrong response"),
+        };
+        let kinds: Vec<&str> = entries.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, ["AddAdmin", "UpdatePolicy", "AllowlistAdd"]);
+        assert_eq!(entries[1].nonce, 2);
+        assert_eq!(entries[0].admin, hex::encode(public_key.serialize()));
+        verify_audit_log(&entries, &public_key).expect("valid log");
+        // the query itself is recorded too
+        let log = store.lock().unwrap().audit.clone();
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[3].admin, hex::encode(auditor_pk.serialize()));
+        verify_audit_log(&log[2..], &public_key).expect("valid page");
+        // tampering is caught
+        let mut edited = log.clone();
+        edited[1].kind = "QueryAll".to_string();
+        assert!(verify_audit_log(&edited, &public_key).is_err());
+        let mut dropped = log.clone();
+        dropped.remove(1);
+        assert!(verify_audit_log(&dropped, &public_key).is_err());
+        assert!(verify_audit_log(&log, &auditor_pk).is_err());
+        // a change that can't be logged is rolled back, nonce excepted
+        store.lock().unwrap().fail_audit = true;
+        let policy = Policy {
+            msat_per_interval: 1,
+            ..Default::default()
+        };
+        let m = build_control_msg(ControlMessage::UpdatePolicy(policy), 3, &secret_key).unwrap();
+        let e = ctrlr.handle(&m).expect_err("audit fails");
+        assert!(e.to_string().contains("audit log failed"));
+        let s = store.lock().unwrap();
+        assert_eq!(s.policy, Some(Policy::default()));
+        assert_eq!(s.audit.len(), 4);
+        assert_eq!(s.nonce, 3);
+    }
+
+    #[test]
+    fn test_controller_delegation() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (delegate_sk, delegate_pk) = secp.generate_keypair(&mut OsRng);
+        let (other_sk, _) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        let now = now_secs() as u32;
+        let cert = Certificate::new(delegate_pk, now + 60, &["QueryAll"])
+            .sign(&secret_key)
+            .unwrap();
+        let chain = std::slice::from_ref(&cert);
+        let m =
+            build_delegated_control_msg(ControlMessage::QueryAll, 1, &delegate_sk, chain).unwrap();
+        assert_eq!(control_msg_signer(&m).unwrap(), (delegate_pk, 1));
+        ctrlr.handle(&m).expect("delegated query");
+        // replayed
+        assert!(ctrlr.handle(&m).is_err());
+        // the delegate has its own nonce, the owner's is untouched
+        assert_eq!(ctrlr.2, 0);
+        let windows = store.lock().unwrap().read_nonce_windows().unwrap();
+        assert_eq!(windows[0].pubkey, hex::encode(delegate_pk.serialize()));
+        // not in the certificate, even inside a Batch
+        let m = build_delegated_control_msg(
+            ControlMessage::UpdatePolicy(Policy::default()),
+            2,
+            &delegate_sk,
+            chain,
+        )
+        .unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        let batch = ControlMessage::Batch(vec![ControlMessage::ResetAll]);
+        let m = build_delegated_control_msg(batch, 3, &delegate_sk, chain).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        // expired, or not issued by an admin
+        let expired = Certificate::new(delegate_pk, now - 1, &["QueryAll"])
+            .sign(&secret_key)
+            .unwrap();
+        let m = build_delegated_control_msg(ControlMessage::QueryAll, 4, &delegate_sk, &[expired])
+            .unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        let stranger = Certificate::new(delegate_pk, now + 60, &["QueryAll"])
+            .sign(&other_sk)
+            .unwrap();
+        let m = build_delegated_control_msg(ControlMessage::QueryAll, 5, &delegate_sk, &[stranger])
+            .unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        // signed by someone other than the delegate
+        let m = build_delegated_control_msg(ControlMessage::QueryAll, 6, &other_sk, chain).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        // parse_msg_delegated checks the chain and kinds too, the plain
+        // parsers only take the controller key
+        let m =
+            build_delegated_control_msg(ControlMessage::QueryAll, 7, &delegate_sk, chain).unwrap();
+        let (msg, nonce, d) = ctrlr.parse_msg_delegated(&m).unwrap();
+        assert_eq!((msg, nonce), (ControlMessage::QueryAll, 7));
+        assert_eq!(d.unwrap().delegate, delegate_pk);
+        assert!(ctrlr.parse_msg_no_nonce(&m).is_err());
+        assert!(ctrlr.parse_msg(&m).is_err());
+        let m =
+            build_delegated_control_msg(ControlMessage::ResetAll, 8, &delegate_sk, chain).unwrap();
+        assert!(ctrlr.parse_msg_delegated(&m).is_err());
+        // a delegate can't sign a response for the controller key
+        let mut buff = ByteBuf::new();
+        serialize_controlresponse(&mut buff, &ControlResponse::Nonce(1)).unwrap();
+        let req = build_control_msg(ControlMessage::Nonce, 1, &secret_key).unwrap();
+        let mut payload = buff.into_vec();
+        payload.extend_from_slice(&request_hash(&req));
+        let fake = nonce::build_msg_delegated(&payload, &delegate_sk, 1, chain).unwrap();
+        assert!(verify_control_response(&fake, &public_key, &req).is_err());
+    }
+
+    #[test]
+    fn test_controller_delegation_replay() {
+        use crate::control::*;
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (admin_sk, admin_pk) = secp.generate_keypair(&mut OsRng);
+        let (delegate_sk, delegate_pk) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store);
+        let now = now_secs() as u32;
+        let admin = Admin {
+            pubkey: hex::encode(admin_pk.serialize()),
+            role: Role::Operator,
+            nonce: 0,
+        };
+        let add = ControlMessage::AddAdmin(admin.clone());
+        ctrlr
+            .handle(&build_control_msg(add.clone(), 1, &secret_key).unwrap())
+            .expect("add admin");
+        // certified by the admin, then the admin is removed and added again
+        let cert = Certificate::new(delegate_pk, now + 60, &["QueryAll"])
+            .sign(&admin_sk)
+            .unwrap();
+        let chain = std::slice::from_ref(&cert);
+        let m =
+            build_delegated_control_msg(ControlMessage::QueryAll, 1, &delegate_sk, chain).unwrap();
+        ctrlr.handle(&m).expect("delegated query");
+        let remove = ControlMessage::RemoveAdmin(admin.pubkey.clone());
+        ctrlr
+            .handle(&build_control_msg(remove, 2, &secret_key).unwrap())
+            .expect("remove admin");
+        assert!(ctrlr.handle(&m).is_err());
+        ctrlr
+            .handle(&build_control_msg(add, 3, &secret_key).unwrap())
+            .expect("add admin again");
+        assert!(ctrlr.handle(&m).is_err());
+        // certified by the owner, then everything is reset
+        let cert = Certificate::new(delegate_pk, now + 60, &["QueryAll"])
+            .sign(&secret_key)
+            .unwrap();
+        let m = build_delegated_control_msg(ControlMessage::QueryAll, 2, &delegate_sk, &[cert])
+            .unwrap();
+        ctrlr.handle(&m).expect("owner delegated query");
+        ctrlr
+            .handle(&build_control_msg(ControlMessage::ResetAll, 4, &secret_key).unwrap())
+            .expect("reset all");
+        assert!(ctrlr.handle(&m).is_err());
+    }
+
+    #[test]
+    fn test_controller_ota() {
+        use crate::control::*;
+        use crate::ota::{p2pkh_address, sign_message};
+        use sphinx_auther::secp256k1::rand::rngs::OsRng;
+
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let (release_sk, release_pk) = secp.generate_keypair(&mut OsRng);
+        let store = Arc::new(Mutex::new(MemPersister::default()));
+        let mut ctrlr = Controller::new_with_persister(secret_key, public_key, store.clone());
+        let sha256_hash =
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string();
+        let ota = |version| {
+            ControlMessage::Ota(OtaParams {
+                version,
+                url: "https://www.sphinx.chat/signer/ota".to_string(),
+                sha256_hash: sha256_hash.clone(),
+                message_sig: sign_message(&sha256_hash, &release_sk).unwrap(),
+            })
+        };
+        // refused without a verifier
+        let m = build_control_msg(ota(2), 1, &secret_key).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        ctrlr.set_ota_verifier(OtaVerifier::new(&p2pkh_address(&release_pk, false)));
+        let m = build_control_msg(ota(2), 2, &secret_key).unwrap();
+        ctrlr.handle(&m).expect("ota");
+        assert_eq!(store.lock().unwrap().ota_version, 2);
+        // the same build, or an older one, can't be installed again
+        for (version, nonce) in [(2, 3), (1, 4)] {
+            let m = build_control_msg(ota(version), nonce, &secret_key).unwrap();
+            assert!(ctrlr.handle(&m).is_err());
+        }
+        let m = build_control_msg(ota(3), 5, &secret_key).unwrap();
+        ctrlr.handle(&m).expect("newer ota");
+        assert_eq!(store.lock().unwrap().ota_version, 3);
+        // rolled back with the rest of a failed batch
+        let batch = ControlMessage::Batch(vec![
+            ota(4),
+            ControlMessage::AllowlistAdd(vec!["garbage".to_string()]),
+        ]);
+        let m = build_control_msg(batch, 6, &secret_key).unwrap();
+        assert!(ctrlr.handle(&m).is_err());
+        assert_eq!(store.lock().unwrap().ota_version, 3);
+    }
 }
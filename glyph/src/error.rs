@@ -1,10 +1,20 @@
+// The code is the last byte of an error on the wire,
+// so existing values must never be renumbered
 #[repr(u8)]
-#[derive(PartialEq, Debug, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(PartialEq, Debug, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum ErrorCode {
     Unidentified = 0,
     Vls = 1,
     Control = 2,
     Proxy = 3,
+    // the VLS message sequence did not match, the signer restarts
+    BadSequence = 4,
+    // the LSS server hmac did not match
+    HmacMismatch = 5,
+    PutConflict = 6,
+    PolicyViolation = 7,
+    VelocityExceeded = 8,
+    BadNonce = 9,
 }
 
 impl From<u8> for ErrorCode {
@@ -12,12 +22,26 @@ impl From<u8> for ErrorCode {
         match item {
             0 => ErrorCode::Unidentified,
             1 => ErrorCode::Vls,
+            2 => ErrorCode::Control,
+            3 => ErrorCode::Proxy,
+            4 => ErrorCode::BadSequence,
+            5 => ErrorCode::HmacMismatch,
+            6 => ErrorCode::PutConflict,
+            7 => ErrorCode::PolicyViolation,
+            8 => ErrorCode::VelocityExceeded,
+            9 => ErrorCode::BadNonce,
             _ => ErrorCode::Unidentified,
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+impl From<ErrorCode> for u8 {
+    fn from(item: ErrorCode) -> Self {
+        item as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Error {
     pub code: ErrorCode,
     pub message: String,
@@ -30,6 +54,19 @@ impl Error {
             message: message.to_string(),
         }
     }
+    pub fn with_code(code: ErrorCode, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+    // an Error inside an anyhow::Error keeps its code, otherwise the fallback is used
+    pub fn from_anyhow(e: &anyhow::Error, fallback: ErrorCode) -> Self {
+        match e.downcast_ref::<Error>() {
+            Some(err) => err.clone(),
+            None => Self::with_code(fallback, &e.to_string()),
+        }
+    }
     pub fn from_slice(slice: &[u8]) -> Self {
         let mut v = slice.to_vec();
         let code = v.pop().unwrap_or_default();
@@ -40,11 +77,17 @@ impl Error {
     }
     pub fn to_vec(&self) -> Vec<u8> {
         let mut v = self.message.as_bytes().to_vec();
-        v.extend_from_slice(&[self.code.clone() as u8]);
+        v.extend_from_slice(&[self.code as u8]);
         v
     }
 }
 
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::error::*;
@@ -58,4 +101,33 @@ mod tests {
         assert_eq!(e.message, e2.message);
         // println!("=> e2.message: {}", e2.message);
     }
+
+    #[test]
+    fn test_error_codes() {
+        for code in 0u8..10 {
+            let e = Error::new(code, "failed");
+            let e2 = Error::from_slice(&e.to_vec());
+            assert_eq!(e2.code as u8, code);
+            assert_eq!(e, e2);
+        }
+        assert_eq!(ErrorCode::from(10), ErrorCode::Unidentified);
+        assert_eq!(ErrorCode::from(u8::MAX), ErrorCode::Unidentified);
+        // stable wire encoding
+        let e = Error::with_code(ErrorCode::BadNonce, "invalid nonce");
+        assert_eq!(e.to_vec(), b"invalid nonce\x09".to_vec());
+        assert_eq!(u8::from(ErrorCode::PolicyViolation), 7);
+        assert_eq!(Error::from_slice(&[]).code, ErrorCode::Unidentified);
+    }
+
+    #[test]
+    fn test_error_from_anyhow() {
+        let e = anyhow::Error::msg(Error::with_code(ErrorCode::HmacMismatch, "bad hmac"));
+        let e2 = Error::from_anyhow(&e, ErrorCode::Vls);
+        assert_eq!(e2.code, ErrorCode::HmacMismatch);
+        assert_eq!(e2.message, "bad hmac");
+        let e = anyhow::anyhow!("something else");
+        let e2 = Error::from_anyhow(&e, ErrorCode::Vls);
+        assert_eq!(e2.code, ErrorCode::Vls);
+        assert_eq!(e2.message, "something else");
+    }
 }
@@ -1,5 +1,7 @@
 pub mod control;
 pub mod error;
+pub mod ota;
+pub mod proxy;
 pub mod ser;
 pub mod topics;
 pub mod types;
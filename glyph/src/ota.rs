@@ -0,0 +1,174 @@
+use crate::types::OtaParams;
+use anyhow::{anyhow, Error as AnyErr, Result};
+#[cfg(feature = "std")]
+use sphinx_auther::bip322;
+use sphinx_auther::secp256k1::hashes::sha256::{self, Hash as Sha256Hash};
+#[cfg(feature = "std")]
+use sphinx_auther::secp256k1::hashes::sha256d::Hash as Sha256dHash;
+use sphinx_auther::secp256k1::hashes::{Hash, HashEngine};
+#[cfg(feature = "std")]
+use sphinx_auther::secp256k1::SecretKey;
+
+#[cfg(feature = "std")]
+pub use bip322::p2pkh_address;
+
+// Checks an OtaParams against the release-signing address.
+// A P2PKH address takes a bitcoin::sign_message signature,
+// P2WPKH and P2TR take BIP-322 ones
+pub struct OtaVerifier {
+    address: String,
+}
+
+impl OtaVerifier {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+    // current_version is the version of the running firmware. Only a newer
+    // version is accepted, so the same build can't be installed again
+    pub fn verify(&self, params: &OtaParams, current_version: u64) -> Result<()> {
+        if params.version <= current_version {
+            return Err(anyhow!(
+                "ota version {} is not newer than {}",
+                params.version,
+                current_version
+            ));
+        }
+        let hash = hex::decode(&params.sha256_hash).map_err(AnyErr::msg)?;
+        if hash.len() != 32 {
+            return Err(anyhow!("ota sha256_hash must be 32 bytes"));
+        }
+        verify_signed_by_address(&params.sha256_hash, &params.message_sig, &self.address)
+    }
+}
+
+// Streams the downloaded binary through sha256
+#[derive(Default)]
+pub struct OtaHasher(sha256::HashEngine);
+
+impl OtaHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.input(chunk);
+    }
+    pub fn finish(self) -> [u8; 32] {
+        Sha256Hash::from_engine(self.0).into_inner()
+    }
+    // sha256_hash is hex encoded, as in OtaParams
+    pub fn verify(self, sha256_hash: &str) -> Result<()> {
+        let expected = hex::decode(sha256_hash).map_err(AnyErr::msg)?;
+        if self.finish()[..] != expected[..] {
+            return Err(anyhow!("ota binary hash mismatch"));
+        }
+        Ok(())
+    }
+}
+
+// bitcoin::sign_message::signed_msg_hash
+#[cfg(feature = "std")]
+pub fn signed_msg_hash(msg: &str) -> Sha256dHash {
+    bip322::signed_msg_hash(msg.as_bytes())
+}
+
+// base64 MessageSignature, as produced by `bitcoin-cli signmessage`
+#[cfg(feature = "std")]
+pub fn sign_message(msg: &str, secret_key: &SecretKey) -> Result<String> {
+    bip322::sign_legacy(msg.as_bytes(), secret_key)
+}
+
+// legacy for P2PKH, BIP-322 simple or full for P2WPKH and P2TR
+#[cfg(feature = "std")]
+pub fn verify_signed_by_address(msg: &str, sig: &str, address: &str) -> Result<()> {
+    bip322::verify(msg.as_bytes(), sig, address)
+}
+#[cfg(not(feature = "std"))]
+pub fn verify_signed_by_address(_msg: &str, _sig: &str, _address: &str) -> Result<()> {
+    Err(anyhow!("OTA verification needs the std feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ota::*;
+    use sphinx_auther::bip322::{p2tr_address, p2wpkh_address, SigFormat};
+    use sphinx_auther::secp256k1::{PublicKey, Secp256k1};
+
+    fn keys() -> (SecretKey, PublicKey) {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        (sk, PublicKey::from_secret_key(&secp, &sk))
+    }
+
+    fn params(version: u64, sk: &SecretKey) -> OtaParams {
+        let sha256_hash =
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string();
+        let message_sig = sign_message(&sha256_hash, sk).unwrap();
+        OtaParams {
+            version,
+            url: "https://www.sphinx.chat/signer/ota".to_string(),
+            sha256_hash,
+            message_sig,
+        }
+    }
+
+    #[test]
+    fn test_ota_verify() {
+        let (sk, pk) = keys();
+        let verifier = OtaVerifier::new(&p2pkh_address(&pk, false));
+        let p = params(2, &sk);
+        verifier.verify(&p, 1).expect("valid ota");
+        assert!(verifier.verify(&p, 2).is_err());
+        assert!(verifier.verify(&p, 3).is_err());
+        // signed by someone else
+        let other = SecretKey::from_slice(&[2; 32]).unwrap();
+        assert!(verifier.verify(&params(2, &other), 1).is_err());
+        // hash swapped after signing
+        let mut p2 = p.clone();
+        p2.sha256_hash = "00".repeat(32);
+        assert!(verifier.verify(&p2, 1).is_err());
+        let testnet = OtaVerifier::new(&p2pkh_address(&pk, true));
+        testnet.verify(&p, 1).expect("testnet address");
+    }
+
+    #[test]
+    fn test_ota_verify_bip322() {
+        let (sk, pk) = keys();
+        let mut p = params(2, &sk);
+        for address in [
+            p2wpkh_address(&pk, false),
+            p2tr_address(&pk, false).unwrap(),
+        ] {
+            let verifier = OtaVerifier::new(&address);
+            // a legacy sig is only good for P2PKH
+            assert!(verifier.verify(&p, 1).is_err());
+            p.message_sig =
+                bip322::sign(p.sha256_hash.as_bytes(), &sk, &address, SigFormat::Simple).unwrap();
+            verifier.verify(&p, 1).expect("valid ota");
+            p.message_sig = sign_message(&p.sha256_hash, &sk).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ota_hasher() {
+        let mut hasher = OtaHasher::new();
+        hasher.update(b"");
+        hasher
+            .verify("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            .expect("empty hash");
+        let data = [7u8; 1000];
+        let mut hasher = OtaHasher::new();
+        for chunk in data.chunks(64) {
+            hasher.update(chunk);
+        }
+        let expected = Sha256Hash::hash(&data).into_inner();
+        assert_eq!(hasher.finish(), expected);
+        let mut hasher = OtaHasher::new();
+        hasher.update(&data[..999]);
+        assert!(hasher.verify(&hex::encode(expected)).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::{Error as GlyphError, ErrorCode};
+use crate::ser::*;
+use crate::types::{ProxyPolicy, ProxyRequest, ProxyResponse, TokenRequest};
+use anyhow::Result;
+use sphinx_auther::secp256k1::SecretKey;
+use sphinx_auther::sign_message;
+use sphinx_auther::token::{is_token_msg, Token, TokenV2};
+
+// the longest a TokenV2 from the PROXY topic can live, a day
+pub const MAX_TOKEN_TTL: u32 = 24 * 60 * 60;
+
+// Answer a PROXY msg from the broker, if the policy allows it.
+// sk is the node key, now is unix seconds for auth tokens.
+// Errors carry ErrorCode::Proxy, for the ERROR topic
+pub fn handle_proxy(
+    input: &[u8],
+    sk: &SecretKey,
+    policy: &ProxyPolicy,
+    now: u64,
+) -> Result<(ProxyRequest, Vec<u8>)> {
+    let req = parse_proxy_request(input).map_err(|e| proxy_error(&e.to_string()))?;
+    if !policy.allows(&req) {
+        return Err(proxy_error(&format!(
+            "{} is not allowed by the proxy policy",
+            req.kind()
+        )));
+    }
+    let res = match &req {
+        // the String keeps this from signing a control msg: msgpack maps
+        // and variant names never start with a valid utf8 byte
+        ProxyRequest::SignMessage(msg) => {
+            if is_token_msg(msg.as_bytes()) {
+                return Err(proxy_error("message looks like an auth token"));
+            }
+            let sig = sign_message(msg.as_bytes(), sk).map_err(|e| proxy_error(&e.to_string()))?;
+            ProxyResponse::MessageSigned(hex::encode(sig))
+        }
+        ProxyRequest::AuthToken => {
+            let token = Token::new_with_time(now as u32)
+                .sign_to_base64(sk)
+                .map_err(|e| proxy_error(&e.to_string()))?;
+            ProxyResponse::AuthToken(token)
+        }
+        ProxyRequest::AuthTokenV2(tr) => {
+            let token = token_v2(tr, input, now as u32)?
+                .sign_to_base64(sk)
+                .map_err(|e| proxy_error(&e.to_string()))?;
+            ProxyResponse::AuthToken(token)
+        }
+    };
+    Ok((req, build_proxy_response(&res)?))
+}
+
+fn token_v2(tr: &TokenRequest, input: &[u8], now: u32) -> Result<TokenV2> {
+    if tr.audience.is_empty() {
+        return Err(proxy_error("a v2 token needs an audience"));
+    }
+    if tr.ttl == 0 || tr.ttl > MAX_TOKEN_TTL {
+        return Err(proxy_error(&format!(
+            "token ttl must be 1 to {} secs",
+            MAX_TOKEN_TTL
+        )));
+    }
+    let scopes: Vec<&str> = tr.scopes.iter().map(|s| s.as_str()).collect();
+    let expiry = now.saturating_add(tr.ttl);
+    Ok(new_token_v2(&tr.audience, now, expiry, input).with_scopes(&scopes))
+}
+
+#[cfg(feature = "std")]
+fn new_token_v2(audience: &str, issued: u32, expiry: u32, _input: &[u8]) -> TokenV2 {
+    TokenV2::new_with_time(audience, issued, expiry)
+}
+// without an OS rng the jti is a hash of the request and the time
+#[cfg(not(feature = "std"))]
+fn new_token_v2(audience: &str, issued: u32, expiry: u32, input: &[u8]) -> TokenV2 {
+    use sphinx_auther::secp256k1::hashes::sha256::Hash as Sha256Hash;
+    use sphinx_auther::secp256k1::hashes::{Hash, HashEngine};
+    let mut engine = Sha256Hash::engine();
+    engine.input(input);
+    engine.input(&issued.to_be_bytes());
+    let hash = Sha256Hash::from_engine(engine).into_inner();
+    let mut jti = [0u8; 16];
+    jti.copy_from_slice(&hash[..16]);
+    TokenV2::new_with_jti(audience, issued, expiry, jti)
+}
+
+pub fn build_proxy_request(req: &ProxyRequest) -> Result<Vec<u8>> {
+    let mut buff = ByteBuf::new();
+    serialize_proxyrequest(&mut buff, req)?;
+    Ok(buff.into_vec())
+}
+
+pub fn parse_proxy_request(input: &[u8]) -> Result<ProxyRequest> {
+    let mut bytes = Bytes::new(input);
+    deserialize_proxyrequest(&mut bytes)
+}
+
+pub fn build_proxy_response(res: &ProxyResponse) -> Result<Vec<u8>> {
+    let mut buff = ByteBuf::new();
+    serialize_proxyresponse(&mut buff, res)?;
+    Ok(buff.into_vec())
+}
+
+pub fn parse_proxy_response(input: &[u8]) -> Result<ProxyResponse> {
+    let mut bytes = Bytes::new(input);
+    deserialize_proxyresponse(&mut bytes)
+}
+
+fn proxy_error(msg: &str) -> anyhow::Error {
+    anyhow::Error::msg(GlyphError::with_code(ErrorCode::Proxy, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy::*;
+    use sphinx_auther::secp256k1::{PublicKey, Secp256k1};
+
+    #[test]
+    fn test_handle_proxy() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[3; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+        let sign = build_proxy_request(&ProxyRequest::SignMessage("hello".into())).unwrap();
+        let token = build_proxy_request(&ProxyRequest::AuthToken).unwrap();
+        // nothing is allowed by default
+        let e = handle_proxy(&sign, &sk, &ProxyPolicy::default(), 0).unwrap_err();
+        assert_eq!(
+            GlyphError::from_anyhow(&e, ErrorCode::Vls).code,
+            ErrorCode::Proxy
+        );
+        assert!(handle_proxy(&token, &sk, &ProxyPolicy::default(), 0).is_err());
+        let policy = ProxyPolicy {
+            sign_message: true,
+            auth_token: true,
+            audiences: Vec::new(),
+        };
+        let (req, res) = handle_proxy(&sign, &sk, &policy, 0).unwrap();
+        assert_eq!(req.kind(), "SignMessage");
+        let sig = match parse_proxy_response(&res).unwrap() {
+            ProxyResponse::MessageSigned(sig) => hex::decode(sig).unwrap(),
+            _ => panic!("wrong response"),
+        };
+        let sig: [u8; 65] = std::convert::TryInto::try_into(&sig[..]).unwrap();
+        sphinx_auther::verify_message(b"hello", &sig, &pk).unwrap();
+        let (_, res) = handle_proxy(&token, &sk, &policy, 1_700_000_000).unwrap();
+        let token = match parse_proxy_response(&res).unwrap() {
+            ProxyResponse::AuthToken(t) => Token::from_base64(&t).unwrap(),
+            _ => panic!("wrong response"),
+        };
+        token.verify(&pk).unwrap();
+        // a 4 byte message would be a token
+        let ts = String::from_utf8(1_701_143_909u32.to_be_bytes().to_vec()).unwrap();
+        let fake = build_proxy_request(&ProxyRequest::SignMessage(ts)).unwrap();
+        let only_sign = ProxyPolicy {
+            sign_message: true,
+            auth_token: false,
+            audiences: Vec::new(),
+        };
+        assert!(handle_proxy(&fake, &sk, &only_sign, 0).is_err());
+        // so would anything starting with the v2 token version
+        let v2 = "\u{2}aaaabbbbcccccccccccccccc\u{6}broker\u{0}".to_string();
+        let fake = build_proxy_request(&ProxyRequest::SignMessage(v2)).unwrap();
+        assert!(handle_proxy(&fake, &sk, &only_sign, 0).is_err());
+        assert!(handle_proxy(&[0xc1], &sk, &policy, 0).is_err());
+    }
+
+    #[test]
+    fn test_handle_proxy_token_v2() {
+        use sphinx_auther::token::{AUDIENCE_MEDIA, AUDIENCE_TRIBES};
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[3; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+        let policy = ProxyPolicy {
+            sign_message: false,
+            auth_token: true,
+            audiences: vec![AUDIENCE_TRIBES.to_string()],
+        };
+        let now = 1_700_000_000;
+        let tr = TokenRequest {
+            audience: AUDIENCE_TRIBES.to_string(),
+            scopes: vec!["read".to_string()],
+            ttl: 3600,
+        };
+        let req = build_proxy_request(&ProxyRequest::AuthTokenV2(tr.clone())).unwrap();
+        let (req, res) = handle_proxy(&req, &sk, &policy, now as u64).unwrap();
+        assert_eq!(req.kind(), "AuthTokenV2");
+        let token = match parse_proxy_response(&res).unwrap() {
+            ProxyResponse::AuthToken(t) => TokenV2::from_base64(&t).unwrap(),
+            _ => panic!("wrong response"),
+        };
+        token.verify(&pk).unwrap();
+        assert_eq!(token.audience, AUDIENCE_TRIBES);
+        assert!(token.has_scope("read"));
+        assert_eq!((token.issued, token.expiry), (now, now + 3600));
+        token.check(AUDIENCE_TRIBES, now + 60).unwrap();
+        assert!(token.check(AUDIENCE_MEDIA, now + 60).is_err());
+        assert!(token.check(AUDIENCE_TRIBES, now + 3601).is_err());
+        // it needs an audience and a ttl up to a day
+        for bad in [
+            TokenRequest {
+                audience: String::new(),
+                ..tr.clone()
+            },
+            TokenRequest {
+                ttl: 0,
+                ..tr.clone()
+            },
+            TokenRequest {
+                ttl: MAX_TOKEN_TTL + 1,
+                ..tr.clone()
+            },
+        ] {
+            let req = build_proxy_request(&ProxyRequest::AuthTokenV2(bad)).unwrap();
+            let e = handle_proxy(&req, &sk, &policy, now as u64).unwrap_err();
+            assert_eq!(
+                GlyphError::from_anyhow(&e, ErrorCode::Vls).code,
+                ErrorCode::Proxy
+            );
+        }
+        // only for the audiences in the policy
+        let media = TokenRequest {
+            audience: AUDIENCE_MEDIA.to_string(),
+            ..tr.clone()
+        };
+        let req = build_proxy_request(&ProxyRequest::AuthTokenV2(media)).unwrap();
+        let e = handle_proxy(&req, &sk, &policy, now as u64).unwrap_err();
+        assert_eq!(
+            GlyphError::from_anyhow(&e, ErrorCode::Vls).code,
+            ErrorCode::Proxy
+        );
+        // and not at all without auth_token
+        let off = ProxyPolicy {
+            auth_token: false,
+            ..policy
+        };
+        let req = build_proxy_request(&ProxyRequest::AuthTokenV2(tr)).unwrap();
+        assert!(handle_proxy(&req, &sk, &off, now as u64).is_err());
+    }
+}
@@ -25,11 +25,88 @@ pub fn serialize_controlmessage(buff: &mut ByteBuf, object: &ControlMessage) ->
             serialize_otaparams(buff, Some("Ota"), ota_params)?;
         }
         ControlMessage::QueryAll => rmp::serialize_variant(buff, "QueryAll")?,
+        ControlMessage::QueryPolicyFilter => rmp::serialize_variant(buff, "QueryPolicyFilter")?,
+        ControlMessage::UpdatePolicyFilter(filter) => {
+            rmp::serialize_map_len(buff, 1)?;
+            serialize_policy_filter(buff, Some("UpdatePolicyFilter"), filter)?;
+        }
+        ControlMessage::ListPending => rmp::serialize_variant(buff, "ListPending")?,
+        ControlMessage::SetTime(secs) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_uint(buff, Some("SetTime"), *secs)?;
+        }
+        ControlMessage::ForceTime(secs) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_uint(buff, Some("ForceTime"), *secs)?;
+        }
+        ControlMessage::QueryProxyPolicy => rmp::serialize_variant(buff, "QueryProxyPolicy")?,
+        ControlMessage::UpdateProxyPolicy(policy) => {
+            rmp::serialize_map_len(buff, 1)?;
+            serialize_proxy_policy(buff, Some("UpdateProxyPolicy"), policy)?;
+        }
+        ControlMessage::QueryAudit { since, limit } => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_field_name(buff, Some("QueryAudit"))?;
+            rmp::serialize_map_len(buff, 2)?;
+            rmp::serialize_uint(buff, Some("since"), *since)?;
+            rmp::serialize_uint(buff, Some("limit"), *limit as u64)?;
+        }
+        ControlMessage::ApprovePending(id) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_uint(buff, Some("ApprovePending"), *id)?;
+        }
+        ControlMessage::DeclinePending(id) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_uint(buff, Some("DeclinePending"), *id)?;
+        }
+        ControlMessage::AllowlistAdd(list) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_string_vec(buff, Some("AllowlistAdd"), list)?;
+        }
+        ControlMessage::AllowlistRemove(list) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_string_vec(buff, Some("AllowlistRemove"), list)?;
+        }
+        ControlMessage::QueryConfig => rmp::serialize_variant(buff, "QueryConfig")?,
+        ControlMessage::QueryInfo => rmp::serialize_variant(buff, "QueryInfo")?,
+        ControlMessage::ListAdmins => rmp::serialize_variant(buff, "ListAdmins")?,
+        ControlMessage::AddAdmin(admin) => {
+            rmp::serialize_map_len(buff, 1)?;
+            serialize_admin(buff, Some("AddAdmin"), admin)?;
+        }
+        ControlMessage::RemoveAdmin(pubkey) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_string(buff, Some("RemoveAdmin"), pubkey)?;
+        }
+        ControlMessage::InjectSeed(params) => {
+            rmp::serialize_map_len(buff, 1)?;
+            serialize_seedparams(buff, Some("InjectSeed"), params)?;
+        }
+        ControlMessage::Batch(msgs) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_field_name(buff, Some("Batch"))?;
+            rmp::serialize_array_len(buff, msgs.len() as u32)?;
+            for m in msgs {
+                serialize_controlmessage(buff, m)?;
+            }
+        }
+        ControlMessage::UpdateConfig(config) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_field_name(buff, Some("UpdateConfig"))?;
+            serialize_config(buff, config)?;
+        }
     }
     Ok(())
 }
 
+// the most msgs in one Batch. A Batch can't hold another one
+pub const MAX_BATCH: u32 = 64;
+
 pub fn deserialize_controlmessage(bytes: &mut Bytes) -> Result<ControlMessage> {
+    deserialize_controlmessage_in(bytes, false)
+}
+
+fn deserialize_controlmessage_in(bytes: &mut Bytes, in_batch: bool) -> Result<ControlMessage> {
     let peek = rmp::peek_byte(bytes, None)?;
     if peek == 0x81 {
         rmp::deserialize_map_len(bytes, 1)?;
@@ -56,11 +133,101 @@ pub fn deserialize_controlmessage(bytes: &mut Bytes) -> Result<ControlMessage> {
             ControlMessage::Ota(params)
         }
         "QueryAll" => ControlMessage::QueryAll,
-        _ => panic!("could not deserialize controlmessage"),
+        "QueryPolicyFilter" => ControlMessage::QueryPolicyFilter,
+        "UpdatePolicyFilter" => {
+            let filter = deserialize_policy_filter(bytes, None)?;
+            ControlMessage::UpdatePolicyFilter(filter)
+        }
+        "ListPending" => ControlMessage::ListPending,
+        "ApprovePending" => {
+            let id = rmp::deserialize_uint(bytes, None)?;
+            ControlMessage::ApprovePending(id)
+        }
+        "SetTime" => {
+            let secs = rmp::deserialize_uint(bytes, None)?;
+            ControlMessage::SetTime(secs)
+        }
+        "ForceTime" => {
+            let secs = rmp::deserialize_uint(bytes, None)?;
+            ControlMessage::ForceTime(secs)
+        }
+        "QueryProxyPolicy" => ControlMessage::QueryProxyPolicy,
+        "UpdateProxyPolicy" => {
+            let policy = deserialize_proxy_policy(bytes, None)?;
+            ControlMessage::UpdateProxyPolicy(policy)
+        }
+        "QueryAudit" => {
+            rmp::deserialize_map_len(bytes, 2)?;
+            let since = rmp::deserialize_uint(bytes, Some("since"))?;
+            let limit = rmp::deserialize_uint(bytes, Some("limit"))?;
+            if limit > u32::MAX as u64 {
+                return Err(anyhow!("QueryAudit limit too big"));
+            }
+            ControlMessage::QueryAudit {
+                since,
+                limit: limit as u32,
+            }
+        }
+        "DeclinePending" => {
+            let id = rmp::deserialize_uint(bytes, None)?;
+            ControlMessage::DeclinePending(id)
+        }
+        "AllowlistAdd" => {
+            let list = rmp::deserialize_string_vec(bytes, None)?;
+            ControlMessage::AllowlistAdd(list)
+        }
+        "AllowlistRemove" => {
+            let list = rmp::deserialize_string_vec(bytes, None)?;
+            ControlMessage::AllowlistRemove(list)
+        }
+        "QueryConfig" => ControlMessage::QueryConfig,
+        "QueryInfo" => ControlMessage::QueryInfo,
+        "ListAdmins" => ControlMessage::ListAdmins,
+        "AddAdmin" => {
+            let admin = deserialize_admin(bytes, None)?;
+            ControlMessage::AddAdmin(admin)
+        }
+        "RemoveAdmin" => {
+            let pubkey = rmp::deserialize_string(bytes, None)?;
+            ControlMessage::RemoveAdmin(pubkey)
+        }
+        "InjectSeed" => {
+            let params = deserialize_seedparams(bytes, None)?;
+            ControlMessage::InjectSeed(params)
+        }
+        "Batch" => {
+            let length = batch_len(bytes, in_batch)?;
+            let mut msgs = Vec::new();
+            for _ in 0..length {
+                msgs.push(deserialize_controlmessage_in(bytes, true)?);
+            }
+            ControlMessage::Batch(msgs)
+        }
+        "UpdateConfig" => {
+            let config = deserialize_config(bytes)?;
+            ControlMessage::UpdateConfig(config)
+        }
+        v => return Err(anyhow!("could not deserialize controlmessage {}", v)),
     };
     Ok(en)
 }
 
+// the length is from the wire, so it is checked before anything is read
+fn batch_len(bytes: &mut Bytes, in_batch: bool) -> Result<u32> {
+    if in_batch {
+        return Err(anyhow!("nested Batch"));
+    }
+    let length = rmp::deserialize_array_len(bytes)?;
+    if length > MAX_BATCH {
+        return Err(anyhow!(
+            "Batch of {} msgs, the max is {}",
+            length,
+            MAX_BATCH
+        ));
+    }
+    Ok(length)
+}
+
 #[test]
 fn test_controlmessage_serde() {
     for test in [
@@ -89,6 +256,77 @@ fn test_controlmessage_serde() {
             message_sig: "H119Q3ZihfysLmcMuOHdSqLqLwmEOOFHF96+16rFkEYEc3dXH8xW1lSM0Fi4ZjZ8XAMSMwltQWJ5pDblAhEZVoc=".to_string(),
         }),
         ControlMessage::QueryAll,
+        ControlMessage::QueryPolicyFilter,
+        ControlMessage::UpdatePolicyFilter(vec![
+            FilterRule {
+                tag: "policy-commitment-htlc-inflight-limit".to_string(),
+                action: FilterAction::Error,
+                is_prefix: false,
+            },
+            FilterRule {
+                tag: "policy-routing".to_string(),
+                action: FilterAction::Warn,
+                is_prefix: true,
+            },
+        ]),
+        ControlMessage::UpdatePolicyFilter(vec![]),
+        ControlMessage::ListPending,
+        ControlMessage::ApprovePending(u64::MAX),
+        ControlMessage::DeclinePending(0),
+        ControlMessage::SetTime(1_700_000_000),
+        ControlMessage::ForceTime(0),
+        ControlMessage::QueryAudit {
+            since: u64::MAX,
+            limit: u32::MAX,
+        },
+        ControlMessage::QueryAudit { since: 0, limit: 0 },
+        ControlMessage::QueryProxyPolicy,
+        ControlMessage::UpdateProxyPolicy(ProxyPolicy {
+            sign_message: true,
+            auth_token: false,
+            audiences: Vec::new(),
+        }),
+        ControlMessage::AllowlistAdd(vec![
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string(),
+            "payee:0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string(),
+        ]),
+        ControlMessage::AllowlistRemove(vec![
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string()
+        ]),
+        ControlMessage::AllowlistRemove(vec![]),
+        ControlMessage::QueryConfig,
+        ControlMessage::QueryInfo,
+        ControlMessage::ListAdmins,
+        ControlMessage::AddAdmin(Admin {
+            pubkey: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .to_string(),
+            role: Role::Auditor,
+            nonce: 0,
+        }),
+        ControlMessage::RemoveAdmin(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string(),
+        ),
+        ControlMessage::UpdateConfig(Config {
+            broker: "broker.sphinx.chat:8883".to_string(),
+            ssid: "sphinx".to_string(),
+            pass: "password".to_string(),
+            network: "regtest".to_string(),
+        }),
+        ControlMessage::Batch(vec![
+            ControlMessage::UpdatePolicy(Policy::default()),
+            ControlMessage::AllowlistAdd(vec![
+                "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string()
+            ]),
+            ControlMessage::QueryAll,
+        ]),
+        ControlMessage::Batch(vec![]),
+        ControlMessage::InjectSeed(SeedParams {
+            pubkey: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .to_string(),
+            cipher: "ab".repeat(60),
+            commitment: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+        }),
     ] {
         //serialize
         let mut buff = ByteBuf::new();
@@ -107,6 +345,31 @@ fn test_controlmessage_serde() {
     }
 }
 
+#[test]
+fn test_batch_limits() {
+    let decode = |msg: &ControlMessage| {
+        let mut buff = ByteBuf::new();
+        serialize_controlmessage(&mut buff, msg).unwrap();
+        deserialize_controlmessage(&mut Bytes::new(buff.as_slice()))
+    };
+    let full = ControlMessage::Batch(vec![ControlMessage::Nonce; MAX_BATCH as usize]);
+    assert_eq!(decode(&full).unwrap(), full);
+    let over = ControlMessage::Batch(vec![ControlMessage::Nonce; MAX_BATCH as usize + 1]);
+    assert!(decode(&over).is_err());
+    let nested = ControlMessage::Batch(vec![ControlMessage::Batch(vec![])]);
+    assert!(decode(&nested).is_err());
+    // a length far past the end of the msg
+    let bomb = [
+        0x81, 0xa5, b'B', b'a', b't', b'c', b'h', 0xdd, 0x7f, 0xff, 0xff, 0xff,
+    ];
+    assert!(deserialize_controlmessage(&mut Bytes::new(&bomb)).is_err());
+
+    let nested = ControlResponse::Batch(vec![ControlResponse::Batch(vec![])]);
+    let mut buff = ByteBuf::new();
+    serialize_controlresponse(&mut buff, &nested).unwrap();
+    assert!(deserialize_controlresponse(&mut Bytes::new(buff.as_slice())).is_err());
+}
+
 fn serialize_all(buff: &mut ByteBuf, field_name: Option<&str>, object: &All) -> Result<()> {
     rmp::serialize_field_name(buff, field_name)?;
     rmp::serialize_map_len(buff, 3u32)?;
@@ -209,11 +472,89 @@ pub fn serialize_controlresponse(buff: &mut ByteBuf, object: &ControlResponse) -
             rmp::serialize_map_len(buff, 1u32)?;
             rmp::serialize_string(buff, Some("Error"), error)?;
         }
+        ControlResponse::PolicyFilterCurrent(filter) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_policy_filter(buff, Some("PolicyFilterCurrent"), filter)?;
+        }
+        ControlResponse::PolicyFilterUpdated(filter) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_policy_filter(buff, Some("PolicyFilterUpdated"), filter)?;
+        }
+        ControlResponse::PendingList(list) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_pending_vec(buff, Some("PendingList"), list)?;
+        }
+        ControlResponse::TimeSet(secs) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_uint(buff, Some("TimeSet"), *secs)?;
+        }
+        ControlResponse::ProxyPolicyCurrent(policy) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_proxy_policy(buff, Some("ProxyPolicyCurrent"), policy)?;
+        }
+        ControlResponse::ProxyPolicyUpdated(policy) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_proxy_policy(buff, Some("ProxyPolicyUpdated"), policy)?;
+        }
+        ControlResponse::AuditLog(entries) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_audit_vec(buff, Some("AuditLog"), entries)?;
+        }
+        ControlResponse::PendingApproved(id) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_uint(buff, Some("PendingApproved"), *id)?;
+        }
+        ControlResponse::PendingDeclined(id) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_uint(buff, Some("PendingDeclined"), *id)?;
+        }
+        ControlResponse::ConfigCurrent(config) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_field_name(buff, Some("ConfigCurrent"))?;
+            serialize_config(buff, config)?;
+        }
+        ControlResponse::ConfigUpdated(config) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_field_name(buff, Some("ConfigUpdated"))?;
+            serialize_config(buff, config)?;
+        }
+        ControlResponse::InfoCurrent(info) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_info(buff, Some("InfoCurrent"), info)?;
+        }
+        ControlResponse::AdminList(admins) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_admin_vec(buff, Some("AdminList"), admins)?;
+        }
+        ControlResponse::AdminAdded(admin) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            serialize_admin(buff, Some("AdminAdded"), admin)?;
+        }
+        ControlResponse::AdminRemoved(pubkey) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_string(buff, Some("AdminRemoved"), pubkey)?;
+        }
+        ControlResponse::SeedInjected(commitment) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_string(buff, Some("SeedInjected"), commitment)?;
+        }
+        ControlResponse::Batch(ress) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_field_name(buff, Some("Batch"))?;
+            rmp::serialize_array_len(buff, ress.len() as u32)?;
+            for r in ress {
+                serialize_controlresponse(buff, r)?;
+            }
+        }
     }
     Ok(())
 }
 
 pub fn deserialize_controlresponse(bytes: &mut Bytes) -> Result<ControlResponse> {
+    deserialize_controlresponse_in(bytes, false)
+}
+
+fn deserialize_controlresponse_in(bytes: &mut Bytes, in_batch: bool) -> Result<ControlResponse> {
     let peek = rmp::peek_byte(bytes, None)?;
     if peek == 0x81 {
         rmp::deserialize_map_len(bytes, 1)?;
@@ -259,7 +600,79 @@ pub fn deserialize_controlresponse(bytes: &mut Bytes) -> Result<ControlResponse>
             let error = rmp::deserialize_string(bytes, None)?;
             ControlResponse::Error(error)
         }
-        _ => panic!("could not deserialize controlresponse"),
+        "PolicyFilterCurrent" => {
+            let filter = deserialize_policy_filter(bytes, None)?;
+            ControlResponse::PolicyFilterCurrent(filter)
+        }
+        "PolicyFilterUpdated" => {
+            let filter = deserialize_policy_filter(bytes, None)?;
+            ControlResponse::PolicyFilterUpdated(filter)
+        }
+        "PendingList" => {
+            let list = deserialize_pending_vec(bytes, None)?;
+            ControlResponse::PendingList(list)
+        }
+        "PendingApproved" => {
+            let id = rmp::deserialize_uint(bytes, None)?;
+            ControlResponse::PendingApproved(id)
+        }
+        "TimeSet" => {
+            let secs = rmp::deserialize_uint(bytes, None)?;
+            ControlResponse::TimeSet(secs)
+        }
+        "ProxyPolicyCurrent" => {
+            let policy = deserialize_proxy_policy(bytes, None)?;
+            ControlResponse::ProxyPolicyCurrent(policy)
+        }
+        "ProxyPolicyUpdated" => {
+            let policy = deserialize_proxy_policy(bytes, None)?;
+            ControlResponse::ProxyPolicyUpdated(policy)
+        }
+        "AuditLog" => {
+            let entries = deserialize_audit_vec(bytes, None)?;
+            ControlResponse::AuditLog(entries)
+        }
+        "PendingDeclined" => {
+            let id = rmp::deserialize_uint(bytes, None)?;
+            ControlResponse::PendingDeclined(id)
+        }
+        "ConfigCurrent" => {
+            let config = deserialize_config(bytes)?;
+            ControlResponse::ConfigCurrent(config)
+        }
+        "ConfigUpdated" => {
+            let config = deserialize_config(bytes)?;
+            ControlResponse::ConfigUpdated(config)
+        }
+        "InfoCurrent" => {
+            let info = deserialize_info(bytes, None)?;
+            ControlResponse::InfoCurrent(info)
+        }
+        "AdminList" => {
+            let admins = deserialize_admin_vec(bytes, None)?;
+            ControlResponse::AdminList(admins)
+        }
+        "AdminAdded" => {
+            let admin = deserialize_admin(bytes, None)?;
+            ControlResponse::AdminAdded(admin)
+        }
+        "AdminRemoved" => {
+            let pubkey = rmp::deserialize_string(bytes, None)?;
+            ControlResponse::AdminRemoved(pubkey)
+        }
+        "SeedInjected" => {
+            let commitment = rmp::deserialize_string(bytes, None)?;
+            ControlResponse::SeedInjected(commitment)
+        }
+        "Batch" => {
+            let length = batch_len(bytes, in_batch)?;
+            let mut ress = Vec::new();
+            for _ in 0..length {
+                ress.push(deserialize_controlresponse_in(bytes, true)?);
+            }
+            ControlResponse::Batch(ress)
+        }
+        v => return Err(anyhow!("could not deserialize controlresponse {}", v)),
     };
     Ok(en)
 }
@@ -322,6 +735,104 @@ fn test_controlresponse_serde() {
             velocity: Some((u64::MAX, vec![u64::MAX, u64::MAX, u64::MAX, u64::MAX])),
         }),
         ControlResponse::Error("I am your father".to_string()),
+        ControlResponse::PolicyFilterCurrent(vec![]),
+        ControlResponse::PolicyFilterUpdated(vec![FilterRule {
+            tag: "policy-".to_string(),
+            action: FilterAction::Warn,
+            is_prefix: true,
+        }]),
+        ControlResponse::PendingList(vec![]),
+        ControlResponse::PendingList(vec![
+            Pending {
+                id: 1,
+                kind: PendingKind::Invoice,
+                payment_hash: "0101010101010101010101010101010101010101010101010101010101010101"
+                    .to_string(),
+                amount_msat: u64::MAX,
+                approved: false,
+            },
+            Pending {
+                id: u64::MAX,
+                kind: PendingKind::Keysend,
+                payment_hash: "0202020202020202020202020202020202020202020202020202020202020202"
+                    .to_string(),
+                amount_msat: 1_000,
+                approved: true,
+            },
+        ]),
+        ControlResponse::PendingApproved(u64::MAX),
+        ControlResponse::PendingDeclined(0),
+        ControlResponse::TimeSet(u64::MAX),
+        ControlResponse::AuditLog(vec![]),
+        ControlResponse::ProxyPolicyCurrent(ProxyPolicy::default()),
+        ControlResponse::ProxyPolicyUpdated(ProxyPolicy {
+            sign_message: false,
+            auth_token: true,
+            audiences: vec!["tribes".to_string(), "media".to_string()],
+        }),
+        ControlResponse::AuditLog(vec![AuditEntry {
+            seq: u64::MAX,
+            nonce: u64::MAX,
+            kind: "UpdatePolicy".to_string(),
+            admin: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .to_string(),
+            timestamp: 1_700_000_000,
+            prev_hash: "00".repeat(32),
+            hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+            sig: "11".repeat(64),
+        }]),
+        ControlResponse::ConfigCurrent(Config::default()),
+        ControlResponse::ConfigUpdated(Config {
+            broker: "broker.sphinx.chat:8883".to_string(),
+            ssid: "sphinx".to_string(),
+            pass: REDACTED.to_string(),
+            network: "regtest".to_string(),
+        }),
+        ControlResponse::InfoCurrent(Info {
+            signer_id: "01010101010101010101010101010101".to_string(),
+            node_id: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .to_string(),
+            network: "regtest".to_string(),
+            version: "0.1.0".to_string(),
+            nonce: u64::MAX,
+            features: vec!["lowmemory".to_string(), "no-std".to_string()],
+            ota_version: Some(3),
+        }),
+        ControlResponse::InfoCurrent(Info::default()),
+        ControlResponse::AdminList(vec![
+            Admin {
+                pubkey: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                    .to_string(),
+                role: Role::Owner,
+                nonce: u64::MAX,
+            },
+            Admin {
+                pubkey: "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+                    .to_string(),
+                role: Role::Operator,
+                nonce: 3,
+            },
+        ]),
+        ControlResponse::AdminList(vec![]),
+        ControlResponse::AdminAdded(Admin {
+            pubkey: "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5"
+                .to_string(),
+            role: Role::Auditor,
+            nonce: 0,
+        }),
+        ControlResponse::AdminRemoved(
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5".to_string(),
+        ),
+        ControlResponse::Batch(vec![
+            ControlResponse::PolicyUpdated(Policy::default()),
+            ControlResponse::ResetWifi,
+            ControlResponse::Nonce(9),
+        ]),
+        ControlResponse::Batch(vec![]),
+        ControlResponse::SeedInjected(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+        ),
     ] {
         //serialize
         let mut buff = ByteBuf::new();
@@ -523,6 +1034,44 @@ fn test_policy_serde() {
     let mut bytes = Bytes::new(buff.as_slice());
     let object = deserialize_policy(&mut bytes, None).unwrap();
     assert!(test == object);
+
+    let test = Policy {
+        msat_per_interval: u64::MAX,
+        interval: Interval::Custom(3 * 24 * 60 * 60),
+        htlc_limit_msat: u64::MAX,
+    };
+
+    //serialize
+    let mut buff = ByteBuf::new();
+    let reference = rmp_serde::to_vec_named(&test).unwrap();
+    serialize_policy(&mut buff, None, &test).unwrap();
+    assert!(reference == *buff.as_vec());
+
+    // deserialize with rmp-serde
+    let result: Policy = rmp_serde::from_slice(buff.as_slice()).unwrap();
+    assert!(test == result);
+
+    // deserialize with rmp
+    let mut bytes = Bytes::new(buff.as_slice());
+    let object = deserialize_policy(&mut bytes, None).unwrap();
+    assert!(test == object);
+}
+
+#[test]
+fn test_policy_backwards_compat() {
+    // a daily policy as persisted before Weekly, Monthly and Custom existed
+    let old: &[u8] = &[
+        0x83, 0xb1, b'm', b's', b'a', b't', b'_', b'p', b'e', b'r', b'_', b'i', b'n', b't', b'e',
+        b'r', b'v', b'a', b'l', 0xcf, 0x00, 0x00, 0x00, 0x04, 0xe3, 0xb2, 0x92, 0x00, 0xa8, b'i',
+        b'n', b't', b'e', b'r', b'v', b'a', b'l', 0xa5, b'd', b'a', b'i', b'l', b'y', 0xaf, b'h',
+        b't', b'l', b'c', b'_', b'l', b'i', b'm', b'i', b't', b'_', b'm', b's', b'a', b't', 0xce,
+        0x3b, 0x9a, 0xca, 0x00,
+    ];
+    let mut bytes = Bytes::new(old);
+    let object = deserialize_policy(&mut bytes, None).unwrap();
+    assert!(object == Policy::default());
+    let result: Policy = rmp_serde::from_slice(old).unwrap();
+    assert!(result == Policy::default());
 }
 
 fn serialize_interval(
@@ -532,26 +1081,46 @@ fn serialize_interval(
 ) -> Result<()> {
     rmp::serialize_field_name(buff, field_name)?;
     match object {
-        Interval::Hourly => rmp::serialize_variant(buff, "hourly")?,
-        Interval::Daily => rmp::serialize_variant(buff, "daily")?,
+        Interval::Custom(secs) => {
+            rmp::serialize_map_len(buff, 1u32)?;
+            rmp::serialize_uint(buff, Some(object.as_str()), *secs)?;
+        }
+        _ => rmp::serialize_variant(buff, object.as_str())?,
     };
     Ok(())
 }
 
 fn deserialize_interval(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Interval> {
     rmp::deserialize_field_name(bytes, field_name)?;
+    let peek = rmp::peek_byte(bytes, None)?;
+    if peek == 0x81 {
+        rmp::deserialize_map_len(bytes, 1)?;
+    }
     let variant = rmp::deserialize_variant(bytes)?;
     let en = match variant.as_str() {
         "hourly" => Interval::Hourly,
         "daily" => Interval::Daily,
-        m => panic!("wrong: {}", m),
+        "weekly" => Interval::Weekly,
+        "monthly" => Interval::Monthly,
+        "custom" => {
+            let secs = rmp::deserialize_uint(bytes, None)?;
+            Interval::Custom(secs)
+        }
+        m => return Err(anyhow!("deserialize_interval: unknown interval {}", m)),
     };
     Ok(en)
 }
 
 #[test]
 fn test_interval_serde() {
-    for test in [Interval::Hourly, Interval::Daily] {
+    for test in [
+        Interval::Hourly,
+        Interval::Daily,
+        Interval::Weekly,
+        Interval::Monthly,
+        Interval::Custom(u64::MAX),
+        Interval::Custom(90),
+    ] {
         //serialize
         let mut buff = ByteBuf::new();
         let reference = rmp_serde::to_vec_named(&test).unwrap();
@@ -569,6 +1138,625 @@ fn test_interval_serde() {
     }
 }
 
+pub fn serialize_policy_filter(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &PolicyFilter,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_array_len(buff, object.len() as u32)?;
+    for rule in object {
+        serialize_filter_rule(buff, None, rule)?;
+    }
+    Ok(())
+}
+
+// the most rules in a PolicyFilter, a few for each VLS policy tag
+pub const MAX_FILTER_RULES: u32 = 256;
+
+pub fn deserialize_policy_filter(
+    bytes: &mut Bytes,
+    field_name: Option<&str>,
+) -> Result<PolicyFilter> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    let length = rmp::deserialize_array_len(bytes)?;
+    if length > MAX_FILTER_RULES {
+        return Err(anyhow!(
+            "policy filter of {} rules, the max is {}",
+            length,
+            MAX_FILTER_RULES
+        ));
+    }
+    let mut rules: PolicyFilter = Vec::new();
+    for _ in 0..length {
+        let rule = deserialize_filter_rule(bytes, None)?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+fn serialize_filter_rule(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &FilterRule,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 3u32)?;
+    rmp::serialize_string(buff, Some("tag"), &object.tag)?;
+    rmp::serialize_string(buff, Some("action"), object.action.as_str())?;
+    rmp::serialize_bool(buff, Some("is_prefix"), object.is_prefix)?;
+    Ok(())
+}
+
+fn deserialize_filter_rule(bytes: &mut Bytes, field_name: Option<&str>) -> Result<FilterRule> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 3)?;
+    let tag = rmp::deserialize_string(bytes, Some("tag"))?;
+    let action = rmp::deserialize_string(bytes, Some("action"))?;
+    let action = action.parse::<FilterAction>().map_err(|e| anyhow!(e))?;
+    let is_prefix = rmp::deserialize_bool(bytes, Some("is_prefix"))?;
+    Ok(FilterRule {
+        tag,
+        action,
+        is_prefix,
+    })
+}
+
+#[test]
+fn test_policy_filter_serde() {
+    for test in [
+        vec![],
+        vec![
+            FilterRule {
+                tag: "policy-channel-safe-mode".to_string(),
+                action: FilterAction::Error,
+                is_prefix: false,
+            },
+            FilterRule {
+                tag: "policy-".to_string(),
+                action: FilterAction::Warn,
+                is_prefix: true,
+            },
+        ],
+    ] {
+        //serialize
+        let mut buff = ByteBuf::new();
+        let reference = rmp_serde::to_vec_named(&test).unwrap();
+        serialize_policy_filter(&mut buff, None, &test).unwrap();
+        assert!(reference == *buff.as_vec());
+
+        // deserialize with rmp-serde
+        let result: PolicyFilter = rmp_serde::from_slice(buff.as_slice()).unwrap();
+        assert!(test == result);
+
+        // deserialize with rmp
+        let mut bytes = Bytes::new(buff.as_slice());
+        let object = deserialize_policy_filter(&mut bytes, None).unwrap();
+        assert!(test == object);
+    }
+    // the rule count is checked before anything is allocated
+    let bomb = [0xdd, 0x7f, 0xff, 0xff, 0xff];
+    assert!(deserialize_policy_filter(&mut Bytes::new(&bomb), None).is_err());
+    let rule = FilterRule {
+        tag: "policy-".to_string(),
+        action: FilterAction::Warn,
+        is_prefix: true,
+    };
+    let too_many = vec![rule; MAX_FILTER_RULES as usize + 1];
+    let mut buff = ByteBuf::new();
+    serialize_policy_filter(&mut buff, None, &too_many).unwrap();
+    assert!(deserialize_policy_filter(&mut Bytes::new(buff.as_slice()), None).is_err());
+}
+
+pub fn serialize_pending_vec(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &Vec<Pending>,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_array_len(buff, object.len() as u32)?;
+    for p in object {
+        serialize_pending(buff, None, p)?;
+    }
+    Ok(())
+}
+
+pub fn deserialize_pending_vec(
+    bytes: &mut Bytes,
+    field_name: Option<&str>,
+) -> Result<Vec<Pending>> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    let length = rmp::deserialize_array_len(bytes)?;
+    let mut list: Vec<Pending> = Vec::with_capacity(length as usize);
+    for _ in 0..length {
+        let p = deserialize_pending(bytes, None)?;
+        list.push(p);
+    }
+    Ok(list)
+}
+
+fn serialize_pending(buff: &mut ByteBuf, field_name: Option<&str>, object: &Pending) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 5u32)?;
+    rmp::serialize_uint(buff, Some("id"), object.id)?;
+    rmp::serialize_string(buff, Some("kind"), object.kind.as_str())?;
+    rmp::serialize_string(buff, Some("payment_hash"), &object.payment_hash)?;
+    rmp::serialize_uint(buff, Some("amount_msat"), object.amount_msat)?;
+    rmp::serialize_bool(buff, Some("approved"), object.approved)?;
+    Ok(())
+}
+
+fn deserialize_pending(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Pending> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 5)?;
+    let id = rmp::deserialize_uint(bytes, Some("id"))?;
+    let kind = rmp::deserialize_string(bytes, Some("kind"))?;
+    let kind = kind.parse::<PendingKind>().map_err(|e| anyhow!(e))?;
+    let payment_hash = rmp::deserialize_string(bytes, Some("payment_hash"))?;
+    let amount_msat = rmp::deserialize_uint(bytes, Some("amount_msat"))?;
+    let approved = rmp::deserialize_bool(bytes, Some("approved"))?;
+    Ok(Pending {
+        id,
+        kind,
+        payment_hash,
+        amount_msat,
+        approved,
+    })
+}
+
+#[test]
+fn test_pending_serde() {
+    let test = Pending {
+        id: u64::MAX,
+        kind: PendingKind::Invoice,
+        payment_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            .to_string(),
+        amount_msat: u64::MAX,
+        approved: true,
+    };
+
+    //serialize
+    let mut buff = ByteBuf::new();
+    let reference = rmp_serde::to_vec_named(&test).unwrap();
+    serialize_pending(&mut buff, None, &test).unwrap();
+    assert!(reference == *buff.as_vec());
+
+    // deserialize with rmp-serde
+    let result: Pending = rmp_serde::from_slice(buff.as_slice()).unwrap();
+    assert!(test == result);
+
+    // deserialize with rmp
+    let mut bytes = Bytes::new(buff.as_slice());
+    let object = deserialize_pending(&mut bytes, None).unwrap();
+    assert!(test == object);
+}
+
+pub fn serialize_admin_vec(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &Vec<Admin>,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_array_len(buff, object.len() as u32)?;
+    for a in object {
+        serialize_admin(buff, None, a)?;
+    }
+    Ok(())
+}
+
+pub fn deserialize_admin_vec(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Vec<Admin>> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    let length = rmp::deserialize_array_len(bytes)?;
+    let mut list: Vec<Admin> = Vec::with_capacity(length as usize);
+    for _ in 0..length {
+        let a = deserialize_admin(bytes, None)?;
+        list.push(a);
+    }
+    Ok(list)
+}
+
+fn serialize_admin(buff: &mut ByteBuf, field_name: Option<&str>, object: &Admin) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 3u32)?;
+    rmp::serialize_string(buff, Some("pubkey"), &object.pubkey)?;
+    rmp::serialize_string(buff, Some("role"), object.role.as_str())?;
+    rmp::serialize_uint(buff, Some("nonce"), object.nonce)?;
+    Ok(())
+}
+
+fn deserialize_admin(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Admin> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 3)?;
+    let pubkey = rmp::deserialize_string(bytes, Some("pubkey"))?;
+    let role = rmp::deserialize_string(bytes, Some("role"))?;
+    let role = role.parse::<Role>().map_err(|e| anyhow!(e))?;
+    let nonce = rmp::deserialize_uint(bytes, Some("nonce"))?;
+    Ok(Admin {
+        pubkey,
+        role,
+        nonce,
+    })
+}
+
+pub fn serialize_proxyrequest(buff: &mut ByteBuf, object: &ProxyRequest) -> Result<()> {
+    match object {
+        ProxyRequest::SignMessage(msg) => {
+            rmp::serialize_map_len(buff, 1)?;
+            rmp::serialize_string(buff, Some("SignMessage"), msg)?;
+        }
+        ProxyRequest::AuthToken => rmp::serialize_variant(buff, "AuthToken")?,
+        ProxyRequest::AuthTokenV2(req) => {
+            rmp::serialize_map_len(buff, 1)?;
+            serialize_token_request(buff, Some("AuthTokenV2"), req)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn deserialize_proxyrequest(bytes: &mut Bytes) -> Result<ProxyRequest> {
+    let peek = rmp::peek_byte(bytes, None)?;
+    if peek == 0x81 {
+        rmp::deserialize_map_len(bytes, 1)?;
+    }
+    let variant = rmp::deserialize_variant(bytes)?;
+    let en = match variant.as_str() {
+        "SignMessage" => {
+            let msg = rmp::deserialize_string(bytes, None)?;
+            ProxyRequest::SignMessage(msg)
+        }
+        "AuthToken" => ProxyRequest::AuthToken,
+        "AuthTokenV2" => {
+            let req = deserialize_token_request(bytes, None)?;
+            ProxyRequest::AuthTokenV2(req)
+        }
+        // these come from the broker, so no panic
+        v => return Err(anyhow!("unknown proxy request {}", v)),
+    };
+    Ok(en)
+}
+
+pub fn serialize_token_request(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &TokenRequest,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 3u32)?;
+    rmp::serialize_string(buff, Some("audience"), &object.audience)?;
+    rmp::serialize_string_vec(buff, Some("scopes"), &object.scopes)?;
+    rmp::serialize_uint(buff, Some("ttl"), object.ttl as u64)?;
+    Ok(())
+}
+
+pub fn deserialize_token_request(
+    bytes: &mut Bytes,
+    field_name: Option<&str>,
+) -> Result<TokenRequest> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 3)?;
+    let audience = rmp::deserialize_string(bytes, Some("audience"))?;
+    let scopes = rmp::deserialize_string_vec(bytes, Some("scopes"))?;
+    let ttl = rmp::deserialize_uint(bytes, Some("ttl"))?;
+    if ttl > u32::MAX as u64 {
+        return Err(anyhow!("token ttl too big"));
+    }
+    Ok(TokenRequest {
+        audience,
+        scopes,
+        ttl: ttl as u32,
+    })
+}
+
+pub fn serialize_proxyresponse(buff: &mut ByteBuf, object: &ProxyResponse) -> Result<()> {
+    rmp::serialize_map_len(buff, 1)?;
+    match object {
+        ProxyResponse::MessageSigned(sig) => {
+            rmp::serialize_string(buff, Some("MessageSigned"), sig)?;
+        }
+        ProxyResponse::AuthToken(token) => {
+            rmp::serialize_string(buff, Some("AuthToken"), token)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn deserialize_proxyresponse(bytes: &mut Bytes) -> Result<ProxyResponse> {
+    rmp::deserialize_map_len(bytes, 1)?;
+    let variant = rmp::deserialize_variant(bytes)?;
+    let en = match variant.as_str() {
+        "MessageSigned" => {
+            let sig = rmp::deserialize_string(bytes, None)?;
+            ProxyResponse::MessageSigned(sig)
+        }
+        "AuthToken" => {
+            let token = rmp::deserialize_string(bytes, None)?;
+            ProxyResponse::AuthToken(token)
+        }
+        v => return Err(anyhow!("unknown proxy response {}", v)),
+    };
+    Ok(en)
+}
+
+#[test]
+fn test_proxy_serde() {
+    for test in [
+        ProxyRequest::SignMessage("hello".to_string()),
+        ProxyRequest::SignMessage(String::new()),
+        ProxyRequest::AuthToken,
+        ProxyRequest::AuthTokenV2(TokenRequest {
+            audience: "tribes".to_string(),
+            scopes: vec!["read".to_string(), "write".to_string()],
+            ttl: 3600,
+        }),
+        ProxyRequest::AuthTokenV2(TokenRequest {
+            audience: String::new(),
+            scopes: Vec::new(),
+            ttl: u32::MAX,
+        }),
+    ] {
+        let mut buff = ByteBuf::new();
+        let reference = rmp_serde::to_vec_named(&test).unwrap();
+        serialize_proxyrequest(&mut buff, &test).unwrap();
+        assert!(reference == *buff.as_vec());
+        let result: ProxyRequest = rmp_serde::from_slice(buff.as_slice()).unwrap();
+        assert!(test == result);
+        let mut bytes = Bytes::new(buff.as_slice());
+        let object = deserialize_proxyrequest(&mut bytes).unwrap();
+        assert!(test == object);
+    }
+    for test in [
+        ProxyResponse::MessageSigned("1f".repeat(65)),
+        ProxyResponse::AuthToken("AAAAAA==".to_string()),
+    ] {
+        let mut buff = ByteBuf::new();
+        let reference = rmp_serde::to_vec_named(&test).unwrap();
+        serialize_proxyresponse(&mut buff, &test).unwrap();
+        assert!(reference == *buff.as_vec());
+        let result: ProxyResponse = rmp_serde::from_slice(buff.as_slice()).unwrap();
+        assert!(test == result);
+        let mut bytes = Bytes::new(buff.as_slice());
+        let object = deserialize_proxyresponse(&mut bytes).unwrap();
+        assert!(test == object);
+    }
+    let mut buff = ByteBuf::new();
+    rmp::serialize_variant(&mut buff, "SignTx").unwrap();
+    assert!(deserialize_proxyrequest(&mut Bytes::new(buff.as_slice())).is_err());
+    // a stored policy from before audiences
+    let mut buff = ByteBuf::new();
+    rmp::serialize_map_len(&mut buff, 2).unwrap();
+    rmp::serialize_bool(&mut buff, Some("sign_message"), true).unwrap();
+    rmp::serialize_bool(&mut buff, Some("auth_token"), true).unwrap();
+    let p = deserialize_proxy_policy(&mut Bytes::new(buff.as_slice()), None).unwrap();
+    assert!(p.auth_token && p.audiences.is_empty());
+}
+
+pub fn serialize_proxy_policy(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &ProxyPolicy,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 3u32)?;
+    rmp::serialize_bool(buff, Some("sign_message"), object.sign_message)?;
+    rmp::serialize_bool(buff, Some("auth_token"), object.auth_token)?;
+    rmp::serialize_string_vec(buff, Some("audiences"), &object.audiences)?;
+    Ok(())
+}
+
+pub fn deserialize_proxy_policy(
+    bytes: &mut Bytes,
+    field_name: Option<&str>,
+) -> Result<ProxyPolicy> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    // a policy from before audiences has 2 fields
+    let old = rmp::peek_byte(bytes, None)? == 0x82;
+    rmp::deserialize_map_len(bytes, if old { 2 } else { 3 })?;
+    let sign_message = rmp::deserialize_bool(bytes, Some("sign_message"))?;
+    let auth_token = rmp::deserialize_bool(bytes, Some("auth_token"))?;
+    let audiences = if old {
+        Vec::new()
+    } else {
+        rmp::deserialize_string_vec(bytes, Some("audiences"))?
+    };
+    Ok(ProxyPolicy {
+        sign_message,
+        auth_token,
+        audiences,
+    })
+}
+
+pub fn serialize_audit_vec(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &Vec<AuditEntry>,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_array_len(buff, object.len() as u32)?;
+    for e in object {
+        serialize_audit_entry(buff, None, e)?;
+    }
+    Ok(())
+}
+
+pub fn deserialize_audit_vec(
+    bytes: &mut Bytes,
+    field_name: Option<&str>,
+) -> Result<Vec<AuditEntry>> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    let length = rmp::deserialize_array_len(bytes)?;
+    let mut list: Vec<AuditEntry> = Vec::with_capacity(length as usize);
+    for _ in 0..length {
+        let e = deserialize_audit_entry(bytes, None)?;
+        list.push(e);
+    }
+    Ok(list)
+}
+
+fn serialize_audit_entry(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &AuditEntry,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 8u32)?;
+    rmp::serialize_uint(buff, Some("seq"), object.seq)?;
+    rmp::serialize_uint(buff, Some("nonce"), object.nonce)?;
+    rmp::serialize_string(buff, Some("kind"), &object.kind)?;
+    rmp::serialize_string(buff, Some("admin"), &object.admin)?;
+    rmp::serialize_uint(buff, Some("timestamp"), object.timestamp)?;
+    rmp::serialize_string(buff, Some("prev_hash"), &object.prev_hash)?;
+    rmp::serialize_string(buff, Some("hash"), &object.hash)?;
+    rmp::serialize_string(buff, Some("sig"), &object.sig)?;
+    Ok(())
+}
+
+fn deserialize_audit_entry(bytes: &mut Bytes, field_name: Option<&str>) -> Result<AuditEntry> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 8)?;
+    let seq = rmp::deserialize_uint(bytes, Some("seq"))?;
+    let nonce = rmp::deserialize_uint(bytes, Some("nonce"))?;
+    let kind = rmp::deserialize_string(bytes, Some("kind"))?;
+    let admin = rmp::deserialize_string(bytes, Some("admin"))?;
+    let timestamp = rmp::deserialize_uint(bytes, Some("timestamp"))?;
+    let prev_hash = rmp::deserialize_string(bytes, Some("prev_hash"))?;
+    let hash = rmp::deserialize_string(bytes, Some("hash"))?;
+    let sig = rmp::deserialize_string(bytes, Some("sig"))?;
+    Ok(AuditEntry {
+        seq,
+        nonce,
+        kind,
+        admin,
+        timestamp,
+        prev_hash,
+        hash,
+        sig,
+    })
+}
+
+fn serialize_info(buff: &mut ByteBuf, field_name: Option<&str>, object: &Info) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 7u32)?;
+    rmp::serialize_string(buff, Some("signer_id"), &object.signer_id)?;
+    rmp::serialize_string(buff, Some("node_id"), &object.node_id)?;
+    rmp::serialize_string(buff, Some("network"), &object.network)?;
+    rmp::serialize_string(buff, Some("version"), &object.version)?;
+    rmp::serialize_uint(buff, Some("nonce"), object.nonce)?;
+    rmp::serialize_string_vec(buff, Some("features"), &object.features)?;
+    match object.ota_version {
+        Some(v) => rmp::serialize_uint(buff, Some("ota_version"), v)?,
+        None => rmp::serialize_none(buff, Some("ota_version"))?,
+    }
+    Ok(())
+}
+
+fn deserialize_info(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Info> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 7)?;
+    let signer_id = rmp::deserialize_string(bytes, Some("signer_id"))?;
+    let node_id = rmp::deserialize_string(bytes, Some("node_id"))?;
+    let network = rmp::deserialize_string(bytes, Some("network"))?;
+    let version = rmp::deserialize_string(bytes, Some("version"))?;
+    let nonce = rmp::deserialize_uint(bytes, Some("nonce"))?;
+    let features = rmp::deserialize_string_vec(bytes, Some("features"))?;
+    let ota_version = if rmp::peek_is_none(bytes, Some("ota_version"))? {
+        rmp::deserialize_none(bytes, Some("ota_version"))?;
+        None
+    } else {
+        Some(rmp::deserialize_uint(bytes, Some("ota_version"))?)
+    };
+    Ok(Info {
+        signer_id,
+        node_id,
+        network,
+        version,
+        nonce,
+        features,
+        ota_version,
+    })
+}
+
+pub fn serialize_heartbeat(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &Heartbeat,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 7u32)?;
+    rmp::serialize_string(buff, Some("node_id"), &object.node_id)?;
+    match object.sequence {
+        Some(seq) => rmp::serialize_uint(buff, Some("sequence"), seq as u64)?,
+        None => rmp::serialize_none(buff, Some("sequence"))?,
+    }
+    rmp::serialize_uint(buff, Some("velocity_msat"), object.velocity_msat)?;
+    rmp::serialize_uint(buff, Some("msat_per_interval"), object.msat_per_interval)?;
+    rmp::serialize_uint(buff, Some("channels"), object.channels)?;
+    rmp::serialize_bool(buff, Some("lss_synced"), object.lss_synced)?;
+    rmp::serialize_uint(buff, Some("uptime"), object.uptime)?;
+    Ok(())
+}
+
+pub fn deserialize_heartbeat(bytes: &mut Bytes, field_name: Option<&str>) -> Result<Heartbeat> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 7)?;
+    let node_id = rmp::deserialize_string(bytes, Some("node_id"))?;
+    let sequence = if rmp::peek_is_none(bytes, Some("sequence"))? {
+        rmp::deserialize_none(bytes, Some("sequence"))?;
+        None
+    } else {
+        let seq = rmp::deserialize_uint(bytes, Some("sequence"))?;
+        if seq > u16::MAX as u64 {
+            return Err(anyhow!("deserialize_heartbeat: sequence out of range"));
+        }
+        Some(seq as u16)
+    };
+    let velocity_msat = rmp::deserialize_uint(bytes, Some("velocity_msat"))?;
+    let msat_per_interval = rmp::deserialize_uint(bytes, Some("msat_per_interval"))?;
+    let channels = rmp::deserialize_uint(bytes, Some("channels"))?;
+    let lss_synced = rmp::deserialize_bool(bytes, Some("lss_synced"))?;
+    let uptime = rmp::deserialize_uint(bytes, Some("uptime"))?;
+    Ok(Heartbeat {
+        node_id,
+        sequence,
+        velocity_msat,
+        msat_per_interval,
+        channels,
+        lss_synced,
+        uptime,
+    })
+}
+
+#[test]
+fn test_heartbeat_serde() {
+    for test in [
+        Heartbeat {
+            node_id: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .to_string(),
+            sequence: Some(u16::MAX),
+            velocity_msat: u64::MAX,
+            msat_per_interval: 21_000_000_000,
+            channels: 3,
+            lss_synced: true,
+            uptime: 60,
+        },
+        Heartbeat::default(),
+    ] {
+        //serialize
+        let mut buff = ByteBuf::new();
+        let reference = rmp_serde::to_vec_named(&test).unwrap();
+        serialize_heartbeat(&mut buff, None, &test).unwrap();
+        assert!(reference == *buff.as_vec());
+
+        // deserialize with rmp-serde
+        let result: Heartbeat = rmp_serde::from_slice(buff.as_slice()).unwrap();
+        assert!(test == result);
+
+        // deserialize with rmp
+        let mut bytes = Bytes::new(buff.as_slice());
+        let object = deserialize_heartbeat(&mut bytes, None).unwrap();
+        assert!(test == object);
+    }
+}
+
 fn serialize_otaparams(
     buff: &mut ByteBuf,
     field_name: Option<&str>,
@@ -622,6 +1810,32 @@ fn test_otaparams_serde() {
     assert!(test == object);
 }
 
+fn serialize_seedparams(
+    buff: &mut ByteBuf,
+    field_name: Option<&str>,
+    object: &SeedParams,
+) -> Result<()> {
+    rmp::serialize_field_name(buff, field_name)?;
+    rmp::serialize_map_len(buff, 3u32)?;
+    rmp::serialize_string(buff, Some("pubkey"), &object.pubkey)?;
+    rmp::serialize_string(buff, Some("cipher"), &object.cipher)?;
+    rmp::serialize_string(buff, Some("commitment"), &object.commitment)?;
+    Ok(())
+}
+
+fn deserialize_seedparams(bytes: &mut Bytes, field_name: Option<&str>) -> Result<SeedParams> {
+    rmp::deserialize_field_name(bytes, field_name)?;
+    rmp::deserialize_map_len(bytes, 3u32)?;
+    let pubkey = rmp::deserialize_string(bytes, Some("pubkey"))?;
+    let cipher = rmp::deserialize_string(bytes, Some("cipher"))?;
+    let commitment = rmp::deserialize_string(bytes, Some("commitment"))?;
+    Ok(SeedParams {
+        pubkey,
+        cipher,
+        commitment,
+    })
+}
+
 pub fn serialize_wifiparams(buff: &mut ByteBuf, object: &WifiParams) -> Result<()> {
     rmp::serialize_map_len(buff, 2u32)?;
     rmp::serialize_string(buff, Some("ssid"), &object.ssid)?;
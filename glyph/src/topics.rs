@@ -17,6 +17,7 @@ pub const LSS_CONFLICT: &str = "lss-conflict";
 pub const LSS_CONFLICT_RES: &str = "lss-conflict-res";
 pub const HELLO: &str = "hello";
 pub const BYE: &str = "bye";
+pub const HEARTBEAT: &str = "heartbeat";
 
 pub const BROKER_SUBS: &[&str] = &[
     ERROR,
@@ -28,6 +29,7 @@ pub const BROKER_SUBS: &[&str] = &[
     INIT_3_RES,
     LSS_RES,
     LSS_CONFLICT_RES,
+    HEARTBEAT,
 ];
 
 pub const SIGNER_SUBS: &[&str] = &[
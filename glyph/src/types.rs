@@ -15,6 +15,110 @@ pub enum ControlMessage {
     QueryVelocity,
     Ota(OtaParams),
     QueryAll,
+    QueryPolicyFilter,
+    UpdatePolicyFilter(PolicyFilter),
+    ListPending,
+    ApprovePending(u64),
+    DeclinePending(u64),
+    AllowlistAdd(Vec<String>),
+    AllowlistRemove(Vec<String>),
+    QueryConfig,
+    UpdateConfig(Config),
+    QueryInfo,
+    ListAdmins,
+    AddAdmin(Admin),
+    // hex pubkey
+    RemoveAdmin(String),
+    // applied all-or-nothing, with a single nonce
+    Batch(Vec<ControlMessage>),
+    InjectSeed(SeedParams),
+    // unix seconds from the broker, for signers without a clock.
+    // SetTime only moves the clock forward, ForceTime can move it back
+    SetTime(u64),
+    ForceTime(u64),
+    // audit entries from seq `since`, at most `limit` of them
+    QueryAudit { since: u64, limit: u32 },
+    QueryProxyPolicy,
+    UpdateProxyPolicy(ProxyPolicy),
+}
+
+impl ControlMessage {
+    // the lowest role allowed to send this message
+    pub fn required_role(&self) -> Role {
+        match self {
+            ControlMessage::Nonce
+            | ControlMessage::QueryPolicy
+            | ControlMessage::QueryAllowlist
+            | ControlMessage::QueryVelocity
+            | ControlMessage::QueryAll
+            | ControlMessage::QueryPolicyFilter
+            | ControlMessage::ListPending
+            | ControlMessage::QueryConfig
+            | ControlMessage::QueryInfo
+            | ControlMessage::ListAdmins
+            | ControlMessage::QueryAudit { .. }
+            | ControlMessage::QueryProxyPolicy => Role::Auditor,
+            ControlMessage::ResetWifi
+            | ControlMessage::UpdatePolicy(_)
+            | ControlMessage::UpdateAllowlist(_)
+            | ControlMessage::UpdatePolicyFilter(_)
+            | ControlMessage::ApprovePending(_)
+            | ControlMessage::DeclinePending(_)
+            | ControlMessage::AllowlistAdd(_)
+            | ControlMessage::AllowlistRemove(_)
+            | ControlMessage::UpdateConfig(_)
+            | ControlMessage::SetTime(_) => Role::Operator,
+            ControlMessage::ResetKeys
+            | ControlMessage::ResetAll
+            | ControlMessage::Ota(_)
+            | ControlMessage::AddAdmin(_)
+            | ControlMessage::RemoveAdmin(_)
+            | ControlMessage::InjectSeed(_)
+            | ControlMessage::ForceTime(_)
+            | ControlMessage::UpdateProxyPolicy(_) => Role::Owner,
+            ControlMessage::Batch(msgs) => msgs
+                .iter()
+                .map(|m| m.required_role())
+                .max()
+                .unwrap_or(Role::Auditor),
+        }
+    }
+    // the variant name, as recorded in the audit log
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ControlMessage::Nonce => "Nonce",
+            ControlMessage::ResetWifi => "ResetWifi",
+            ControlMessage::ResetKeys => "ResetKeys",
+            ControlMessage::ResetAll => "ResetAll",
+            ControlMessage::QueryPolicy => "QueryPolicy",
+            ControlMessage::UpdatePolicy(_) => "UpdatePolicy",
+            ControlMessage::QueryAllowlist => "QueryAllowlist",
+            ControlMessage::UpdateAllowlist(_) => "UpdateAllowlist",
+            ControlMessage::QueryVelocity => "QueryVelocity",
+            ControlMessage::Ota(_) => "Ota",
+            ControlMessage::QueryAll => "QueryAll",
+            ControlMessage::QueryPolicyFilter => "QueryPolicyFilter",
+            ControlMessage::UpdatePolicyFilter(_) => "UpdatePolicyFilter",
+            ControlMessage::ListPending => "ListPending",
+            ControlMessage::ApprovePending(_) => "ApprovePending",
+            ControlMessage::DeclinePending(_) => "DeclinePending",
+            ControlMessage::AllowlistAdd(_) => "AllowlistAdd",
+            ControlMessage::AllowlistRemove(_) => "AllowlistRemove",
+            ControlMessage::QueryConfig => "QueryConfig",
+            ControlMessage::UpdateConfig(_) => "UpdateConfig",
+            ControlMessage::QueryInfo => "QueryInfo",
+            ControlMessage::ListAdmins => "ListAdmins",
+            ControlMessage::AddAdmin(_) => "AddAdmin",
+            ControlMessage::RemoveAdmin(_) => "RemoveAdmin",
+            ControlMessage::Batch(_) => "Batch",
+            ControlMessage::InjectSeed(_) => "InjectSeed",
+            ControlMessage::SetTime(_) => "SetTime",
+            ControlMessage::ForceTime(_) => "ForceTime",
+            ControlMessage::QueryAudit { .. } => "QueryAudit",
+            ControlMessage::QueryProxyPolicy => "QueryProxyPolicy",
+            ControlMessage::UpdateProxyPolicy(_) => "UpdateProxyPolicy",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -38,6 +142,26 @@ pub enum ControlResponse {
     OtaConfirm(OtaParams),
     AllCurrent(All),
     Error(String),
+    PolicyFilterCurrent(PolicyFilter),
+    PolicyFilterUpdated(PolicyFilter),
+    PendingList(Vec<Pending>),
+    PendingApproved(u64),
+    PendingDeclined(u64),
+    ConfigCurrent(Config),
+    ConfigUpdated(Config),
+    InfoCurrent(Info),
+    AdminList(Vec<Admin>),
+    AdminAdded(Admin),
+    AdminRemoved(String),
+    // one response per message in the Batch
+    Batch(Vec<ControlResponse>),
+    // the hex sha256 of the installed seed
+    SeedInjected(String),
+    // the signer time after SetTime or ForceTime
+    TimeSet(u64),
+    AuditLog(Vec<AuditEntry>),
+    ProxyPolicyCurrent(ProxyPolicy),
+    ProxyPolicyUpdated(ProxyPolicy),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq)]
@@ -48,6 +172,19 @@ pub struct Config {
     pub network: String,
 }
 
+// stands in for the wifi password in control responses
+pub const REDACTED: &str = "********";
+
+impl Config {
+    pub fn redacted(&self) -> Self {
+        let mut c = self.clone();
+        if !c.pass.is_empty() {
+            c.pass = REDACTED.to_string();
+        }
+        c
+    }
+}
+
 pub type Velocity = (u64, Vec<u64>);
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -72,27 +209,195 @@ impl Default for Policy {
 pub enum Interval {
     Hourly,
     Daily,
+    Weekly,
+    // 30 days
+    Monthly,
+    // a rolling window of this many seconds
+    Custom(u64),
 }
 
+// "custom:<seconds>" for a Custom interval
 impl FromStr for Interval {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "daily" => Ok(Interval::Daily),
             "hourly" => Ok(Interval::Hourly),
-            _ => Err("invalid interval".to_string()),
+            "weekly" => Ok(Interval::Weekly),
+            "monthly" => Ok(Interval::Monthly),
+            _ => match s.strip_prefix("custom:").map(|secs| secs.parse::<u64>()) {
+                Some(Ok(secs)) if secs > 0 => Ok(Interval::Custom(secs)),
+                _ => Err("invalid interval".to_string()),
+            },
+        }
+    }
+}
+// the FromStr form, "custom:<seconds>" for a Custom interval
+impl core::fmt::Display for Interval {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Interval::Custom(secs) => write!(f, "custom:{}", secs),
+            _ => f.write_str(self.as_str()),
         }
     }
 }
 impl Interval {
+    // the variant name, without the seconds of a Custom interval
     pub fn as_str(&self) -> &'static str {
         match self {
             Interval::Hourly => "hourly",
             Interval::Daily => "daily",
+            Interval::Weekly => "weekly",
+            Interval::Monthly => "monthly",
+            Interval::Custom(_) => "custom",
+        }
+    }
+    // length of the window in seconds
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Interval::Hourly => 60 * 60,
+            Interval::Daily => 24 * 60 * 60,
+            Interval::Weekly => 7 * 24 * 60 * 60,
+            Interval::Monthly => 30 * 24 * 60 * 60,
+            Interval::Custom(secs) => *secs,
+        }
+    }
+}
+
+// an empty filter keeps the VLS permissive default
+pub type PolicyFilter = Vec<FilterRule>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FilterRule {
+    pub tag: String,
+    pub action: FilterAction,
+    // match every tag starting with `tag`
+    pub is_prefix: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    Error,
+    Warn,
+}
+
+impl FromStr for FilterAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(FilterAction::Error),
+            "warn" => Ok(FilterAction::Warn),
+            _ => Err("invalid filter action".to_string()),
+        }
+    }
+}
+impl FilterAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterAction::Error => "error",
+            FilterAction::Warn => "warn",
+        }
+    }
+}
+
+const BASE58_CHARS: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARS: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// Allowlist entries use the VLS format: a bitcoin address (optionally
+// "address:" prefixed), "xpub:<xpub>" or "payee:<node pubkey>". Only the
+// shape is checked here, the signer parses them fully.
+pub fn check_allowlist_entry(entry: &str) -> Result<(), String> {
+    let ok = match entry.split_once(':') {
+        Some(("address", body)) => is_address(body),
+        Some(("xpub", body)) => is_xpub(body),
+        Some(("payee", body)) => is_pubkey(body),
+        Some(_) => false,
+        None => is_address(entry),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("invalid allowlist entry {}", entry))
+    }
+}
+
+fn is_base58(s: &str) -> bool {
+    s.chars().all(|c| BASE58_CHARS.contains(c))
+}
+
+fn is_address(s: &str) -> bool {
+    let lower = s.to_lowercase();
+    if let Some((hrp, data)) = lower.rsplit_once('1') {
+        if ["bc", "tb", "bcrt"].contains(&hrp) {
+            // bech32 is case insensitive, but not mixed case
+            let one_case = s == lower || s == s.to_uppercase();
+            return one_case
+                && (14..=90).contains(&s.len())
+                && data.chars().all(|c| BECH32_CHARS.contains(c));
+        }
+    }
+    (26..=35).contains(&s.len()) && s.starts_with(['1', '3', 'm', 'n', '2']) && is_base58(s)
+}
+
+fn is_xpub(s: &str) -> bool {
+    s.len() == 111 && (s.starts_with("xpub") || s.starts_with("tpub")) && is_base58(s)
+}
+
+fn is_pubkey(s: &str) -> bool {
+    s.len() == 66
+        && (s.starts_with("02") || s.starts_with("03"))
+        && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// a payment over the velocity budget, waiting for manual approval
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub id: u64,
+    pub kind: PendingKind,
+    // hex encoded
+    pub payment_hash: String,
+    pub amount_msat: u64,
+    pub approved: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingKind {
+    Invoice,
+    Keysend,
+}
+
+impl FromStr for PendingKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "invoice" => Ok(PendingKind::Invoice),
+            "keysend" => Ok(PendingKind::Keysend),
+            _ => Err("invalid pending kind".to_string()),
+        }
+    }
+}
+impl PendingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PendingKind::Invoice => "invoice",
+            PendingKind::Keysend => "keysend",
         }
     }
 }
 
+// A seed encrypted to the signer's provisioning pubkey
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SeedParams {
+    // hex pubkey of the one-time key used for ECDH
+    pub pubkey: String,
+    // hex sphinx_crypter::chacha payload of the 32 byte seed
+    pub cipher: String,
+    // hex sha256 of the seed
+    pub commitment: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OtaParams {
     pub version: u64,
@@ -102,11 +407,285 @@ pub struct OtaParams {
     // A base64 encoded bitcoin::sign_message::MessageSignature on the sha256_hash string
     // Should satisfy bitcoin::sign_message::is_signed_by_address
     // Get the message hash from bitcoin::sign_message::signed_msg_hash
+    // For a P2WPKH or P2TR release address, a BIP-322 simple or full signature
     pub message_sig: String,
 }
 
+// A controller key besides the signer's own one, which is always an Owner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Admin {
+    // hex encoded
+    pub pubkey: String,
+    pub role: Role,
+    // the last nonce used by this admin
+    pub nonce: u64,
+}
+
+// the persisted sphinx_auther::nonce::NonceWindow of one signer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NonceWindowState {
+    // hex encoded
+    pub pubkey: String,
+    pub top: u64,
+    pub seen: Vec<u64>,
+}
+
+// Requests from the broker on the PROXY topic, answered with the node key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ProxyRequest {
+    // signed with sphinx_auther::sign_message, like lightning signmessage
+    SignMessage(String),
+    // a v1 sphinx_auther::token::Token, for services that don't take v2 yet
+    AuthToken,
+    // a sphinx_auther::token::TokenV2 for one audience
+    AuthTokenV2(TokenRequest),
+}
+
+// what a TokenV2 from the PROXY topic is for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenRequest {
+    pub audience: String,
+    pub scopes: Vec<String>,
+    // secs until it expires, at most proxy::MAX_TOKEN_TTL
+    pub ttl: u32,
+}
+
+impl ProxyRequest {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProxyRequest::SignMessage(_) => "SignMessage",
+            ProxyRequest::AuthToken => "AuthToken",
+            ProxyRequest::AuthTokenV2(_) => "AuthTokenV2",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ProxyResponse {
+    // hex of the 65 byte recoverable signature
+    MessageSigned(String),
+    // base64 Token or TokenV2, as asked for
+    AuthToken(String),
+}
+
+// which ProxyRequests the signer answers, none by default
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ProxyPolicy {
+    pub sign_message: bool,
+    pub auth_token: bool,
+    // the audiences a TokenV2 can be issued for. A v1 token has none
+    #[serde(default)]
+    pub audiences: Vec<String>,
+}
+
+impl ProxyPolicy {
+    pub fn allows(&self, req: &ProxyRequest) -> bool {
+        match req {
+            ProxyRequest::SignMessage(_) => self.sign_message,
+            ProxyRequest::AuthToken => self.auth_token,
+            ProxyRequest::AuthTokenV2(tr) => {
+                self.auth_token && self.audiences.iter().any(|a| a == &tr.audience)
+            }
+        }
+    }
+}
+
+// One accepted control msg. The hash covers every other field and the
+// hash of the entry before it, so an edited or dropped entry breaks the chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AuditEntry {
+    // position in the log, starting at 0
+    pub seq: u64,
+    pub nonce: u64,
+    // the ControlMessage variant
+    pub kind: String,
+    // hex pubkey of the sender
+    pub admin: String,
+    // unix seconds, 0 if the signer has no clock
+    pub timestamp: u64,
+    // hex sha256, all zeros for the first entry
+    pub prev_hash: String,
+    pub hash: String,
+    // hex compact ecdsa signature of the hash by the signer's control key
+    pub sig: String,
+}
+
+// ordered from least to most privileged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // read only
+    Auditor,
+    // can change policy, allowlist and config
+    Operator,
+    // can also reset, OTA and manage admins
+    Owner,
+}
+
+impl FromStr for Role {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auditor" => Ok(Role::Auditor),
+            "operator" => Ok(Role::Operator),
+            "owner" => Ok(Role::Owner),
+            _ => Err("invalid role".to_string()),
+        }
+    }
+}
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Auditor => "auditor",
+            Role::Operator => "operator",
+            Role::Owner => "owner",
+        }
+    }
+    pub fn allows(&self, msg: &ControlMessage) -> bool {
+        *self >= msg.required_role()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Info {
+    // hex encoded
+    pub signer_id: String,
+    // hex encoded, empty if the signer has no node yet
+    pub node_id: String,
+    pub network: String,
+    // version of the crate that answered
+    pub version: String,
+    pub nonce: u64,
+    // "lowmemory", "no-std"
+    pub features: Vec<String>,
+    pub ota_version: Option<u64>,
+}
+
+// published by the signer on the HEARTBEAT topic
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Heartbeat {
+    // hex encoded node pubkey
+    pub node_id: String,
+    // the last VLS message sequence handled
+    pub sequence: Option<u16>,
+    // spent in the current velocity window
+    pub velocity_msat: u64,
+    pub msat_per_interval: u64,
+    pub channels: u64,
+    // false while VLS mutations are waiting for LSS
+    pub lss_synced: bool,
+    // seconds
+    pub uptime: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct WifiParams {
     pub ssid: String,
     pub password: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::types::*;
+
+    #[test]
+    fn test_interval_from_str() {
+        for int in [
+            Interval::Hourly,
+            Interval::Daily,
+            Interval::Weekly,
+            Interval::Monthly,
+        ] {
+            assert_eq!(Interval::from_str(int.as_str()), Ok(int));
+        }
+        for int in [
+            Interval::Hourly,
+            Interval::Daily,
+            Interval::Weekly,
+            Interval::Monthly,
+            Interval::Custom(1),
+            Interval::Custom(600),
+        ] {
+            assert_eq!(Interval::from_str(&int.to_string()), Ok(int));
+        }
+        assert_eq!(Interval::Custom(600).to_string(), "custom:600");
+        assert_eq!(Interval::from_str("custom:600"), Ok(Interval::Custom(600)));
+        assert!(Interval::from_str("custom:0").is_err());
+        assert!(Interval::from_str("custom").is_err());
+        assert!(Interval::from_str("yearly").is_err());
+    }
+
+    #[test]
+    fn test_role_allows() {
+        let auditor = Role::Auditor;
+        assert!(auditor.allows(&ControlMessage::QueryAll));
+        assert!(auditor.allows(&ControlMessage::Nonce));
+        assert!(!auditor.allows(&ControlMessage::ResetAll));
+        assert!(!auditor.allows(&ControlMessage::UpdatePolicy(Policy::default())));
+        let operator = Role::Operator;
+        assert!(operator.allows(&ControlMessage::UpdatePolicy(Policy::default())));
+        assert!(operator.allows(&ControlMessage::ApprovePending(1)));
+        assert!(!operator.allows(&ControlMessage::ResetKeys));
+        assert!(!operator.allows(&ControlMessage::RemoveAdmin("02".to_string())));
+        let owner = Role::Owner;
+        assert!(owner.allows(&ControlMessage::ResetAll));
+        assert!(owner.allows(&ControlMessage::QueryAll));
+        // a batch needs the highest role of its msgs
+        let batch = ControlMessage::Batch(vec![
+            ControlMessage::QueryAll,
+            ControlMessage::UpdatePolicy(Policy::default()),
+        ]);
+        assert_eq!(batch.required_role(), Role::Operator);
+        assert!(!auditor.allows(&batch));
+        assert!(operator.allows(&batch));
+        for role in [Role::Auditor, Role::Operator, Role::Owner] {
+            assert_eq!(Role::from_str(role.as_str()), Ok(role));
+        }
+    }
+
+    #[test]
+    fn test_config_redacted() {
+        let c = Config {
+            broker: "localhost:1883".to_string(),
+            ssid: "sphinx".to_string(),
+            pass: "password".to_string(),
+            network: "regtest".to_string(),
+        };
+        let r = c.redacted();
+        assert_eq!(r.pass, REDACTED);
+        assert_eq!(r.broker, c.broker);
+        assert_eq!(r.ssid, c.ssid);
+        assert_eq!(Config::default().redacted().pass, "");
+    }
+
+    #[test]
+    fn test_check_allowlist_entry() {
+        for good in [
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ",
+            "address:tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+            "bcrt1qsdkn6qm4hvls3s7gr4nxwpk7aa3asxfdsq5xt8",
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "xpub:xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+            "payee:0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        ] {
+            assert_eq!(check_allowlist_entry(good), Ok(()), "{}", good);
+        }
+        for bad in [
+            "",
+            "alice",
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdQ",
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdb",
+            "ltc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "0BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+            "xpub:xpub661MyMwAqRbc",
+            "payee:0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "payee:",
+            "node:0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        ] {
+            assert!(check_allowlist_entry(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use crate::msgs::*;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Error as AnyErr, Result};
 use lightning_signer::persist::ExternalPersistHelper;
 use lightning_signer::persist::Mutations;
 use secp256k1::PublicKey;
+use sphinx_glyph::error::{Error as GlyphError, ErrorCode};
 use std::collections::BTreeMap;
 use vls_protocol_signer::handler::{HandlerBuilder, InitHandler};
 use vls_protocol_signer::lightning_signer;
@@ -103,7 +104,7 @@ impl LssSigner {
                     .to_vec(),
             );
             if !success {
-                return Err(anyhow!("invalid server hmac"));
+                return Err(hmac_mismatch());
             }
         }
 
@@ -181,7 +182,7 @@ pub fn handle_lss_msg(
         Msg::Created(bm) => {
             // dont need to check muts if theyre empty
             if !bm.muts.is_empty() && !lss_signer.check_hmac(bm) {
-                return Err(anyhow!("Invalid server hmac"));
+                return Err(hmac_mismatch());
             }
             let bs = lss_signer.empty_created();
             Ok((topics::INIT_2_RES.to_string(), bs))
@@ -197,7 +198,7 @@ pub fn handle_lss_msg(
             if server_hmac == shmac {
                 Ok((topics::VLS_RES.to_string(), previous.0))
             } else {
-                Err(anyhow!("Invalid server hmac"))
+                Err(hmac_mismatch())
             }
         }
         Msg::PutConflict => {
@@ -206,3 +207,10 @@ pub fn handle_lss_msg(
         }
     }
 }
+
+fn hmac_mismatch() -> AnyErr {
+    AnyErr::msg(GlyphError::with_code(
+        ErrorCode::HmacMismatch,
+        "invalid server hmac",
+    ))
+}
@@ -118,7 +118,8 @@ pub fn deserialize_string_vec(bytes: &mut Bytes, field_name: Option<&str>) -> Re
     deserialize_field_name(bytes, field_name)?;
     let length =
         decode::read_array_len(bytes).map_err(|_| Error::msg("could not read array length"))?;
-    let mut list: Vec<String> = Vec::with_capacity(length as usize);
+    // the length is untrusted, so the list grows as strings are read
+    let mut list: Vec<String> = Vec::new();
     for _ in 0..length {
         let e = deserialize_raw_string(bytes)?;
         list.push(e);
@@ -138,6 +139,18 @@ pub fn deserialize_uint(bytes: &mut Bytes, field_name: Option<&str>) -> Result<u
     Ok(object)
 }
 
+pub fn serialize_bool(buff: &mut ByteBuf, field_name: Option<&str>, object: bool) -> Result<()> {
+    serialize_field_name(buff, field_name)?;
+    encode::write_bool(buff, object).map_err(Error::msg)?;
+    Ok(())
+}
+
+pub fn deserialize_bool(bytes: &mut Bytes, field_name: Option<&str>) -> Result<bool> {
+    deserialize_field_name(bytes, field_name)?;
+    let object = decode::read_bool(bytes).map_err(|_| Error::msg("could not read bool"))?;
+    Ok(object)
+}
+
 pub fn serialize_bin(buff: &mut ByteBuf, field_name: Option<&str>, object: &[u8]) -> Result<()> {
     serialize_field_name(buff, field_name)?;
     encode::write_bin(buff, object).map_err(Error::msg)?;
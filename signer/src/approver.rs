@@ -1,35 +1,388 @@
-use crate::root::policy_interval;
+use crate::clock::SyncClock;
+use crate::root::{interval_buckets, policy_interval};
 use sphinx_glyph::types;
-use types::{Policy, Velocity};
+use types::{ControlMessage, ControlResponse, Pending, PendingKind, Policy, Velocity};
 
+use core::sync::atomic::{AtomicU64, Ordering};
+use lightning_signer::bitcoin::{Transaction, TxOut};
+use lightning_signer::invoice::{Invoice, InvoiceAttributes};
+use lightning_signer::lightning::ln::PaymentHash;
+use lightning_signer::prelude::{Mutex, SendSync};
 use lightning_signer::util::clock::Clock;
 use lightning_signer::util::velocity::{VelocityControl, VelocityControlSpec};
 use lightning_signer::Arc;
-use vls_protocol_signer::approver::{NegativeApprover, VelocityApprover};
+use vls_protocol_signer::approver::{Approve, VelocityApprover};
 use vls_protocol_signer::lightning_signer;
 
-pub type SphinxApprover = VelocityApprover<NegativeApprover>;
+// payments within the velocity budget are approved,
+// anything over it is parked in the pending queue
+pub struct SphinxApprover {
+    inner: VelocityApprover<PendingApprover>,
+    pending: PendingApprover,
+    // only set for no-native signers
+    sync_clock: Option<Arc<SyncClock>>,
+    // the most a single HTLC can carry, updated with the policy
+    htlc_limit_msat: AtomicU64,
+}
+
+impl SendSync for SphinxApprover {}
+
+impl SphinxApprover {
+    pub fn control(&self) -> VelocityControl {
+        self.inner.control()
+    }
+    pub fn set_control(&self, control: VelocityControl) {
+        self.inner.set_control(control)
+    }
+    pub fn pending(&self) -> &PendingApprover {
+        &self.pending
+    }
+    pub fn sync_clock(&self) -> Option<&Arc<SyncClock>> {
+        self.sync_clock.as_ref()
+    }
+    pub fn set_sync_clock(&mut self, clock: Option<Arc<SyncClock>>) {
+        self.sync_clock = clock
+    }
+    pub fn htlc_limit_msat(&self) -> u64 {
+        self.htlc_limit_msat.load(Ordering::SeqCst)
+    }
+    pub fn set_htlc_limit_msat(&self, limit: u64) {
+        self.htlc_limit_msat.store(limit, Ordering::SeqCst)
+    }
+}
+
+impl Approve for SphinxApprover {
+    fn approve_invoice(&self, invoice: &Invoice) -> bool {
+        self.inner.approve_invoice(invoice)
+    }
+    fn approve_keysend(&self, payment_hash: PaymentHash, amount_msat: u64) -> bool {
+        self.inner.approve_keysend(payment_hash, amount_msat)
+    }
+    fn approve_onchain(
+        &self,
+        tx: &Transaction,
+        prev_outs: &[TxOut],
+        unknown_indices: &[usize],
+    ) -> bool {
+        self.inner.approve_onchain(tx, prev_outs, unknown_indices)
+    }
+}
+
+// the most payments kept waiting for approval
+pub const MAX_PENDING: usize = 100;
+
+// The delegate of the VelocityApprover. A payment is queued the first time
+// it is seen. Once approved by an admin, the next attempt with the same
+// payment hash goes through. A full queue drops its oldest entry.
+#[derive(Clone, Default)]
+pub struct PendingApprover(Arc<Mutex<Vec<Pending>>>);
+
+impl SendSync for PendingApprover {}
+
+impl PendingApprover {
+    pub fn new(mut initial: Vec<Pending>) -> Self {
+        while initial.len() > MAX_PENDING {
+            evict(&mut initial);
+        }
+        Self(Arc::new(Mutex::new(initial)))
+    }
+    pub fn list(&self) -> Vec<Pending> {
+        self.0.lock().unwrap().clone()
+    }
+    pub fn contains(&self, id: u64) -> bool {
+        self.0.lock().unwrap().iter().any(|p| p.id == id)
+    }
+    pub fn approve(&self, id: u64) -> anyhow::Result<()> {
+        let mut queue = self.0.lock().unwrap();
+        match queue.iter_mut().find(|p| p.id == id) {
+            Some(p) => {
+                p.approved = true;
+                Ok(())
+            }
+            None => Err(anyhow::anyhow!("no pending payment {}", id)),
+        }
+    }
+    pub fn decline(&self, id: u64) -> anyhow::Result<()> {
+        let mut queue = self.0.lock().unwrap();
+        let len = queue.len();
+        queue.retain(|p| p.id != id);
+        if queue.len() == len {
+            return Err(anyhow::anyhow!("no pending payment {}", id));
+        }
+        Ok(())
+    }
+    fn check(&self, kind: PendingKind, payment_hash: &[u8; 32], amount_msat: u64) -> bool {
+        let hash = hex::encode(payment_hash);
+        let mut queue = self.0.lock().unwrap();
+        if let Some(i) = queue.iter().position(|p| p.payment_hash == hash) {
+            // the approval only covers what the admin saw
+            let p = &queue[i];
+            if p.approved && p.kind == kind && amount_msat <= p.amount_msat {
+                queue.remove(i);
+                return true;
+            }
+            if p.approved {
+                log::warn!(
+                    "payment {} was approved for {} msat, not {}",
+                    hash,
+                    p.amount_msat,
+                    amount_msat
+                );
+            }
+            return false;
+        }
+        let id = queue.iter().map(|p| p.id).max().map_or(1, |id| id + 1);
+        if queue.len() >= MAX_PENDING {
+            evict(&mut queue);
+        }
+        log::info!("payment {} queued for approval as #{}", hash, id);
+        queue.push(Pending {
+            id,
+            kind,
+            payment_hash: hash,
+            amount_msat,
+            approved: false,
+        });
+        false
+    }
+}
+
+// drop the oldest payment nobody approved yet, or else the oldest one
+fn evict(queue: &mut Vec<Pending>) {
+    let i = queue.iter().position(|p| !p.approved).unwrap_or(0);
+    let p = queue.remove(i);
+    log::warn!("pending queue full, dropped payment #{}", p.id);
+}
+
+impl Approve for PendingApprover {
+    fn approve_invoice(&self, invoice: &Invoice) -> bool {
+        self.check(
+            PendingKind::Invoice,
+            invoice.invoice_payment_hash(),
+            invoice.invoice_amount_msat(),
+        )
+    }
+    fn approve_keysend(&self, payment_hash: PaymentHash, amount_msat: u64) -> bool {
+        self.check(PendingKind::Keysend, &payment_hash.0, amount_msat)
+    }
+    fn approve_onchain(
+        &self,
+        _tx: &Transaction,
+        _prev_outs: &[TxOut],
+        _unknown_indices: &[usize],
+    ) -> bool {
+        false
+    }
+}
 
 pub fn approver_control(
     initial_policy: Policy,
     initial_velocity: Option<Velocity>,
 ) -> VelocityControl {
-    let spec = VelocityControlSpec {
-        limit_msat: initial_policy.msat_per_interval,
-        interval_type: policy_interval(initial_policy.interval),
-    };
-    match initial_velocity {
-        Some(v) => VelocityControl::load_from_state(spec, v),
-        None => VelocityControl::new(spec),
+    let limit_msat = initial_policy.msat_per_interval;
+    if let Some(interval_type) = policy_interval(initial_policy.interval) {
+        let spec = VelocityControlSpec {
+            limit_msat,
+            interval_type,
+        };
+        return match initial_velocity {
+            Some(v) => VelocityControl::load_from_state(spec, v),
+            None => VelocityControl::new(spec),
+        };
+    }
+    let (bucket_interval, num_buckets) = interval_buckets(initial_policy.interval);
+    let mut control = VelocityControl::new_with_intervals(limit_msat, bucket_interval, num_buckets);
+    if let Some((start_sec, buckets)) = initial_velocity {
+        // a state saved under a different window is dropped
+        if buckets.len() == num_buckets {
+            control.start_sec = start_sec;
+            control.buckets = buckets;
+        }
+    }
+    control
+}
+
+// Carry the spend history of `old` into `new`. With the same window the
+// buckets are kept as they are. Otherwise each old bucket is moved into the
+// new bucket covering its start time, rounding towards the newest bucket so
+// that no spend expires earlier than it would have before.
+pub fn migrate_control(old: &VelocityControl, mut new: VelocityControl) -> VelocityControl {
+    new.start_sec = old.start_sec;
+    if new.bucket_interval == old.bucket_interval && new.buckets.len() == old.buckets.len() {
+        new.buckets = old.buckets.clone();
+        return new;
     }
+    let old_interval = old.bucket_interval as u64;
+    let new_interval = new.bucket_interval as u64;
+    for (i, amt) in old.buckets.iter().enumerate() {
+        let j = (i as u64 * old_interval / new_interval) as usize;
+        if let Some(b) = new.buckets.get_mut(j) {
+            *b = b.saturating_add(*amt);
+        }
+    }
+    new
 }
 
 pub fn create_approver(
     clock: Arc<dyn Clock>,
     initial_policy: Policy,
     initial_velocity: Option<Velocity>,
+    initial_pending: Vec<Pending>,
 ) -> SphinxApprover {
-    let delegate = NegativeApprover();
+    let pending = PendingApprover::new(initial_pending);
+    let htlc_limit_msat = AtomicU64::new(initial_policy.htlc_limit_msat);
     let control = approver_control(initial_policy, initial_velocity);
-    VelocityApprover::new(clock.clone(), control, delegate)
+    SphinxApprover {
+        inner: VelocityApprover::new(clock.clone(), control, pending.clone()),
+        pending,
+        sync_clock: None,
+        htlc_limit_msat,
+    }
+}
+
+// apply ListPending, ApprovePending and DeclinePending to the queue
+pub fn update_pending(pending: &PendingApprover, msg: &ControlMessage) -> Option<ControlResponse> {
+    let res = match msg {
+        ControlMessage::ListPending => ControlResponse::PendingList(pending.list()),
+        ControlMessage::ApprovePending(id) => match pending.approve(*id) {
+            Ok(()) => ControlResponse::PendingApproved(*id),
+            Err(e) => ControlResponse::Error(format!("approve pending failed {:?}", e)),
+        },
+        ControlMessage::DeclinePending(id) => match pending.decline(*id) {
+            Ok(()) => ControlResponse::PendingDeclined(*id),
+            Err(e) => ControlResponse::Error(format!("decline pending failed {:?}", e)),
+        },
+        _ => return None,
+    };
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightning_signer::util::clock::ManualClock;
+    use std::time::Duration;
+    use types::Interval;
+
+    fn policy(msat_per_interval: u64, interval: Interval) -> Policy {
+        Policy {
+            msat_per_interval,
+            interval,
+            htlc_limit_msat: msat_per_interval,
+        }
+    }
+
+    #[test]
+    fn test_migrate_same_interval() {
+        let mut old = approver_control(policy(10_000, Interval::Daily), None);
+        assert!(old.insert(1_000_000, 8_000));
+        // lowering the limit does not reset the budget
+        let mut new = migrate_control(&old, approver_control(policy(9_000, Interval::Daily), None));
+        assert_eq!(new.get_state(), old.get_state());
+        assert!(!new.insert(1_000_010, 2_000));
+        assert!(new.insert(1_000_010, 1_000));
+    }
+
+    #[test]
+    fn test_migrate_interval_change() {
+        let mut old = approver_control(policy(10_000, Interval::Daily), None);
+        assert!(old.insert(1_000_000, 8_000));
+        for int in [Interval::Hourly, Interval::Weekly, Interval::Custom(600)] {
+            let mut new = migrate_control(&old, approver_control(policy(10_000, int), None));
+            assert!(!new.insert(1_000_010, 3_000));
+            assert!(new.insert(1_000_010, 2_000));
+        }
+        // spend older than the new window is dropped
+        let mut old = approver_control(policy(10_000, Interval::Weekly), None);
+        assert!(old.insert(1_000_000, 8_000));
+        let mut new = migrate_control(
+            &old,
+            approver_control(policy(10_000, Interval::Custom(600)), None),
+        );
+        assert!(new.insert(1_000_000 + 2 * 24 * 60 * 60, 10_000));
+    }
+
+    #[test]
+    fn test_set_policy_keeps_spend() {
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000_000)));
+        let approver = create_approver(clock, policy(10_000, Interval::Daily), None, Vec::new());
+        assert!(approver.approve_keysend(PaymentHash([1; 32]), 8_000));
+        let mut store = sphinx_glyph::control::DummyPersister;
+        crate::policy::set_approver_policy(&approver, policy(9_000, Interval::Daily), &mut store)
+            .expect("set policy failed");
+        // only 1_000 left, so this one is queued
+        assert!(!approver.approve_keysend(PaymentHash([2; 32]), 2_000));
+        assert_eq!(approver.pending().list().len(), 1);
+        assert!(approver.approve_keysend(PaymentHash([3; 32]), 1_000));
+    }
+
+    #[test]
+    fn test_pending_keysend() {
+        let pa = PendingApprover::default();
+        let hash = PaymentHash([1; 32]);
+        // first attempt is parked, not approved
+        assert!(!pa.approve_keysend(hash, 5_000));
+        assert!(!pa.approve_keysend(hash, 5_000));
+        let list = pa.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, 1);
+        assert_eq!(list[0].kind, PendingKind::Keysend);
+        assert_eq!(list[0].amount_msat, 5_000);
+        assert!(!list[0].approved);
+        // approved once, then removed from the queue
+        pa.approve(1).expect("approve failed");
+        assert!(pa.approve_keysend(hash, 5_000));
+        assert!(pa.list().is_empty());
+        assert!(!pa.approve_keysend(hash, 5_000));
+    }
+
+    #[test]
+    fn test_pending_amount() {
+        let pa = PendingApprover::default();
+        let hash = PaymentHash([1; 32]);
+        assert!(!pa.approve_keysend(hash, 1_000_000));
+        pa.approve(1).expect("approve failed");
+        // a retry for more than was approved is refused, and keeps the entry
+        assert!(!pa.approve_keysend(hash, 1_000_001));
+        assert_eq!(pa.list().len(), 1);
+        assert!(pa.approve_keysend(hash, 1_000_000));
+        assert!(pa.list().is_empty());
+    }
+
+    #[test]
+    fn test_pending_decline() {
+        let pa = PendingApprover::default();
+        assert!(!pa.approve_keysend(PaymentHash([1; 32]), 1_000));
+        assert!(!pa.approve_keysend(PaymentHash([2; 32]), 2_000));
+        let res = update_pending(&pa, &ControlMessage::DeclinePending(1));
+        assert_eq!(res, Some(ControlResponse::PendingDeclined(1)));
+        let list = pa.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, 2);
+        assert!(pa.decline(1).is_err());
+        assert!(pa.approve(1).is_err());
+        assert!(update_pending(&pa, &ControlMessage::QueryAll).is_none());
+    }
+
+    #[test]
+    fn test_pending_cap() {
+        let pa = PendingApprover::default();
+        for i in 0..MAX_PENDING {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            assert!(!pa.approve_keysend(PaymentHash(hash), 1_000));
+        }
+        pa.approve(1).expect("approve failed");
+        // the oldest unapproved payment makes room for the new one
+        assert!(!pa.approve_keysend(PaymentHash([0xff; 32]), 1_000));
+        let list = pa.list();
+        assert_eq!(list.len(), MAX_PENDING);
+        assert_eq!(list[0].id, 1);
+        assert_eq!(list[1].id, 3);
+        assert_eq!(list[MAX_PENDING - 1].id, MAX_PENDING as u64 + 1);
+        // a restored queue is capped too
+        let mut restored = list.clone();
+        restored.extend(list);
+        assert_eq!(PendingApprover::new(restored).list().len(), MAX_PENDING);
+    }
 }
@@ -0,0 +1,78 @@
+use lightning_signer::prelude::{Mutex, SendSync};
+use lightning_signer::util::clock::Clock;
+use std::time::Duration;
+use vls_protocol_signer::lightning_signer;
+
+// broker and signer times further apart than this are logged
+pub const SKEW_WARN_SECS: u64 = 120;
+
+// A manual clock for signers without a RTC. It is set from SetTime control
+// msgs, and only moves forward unless forced.
+pub struct SyncClock(Mutex<Duration>);
+
+impl SendSync for SyncClock {}
+
+impl Clock for SyncClock {
+    fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl SyncClock {
+    pub fn new(now: Duration) -> Self {
+        Self(Mutex::new(now))
+    }
+    // whether set_time would take this time, without setting it
+    pub fn check_time(&self, secs: u64, force: bool) -> anyhow::Result<()> {
+        check_time(self.now().as_secs(), secs, force)
+    }
+    // returns how far the clock moved, negative if it was forced back
+    pub fn set_time(&self, secs: u64, force: bool) -> anyhow::Result<i64> {
+        let mut now = self.0.lock().unwrap();
+        let current = now.as_secs();
+        let skew = secs as i64 - current as i64;
+        if skew.unsigned_abs() > SKEW_WARN_SECS {
+            log::warn!(
+                "clock skew of {}s between broker ({}) and signer ({})",
+                skew,
+                secs,
+                current
+            );
+        }
+        check_time(current, secs, force)?;
+        *now = Duration::from_secs(secs);
+        Ok(skew)
+    }
+}
+
+// the clock only moves forward, unless forced
+pub fn check_time(current: u64, secs: u64, force: bool) -> anyhow::Result<()> {
+    if secs < current && !force {
+        return Err(anyhow::anyhow!(
+            "time {} is before the signer time {}",
+            secs,
+            current
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_clock() {
+        let clock = SyncClock::new(Duration::from_secs(1_000));
+        assert_eq!(clock.set_time(1_500, false).unwrap(), 500);
+        assert_eq!(clock.now().as_secs(), 1_500);
+        // never back, unless forced
+        assert!(clock.check_time(1_400, false).is_err());
+        clock.check_time(1_400, true).unwrap();
+        assert!(clock.set_time(1_400, false).is_err());
+        assert_eq!(clock.now().as_secs(), 1_500);
+        assert_eq!(clock.set_time(1_500, false).unwrap(), 0);
+        assert_eq!(clock.set_time(1_400, true).unwrap(), -100);
+        assert_eq!(clock.now().as_secs(), 1_400);
+    }
+}
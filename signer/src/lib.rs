@@ -1,4 +1,5 @@
 pub mod approver;
+pub mod clock;
 pub mod derive;
 #[cfg(not(feature = "lowmemory"))]
 pub mod mobile;
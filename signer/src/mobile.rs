@@ -1,4 +1,4 @@
-use crate::approver::SphinxApprover;
+use crate::approver::{update_pending, PendingApprover, SphinxApprover};
 use crate::kvv::{CloudKVVStore, KVVPersister, MemoryKVVStore, RmpFormat};
 use crate::root::{builder_inner, handle_init, handle_with_lss};
 use anyhow::{Error, Result};
@@ -11,8 +11,11 @@ use lightning_signer::Arc;
 use lss_connector::secp256k1::PublicKey;
 use lss_connector::{handle_lss_msg, LssSigner, Msg};
 use serde::{Deserialize, Serialize};
+use sphinx_glyph::proxy::handle_proxy;
 use sphinx_glyph::topics;
-use sphinx_glyph::types::{Policy, Velocity};
+use sphinx_glyph::types::{
+    ControlMessage, ControlResponse, Pending, Policy, PolicyFilter, ProxyPolicy, Velocity,
+};
 use std::collections::BTreeMap;
 use std::time::Duration;
 use vls_protocol_signer::handler::{HandlerBuilder, InitHandler, RootHandler};
@@ -42,10 +45,18 @@ pub struct Args {
     seed: [u8; 32],
     network: Network,
     policy: Policy,
+    #[serde(default)]
+    policy_filter: PolicyFilter,
     allowlist: Vec<String>,
     timestamp: u64, // number of seconds
     lss_nonce: [u8; 32],
     signer_id: [u8; 16],
+    // payments waiting for approval, kept by the app
+    #[serde(default)]
+    pending: Vec<Pending>,
+    // which PROXY requests are answered, none by default
+    #[serde(default)]
+    proxy_policy: ProxyPolicy,
 }
 
 pub type State = BTreeMap<String, (u64, Vec<u8>)>;
@@ -59,6 +70,7 @@ pub struct RunReturn {
     pub cmd: String,
     pub velocity: Option<Velocity>,
     pub server_hmac: Option<[u8; 32]>,
+    pub pending: Option<Vec<Pending>>,
 }
 
 pub fn run_init_1(
@@ -132,9 +144,16 @@ pub fn run_vls(
     let (_res, rh, approver, lss_signer) =
         run_init_3(args, state, lss_msg1, lss_msg2, lss_msg3, velocity)?;
     let s1 = approver.control().get_state();
-    let (vls_res, lss_res, sequence, cmd, server_hmac) =
-        handle_with_lss(&rh, &lss_signer, vls_msg.to_vec(), expected_sequence, true)
-            .map_err(Error::msg)?;
+    let p1 = approver.pending().list();
+    let (vls_res, lss_res, sequence, cmd, server_hmac) = handle_with_lss(
+        &rh,
+        &approver,
+        &lss_signer,
+        vls_msg.to_vec(),
+        expected_sequence,
+        true,
+    )
+    .map_err(Error::msg)?;
     let mut ret = if lss_res.is_empty() {
         RunReturn::new_vls(topics::VLS_RES, vls_res, sequence, cmd)
     } else {
@@ -151,6 +170,10 @@ pub fn run_vls(
     if s1 != s2 {
         ret.set_velocity(s2);
     }
+    let p2 = approver.pending().list();
+    if p1 != p2 {
+        ret.set_pending(p2);
+    }
     // rh.commit();
     Ok(ret)
 }
@@ -179,6 +202,19 @@ pub fn run_lss(
     Ok(ret)
 }
 
+// a PROXY request from the broker, answered with the node key.
+// the response bytes are in vls_bytes
+pub fn run_proxy(args: Args, proxy_msg: &[u8]) -> Result<RunReturn> {
+    let (_, sk) = crate::derive_node_keys(&args.network, &args.seed);
+    let (req, res) = handle_proxy(proxy_msg, &sk, &args.proxy_policy, args.timestamp)?;
+    Ok(RunReturn::new_vls(
+        topics::PROXY_RES,
+        res,
+        u16::default(),
+        req.kind().to_string(),
+    ))
+}
+
 fn handler_builder(
     args: Args,
     state: State,
@@ -207,11 +243,14 @@ fn handler_builder(
         args.seed,
         args.network,
         args.policy,
+        args.policy_filter,
         args.allowlist,
         velocity,
+        args.pending,
         persister,
         clock,
         stf,
+        None,
     )?;
     // let muts = tmp.prepare();
     // if !muts.is_empty() {
@@ -237,6 +276,7 @@ impl RunReturn {
             cmd,
             velocity: None,
             server_hmac,
+            pending: None,
         }
     }
     pub fn new_vls(topic: &str, vls_bytes: Vec<u8>, sequence: u16, cmd: String) -> Self {
@@ -248,6 +288,7 @@ impl RunReturn {
             cmd,
             velocity: None,
             server_hmac: None,
+            pending: None,
         }
     }
     pub fn new_lss(topic: &str, lss_bytes: Vec<u8>, cmd: String) -> Self {
@@ -259,11 +300,27 @@ impl RunReturn {
             cmd,
             velocity: None,
             server_hmac: None,
+            pending: None,
         }
     }
     pub fn set_velocity(&mut self, velocity: Velocity) {
         self.velocity = Some(velocity);
     }
+    pub fn set_pending(&mut self, pending: Vec<Pending>) {
+        self.pending = Some(pending);
+    }
+}
+
+// ListPending, ApprovePending or DeclinePending against the app's queue,
+// returning the updated queue to be stored for the next run
+pub fn handle_pending(
+    pending: Vec<Pending>,
+    msg: &ControlMessage,
+) -> Result<(ControlResponse, Vec<Pending>)> {
+    let pa = PendingApprover::new(pending);
+    let res =
+        update_pending(&pa, msg).ok_or_else(|| anyhow::anyhow!("not a pending control message"))?;
+    Ok((res, pa.list()))
 }
 
 pub struct NowClock(Duration);
@@ -406,10 +463,13 @@ mod tests {
             seed: [1; 32],
             network: Network::Regtest,
             policy: Default::default(),
+            policy_filter: Default::default(),
             allowlist: vec![],
             timestamp: ts.as_secs(),
             lss_nonce: [32; 32],
             signer_id: [9; 16],
+            pending: vec![],
+            proxy_policy: Default::default(),
         }
     }
 
@@ -1,25 +1,34 @@
-use sphinx_glyph::types::Policy;
+use sphinx_glyph::types::{Policy, PolicyFilter};
 
+use lightning_signer::node::Allowable;
 use lightning_signer::persist::Mutations;
 use lightning_signer::policy::simple_validator::SimpleValidatorFactory;
 use lightning_signer::Arc;
-use sphinx_glyph::control::{All, ControlMessage, ControlResponse};
+use sphinx_glyph::control::{All, ControlMessage, ControlPersist, ControlResponse};
 use vls_protocol_signer::handler::{Handler, RootHandler};
 use vls_protocol_signer::lightning_signer;
 use vls_protocol_signer::lightning_signer::bitcoin::Network;
 
-use crate::approver::{approver_control, SphinxApprover};
+use crate::approver::{approver_control, migrate_control, update_pending, SphinxApprover};
+use crate::clock::check_time;
+use lightning_signer::util::clock::Clock;
 
 pub fn update_controls(
     rh: &RootHandler,
     msg: ControlMessage,
     mut res: ControlResponse,
     approver: &SphinxApprover,
+    store: &mut dyn ControlPersist,
 ) -> (ControlResponse, Option<Mutations>) {
     let mut muts = None;
     match msg {
         ControlMessage::UpdatePolicy(new_policy) => {
-            if let Err(e) = set_approver_policy(approver, new_policy) {
+            let network = rh.node().network();
+            let filter = store.read_policy_filter().unwrap_or_default();
+            if let Err(e) = set_policy(rh, network, new_policy.clone(), filter) {
+                log::error!("set policy failed {:?}", e);
+                res = ControlResponse::Error(format!("set policy failed {:?}", e))
+            } else if let Err(e) = set_approver_policy(approver, new_policy, store) {
                 log::error!("set policy failed {:?}", e);
                 res = ControlResponse::Error(format!("set policy failed {:?}", e))
             }
@@ -33,6 +42,26 @@ pub fn update_controls(
                 res = ControlResponse::Error(format!("set allowlist failed {:?}", e))
             }
         },
+        ControlMessage::AllowlistAdd(add) => match add_allowlist(rh, &add) {
+            Ok((muts_, al)) => {
+                muts = Some(muts_);
+                res = ControlResponse::AllowlistUpdated(al);
+            }
+            Err(e) => {
+                log::error!("add allowlist failed {:?}", e);
+                res = ControlResponse::Error(format!("add allowlist failed {:?}", e))
+            }
+        },
+        ControlMessage::AllowlistRemove(rm) => match remove_allowlist(rh, &rm) {
+            Ok((muts_, al)) => {
+                muts = Some(muts_);
+                res = ControlResponse::AllowlistUpdated(al);
+            }
+            Err(e) => {
+                log::error!("remove allowlist failed {:?}", e);
+                res = ControlResponse::Error(format!("remove allowlist failed {:?}", e))
+            }
+        },
         ControlMessage::QueryAllowlist => match get_allowlist(rh) {
             Ok(al) => res = ControlResponse::AllowlistCurrent(al),
             Err(e) => {
@@ -57,11 +86,133 @@ pub fn update_controls(
                 res = ControlResponse::Error(format!("read allowlist failed {:?}", e))
             }
         },
+        ControlMessage::UpdatePolicyFilter(new_filter) => {
+            let network = rh.node().network();
+            let policy = store.read_policy().unwrap_or_default();
+            if let Err(e) = set_policy(rh, network, policy, new_filter) {
+                log::error!("set policy filter failed {:?}", e);
+                res = ControlResponse::Error(format!("set policy filter failed {:?}", e))
+            }
+        }
+        ControlMessage::QueryInfo => {
+            if let ControlResponse::InfoCurrent(mut info) = res {
+                info.network = rh.node().network().to_string();
+                info.version = env!("CARGO_PKG_VERSION").to_string();
+                res = ControlResponse::InfoCurrent(info);
+            } else {
+                res = ControlResponse::Error("wrong ControlResponse type".to_string())
+            }
+        }
+        ControlMessage::Batch(msgs) => {
+            if let Err(e) = check_batch(rh, approver, &msgs) {
+                // nothing was applied
+                log::error!("batch refused {:?}", e);
+                res = ControlResponse::Error(format!("batch refused {:?}", e))
+            } else if let ControlResponse::Batch(ress) = res {
+                let mut batch_muts = Vec::new();
+                let mut batch_ress = Vec::with_capacity(ress.len());
+                for (m, r) in msgs.into_iter().zip(ress) {
+                    let (r2, muts_) = update_controls(rh, m, r, approver, store);
+                    if let Some(muts_) = muts_ {
+                        batch_muts.extend(muts_.into_inner());
+                    }
+                    batch_ress.push(r2);
+                }
+                if !batch_muts.is_empty() {
+                    muts = Some(Mutations::from_vec(batch_muts));
+                }
+                res = ControlResponse::Batch(batch_ress);
+            } else {
+                res = ControlResponse::Error("wrong ControlResponse type".to_string())
+            }
+        }
+        ControlMessage::SetTime(secs) => {
+            res = set_time(approver, secs, false, store);
+        }
+        ControlMessage::ForceTime(secs) => {
+            res = set_time(approver, secs, true, store);
+        }
+        ControlMessage::ListPending
+        | ControlMessage::ApprovePending(_)
+        | ControlMessage::DeclinePending(_) => {
+            if let Some(r) = update_pending(approver.pending(), &msg) {
+                res = r;
+            }
+        }
         _ => (),
     }
     (res, muts)
 }
 
+// Refuses a msg the signer would fail to apply. A Batch is checked as a
+// whole, so none of it is applied if any msg in it would fail. Pass it to
+// Controller::handle_checked, so the glyph store is not changed either
+pub fn check_controls(
+    rh: &RootHandler,
+    approver: &SphinxApprover,
+    msg: &ControlMessage,
+) -> anyhow::Result<()> {
+    match msg {
+        ControlMessage::Batch(msgs) => check_batch(rh, approver, msgs),
+        m => check_batch(rh, approver, std::slice::from_ref(m)),
+    }
+}
+
+// each msg is checked against the state left by the ones before it
+fn check_batch(
+    rh: &RootHandler,
+    approver: &SphinxApprover,
+    msgs: &[ControlMessage],
+) -> anyhow::Result<()> {
+    let mut time = approver.sync_clock().map(|c| c.now().as_secs());
+    let mut declined = Vec::new();
+    for (i, msg) in msgs.iter().enumerate() {
+        let res = match msg {
+            ControlMessage::UpdateAllowlist(entries)
+            | ControlMessage::AllowlistAdd(entries)
+            | ControlMessage::AllowlistRemove(entries) => check_allowlist(rh, entries),
+            ControlMessage::SetTime(secs) | ControlMessage::ForceTime(secs) => {
+                let force = matches!(msg, ControlMessage::ForceTime(_));
+                match time {
+                    Some(current) => {
+                        time = Some(*secs);
+                        check_time(current, *secs, force)
+                    }
+                    None => Err(anyhow::anyhow!("the signer has a native clock")),
+                }
+            }
+            ControlMessage::ApprovePending(id) | ControlMessage::DeclinePending(id) => {
+                if !approver.pending().contains(*id) || declined.contains(id) {
+                    Err(anyhow::anyhow!("no pending payment {}", id))
+                } else {
+                    if let ControlMessage::DeclinePending(_) = msg {
+                        declined.push(*id);
+                    }
+                    Ok(())
+                }
+            }
+            ControlMessage::Batch(_) => Err(anyhow::anyhow!("nested Batch")),
+            _ => Ok(()),
+        };
+        res.map_err(|e| e.context(format!("msg {} refused", i)))?;
+    }
+    Ok(())
+}
+
+pub fn signer_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "lowmemory") {
+        features.push("lowmemory".to_string());
+    }
+    if cfg!(feature = "no-std") {
+        features.push("no-std".to_string());
+    }
+    if cfg!(feature = "no-native") {
+        features.push("no-native".to_string());
+    }
+    features
+}
+
 pub fn set_allowlist(
     root_handler: &RootHandler,
     allowlist: &[String],
@@ -72,6 +223,38 @@ pub fn set_allowlist(
     Ok(muts)
 }
 
+// every entry is parsed before the node is touched
+fn check_allowlist(root_handler: &RootHandler, entries: &[String]) -> anyhow::Result<()> {
+    let network = root_handler.node().network();
+    for entry in entries {
+        Allowable::from_str(entry, network)
+            .map_err(|e| anyhow::anyhow!("invalid allowlist entry {}: {}", entry, e))?;
+    }
+    Ok(())
+}
+
+pub fn add_allowlist(
+    root_handler: &RootHandler,
+    entries: &[String],
+) -> anyhow::Result<(Mutations, Vec<String>)> {
+    check_allowlist(root_handler, entries)?;
+    let muts = root_handler
+        .with_persist(|node| Ok(node.add_allowlist(entries)?))
+        .map_err(|e| anyhow::anyhow!("error adding to allowlist {:?}", e))?;
+    Ok((muts, get_allowlist(root_handler)?))
+}
+
+pub fn remove_allowlist(
+    root_handler: &RootHandler,
+    entries: &[String],
+) -> anyhow::Result<(Mutations, Vec<String>)> {
+    check_allowlist(root_handler, entries)?;
+    let muts = root_handler
+        .with_persist(|node| Ok(node.remove_allowlist(entries)?))
+        .map_err(|e| anyhow::anyhow!("error removing from allowlist {:?}", e))?;
+    Ok((muts, get_allowlist(root_handler)?))
+}
+
 pub fn get_allowlist(root_handler: &RootHandler) -> anyhow::Result<Vec<String>> {
     match root_handler.node().allowlist() {
         Ok(al) => Ok(al),
@@ -79,15 +262,69 @@ pub fn get_allowlist(root_handler: &RootHandler) -> anyhow::Result<Vec<String>>
     }
 }
 
-pub fn set_policy(root_handler: &RootHandler, network: Network, po: Policy) -> anyhow::Result<()> {
-    let policy = crate::root::make_policy(network, &po);
+pub fn set_policy(
+    root_handler: &RootHandler,
+    network: Network,
+    po: Policy,
+    pf: PolicyFilter,
+) -> anyhow::Result<()> {
+    let policy = crate::root::make_policy(network, &po, &pf);
     let validator_factory = Arc::new(SimpleValidatorFactory::new_with_policy(policy));
     root_handler.node().set_validator_factory(validator_factory);
     Ok(())
 }
 
-pub fn set_approver_policy(approver: &SphinxApprover, po: Policy) -> anyhow::Result<()> {
-    let app_control = approver_control(po, None);
+// keeps the spend so far, so a policy update can't reset the budget
+pub fn set_approver_policy(
+    approver: &SphinxApprover,
+    po: Policy,
+    store: &mut dyn ControlPersist,
+) -> anyhow::Result<()> {
+    approver.set_htlc_limit_msat(po.htlc_limit_msat);
+    let app_control = migrate_control(&approver.control(), approver_control(po, None));
+    let state = app_control.get_state();
     approver.set_control(app_control);
+    store.write_velocity(state)?;
+    Ok(())
+}
+
+fn set_time(
+    approver: &SphinxApprover,
+    secs: u64,
+    force: bool,
+    store: &mut dyn ControlPersist,
+) -> ControlResponse {
+    let clock = match approver.sync_clock() {
+        Some(c) => c,
+        None => return ControlResponse::Error("the signer has a native clock".to_string()),
+    };
+    match clock.set_time(secs, force) {
+        Ok(skew) => {
+            // spend recorded ahead of the new time still counts
+            if skew < 0 {
+                if let Err(e) = shift_velocity(approver, skew.unsigned_abs(), store) {
+                    log::error!("shift velocity failed {:?}", e);
+                }
+            }
+            ControlResponse::TimeSet(secs)
+        }
+        Err(e) => {
+            log::error!("set time failed {:?}", e);
+            ControlResponse::Error(format!("set time failed {:?}", e))
+        }
+    }
+}
+
+// after the clock is forced back, move the buckets back with it
+fn shift_velocity(
+    approver: &SphinxApprover,
+    back: u64,
+    store: &mut dyn ControlPersist,
+) -> anyhow::Result<()> {
+    let mut control = approver.control();
+    control.start_sec = control.start_sec.saturating_sub(back);
+    let state = control.get_state();
+    approver.set_control(control);
+    store.write_velocity(state)?;
     Ok(())
 }
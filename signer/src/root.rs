@@ -1,13 +1,15 @@
 use crate::approver::{create_approver, SphinxApprover};
+use crate::clock::SyncClock;
+use sphinx_glyph::error::ErrorCode;
 use sphinx_glyph::types;
-use types::{Interval, Policy, Velocity};
+use types::{FilterAction, Interval, Pending, Policy, Velocity};
 
 use lightning_signer::bitcoin::blockdata::constants::ChainHash;
 use lightning_signer::bitcoin::Network;
 use lightning_signer::io::Cursor;
 use lightning_signer::node::NodeServices;
 use lightning_signer::persist::{Mutations, Persist};
-use lightning_signer::policy::filter::PolicyFilter;
+use lightning_signer::policy::filter::{FilterResult, FilterRule, PolicyFilter};
 use lightning_signer::policy::simple_validator::{
     make_simple_policy, SimplePolicy, SimpleValidatorFactory,
 };
@@ -18,7 +20,7 @@ use lightning_signer::wallet::Wallet;
 use lightning_signer::Arc;
 use lss_connector::{LssSigner, Response as LssResponse, SignerMutations};
 use thiserror::Error;
-use vls_protocol::model::PubKey;
+use vls_protocol::model::{Htlc, PubKey};
 use vls_protocol::msgs::{self, read_serial_request_header, write_serial_response_header, Message};
 #[cfg(feature = "lowmemory")]
 use vls_protocol::serde_bolt::NonContiguousOctets;
@@ -42,8 +44,7 @@ pub enum VlsHandlerError {
     MsgWrite(String),
     #[error("failed lss_msg.to_vec: {0}")]
     LssWrite(String),
-    // vls-mqtt tests against "invalid sequence" at the start of the error
-    // message to detect a bad sequence error, exit(0), and restart the signer
+    // ErrorCode::BadSequence tells vls-mqtt to exit(0) and restart the signer
     #[error("invalid sequence: {0}, expected {1}")]
     BadSequence(u16, u16),
     #[error("client {0} handler error: {1}")]
@@ -52,41 +53,73 @@ pub enum VlsHandlerError {
     RootHandle(String),
 }
 
+impl VlsHandlerError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            VlsHandlerError::BadSequence(_, _) => ErrorCode::BadSequence,
+            VlsHandlerError::ClientHandle(_, e) | VlsHandlerError::RootHandle(e) => {
+                // VLS reports validation errors as "policy failure: ..."
+                if e.contains("policy failure") {
+                    ErrorCode::PolicyViolation
+                } else {
+                    ErrorCode::Vls
+                }
+            }
+            _ => ErrorCode::Vls,
+        }
+    }
+}
+
+impl From<VlsHandlerError> for sphinx_glyph::error::Error {
+    fn from(e: VlsHandlerError) -> Self {
+        Self::with_code(e.code(), &e.to_string())
+    }
+}
+
 pub fn builder(
     seed: [u8; 32],
     network: Network,
     initial_policy: Policy,
+    initial_filter: types::PolicyFilter,
     initial_allowlist: Vec<String>,
     initial_velocity: Option<Velocity>,
+    initial_pending: Vec<Pending>,
     persister: Arc<dyn Persist>,
 ) -> anyhow::Result<(HandlerBuilder, Arc<SphinxApprover>)> {
-    let clock = make_clock();
+    let (clock, sync_clock) = make_clock();
     let random_time_factory = crate::rst::RandomStartingTimeFactory::new();
     builder_inner(
         seed,
         network,
         initial_policy,
+        initial_filter,
         initial_allowlist,
         initial_velocity,
+        initial_pending,
         persister,
         clock,
         random_time_factory,
+        sync_clock,
     )
 }
 
-fn make_clock() -> Arc<dyn Clock> {
+// without a native clock, the time is set by SetTime control msgs
+fn make_clock() -> (Arc<dyn Clock>, Option<Arc<SyncClock>>) {
     #[cfg(not(feature = "no-native"))]
     {
-        Arc::new(lightning_signer::util::clock::StandardClock())
+        (
+            Arc::new(lightning_signer::util::clock::StandardClock()),
+            None,
+        )
     }
     #[cfg(feature = "no-native")]
     {
-        use lightning_signer::util::clock::ManualClock;
         use std::time::SystemTime;
         let timestamp = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
-        Arc::new(ManualClock::new(timestamp))
+        let clock = Arc::new(SyncClock::new(timestamp));
+        (clock.clone(), Some(clock))
     }
 }
 
@@ -95,14 +128,17 @@ pub fn builder_inner(
     seed: [u8; 32],
     network: Network,
     initial_policy: Policy,
+    initial_filter: types::PolicyFilter,
     initial_allowlist: Vec<String>,
     initial_velocity: Option<Velocity>,
+    initial_pending: Vec<Pending>,
     persister: Arc<dyn Persist>,
     clock: Arc<dyn Clock>,
     starting_time_factory: Arc<dyn StartingTimeFactory>,
+    sync_clock: Option<Arc<SyncClock>>,
 ) -> anyhow::Result<(HandlerBuilder, Arc<SphinxApprover>)> {
     //
-    let policy = make_policy(network, &initial_policy);
+    let policy = make_policy(network, &initial_policy, &initial_filter);
     let validator_factory = Arc::new(SimpleValidatorFactory::new_with_policy(policy));
 
     let services = NodeServices {
@@ -115,26 +151,94 @@ pub fn builder_inner(
     log::debug!("create handler builder with network {:?}", network);
     let mut handler_builder =
         HandlerBuilder::new(network, 0, services, seed).allowlist(initial_allowlist);
-    // FIXME set up a manual approver (ui_approver)
-    let approv = create_approver(clock.clone(), initial_policy, initial_velocity);
+    let mut approv = create_approver(
+        clock.clone(),
+        initial_policy,
+        initial_velocity,
+        initial_pending,
+    );
+    approv.set_sync_clock(sync_clock);
     let approver = Arc::new(approv);
     handler_builder = handler_builder.approver(approver.clone());
     Ok((handler_builder, approver))
 }
 
-pub fn make_policy(network: Network, _po: &Policy) -> SimplePolicy {
+pub fn make_policy(network: Network, _po: &Policy, pf: &types::PolicyFilter) -> SimplePolicy {
     let mut p = make_simple_policy(network);
-    // let mut p = make_simple_policy(network);
-    // p.max_htlc_value_sat = po.htlc_limit_msat;
-    p.filter = PolicyFilter::new_permissive();
-    // FIXME for prod use a nempty filter
+    // VLS's max_htlc_value_sat caps the HTLCs in flight together,
+    // the per-HTLC htlc_limit_msat is checked in check_htlc_limit
+    p.filter = if pf.is_empty() {
+        PolicyFilter::new_permissive()
+    } else {
+        policy_filter(pf)
+    };
     p
 }
 
-pub fn policy_interval(int: Interval) -> VelocityControlIntervalType {
+// the HTLCs a commitment msg would sign or validate
+fn msg_htlcs(msg: &Message) -> &[Htlc] {
+    match msg {
+        Message::ValidateCommitmentTx(m) => &m.htlcs,
+        Message::ValidateCommitmentTx2(m) => &m.htlcs,
+        Message::SignRemoteCommitmentTx(m) => &m.htlcs,
+        Message::SignRemoteCommitmentTx2(m) => &m.htlcs,
+        Message::SignLocalCommitmentTx2(m) => &m.htlcs,
+        _ => &[],
+    }
+}
+
+// no single HTLC can carry more than the policy's htlc_limit_msat.
+// An HTLC of exactly the limit is fine
+pub fn check_htlc_limit(msg: &Message, limit_msat: u64) -> Result<(), String> {
+    match msg_htlcs(msg).iter().find(|h| h.amount > limit_msat) {
+        Some(h) => Err(format!(
+            "policy failure: htlc of {} msat is over the limit of {} msat",
+            h.amount, limit_msat
+        )),
+        None => Ok(()),
+    }
+}
+
+// any tag not matched by a rule is an error
+pub fn policy_filter(pf: &types::PolicyFilter) -> PolicyFilter {
+    let rules = pf
+        .iter()
+        .map(|r| FilterRule {
+            tag: r.tag.clone(),
+            is_prefix: r.is_prefix,
+            action: filter_action(r.action),
+        })
+        .collect();
+    PolicyFilter { rules }
+}
+
+pub fn filter_action(fa: FilterAction) -> FilterResult {
+    match fa {
+        FilterAction::Error => FilterResult::Error,
+        FilterAction::Warn => FilterResult::Warn,
+    }
+}
+
+// VLS only has a spec for hourly and daily windows
+pub fn policy_interval(int: Interval) -> Option<VelocityControlIntervalType> {
+    match int {
+        Interval::Hourly => Some(VelocityControlIntervalType::Hourly),
+        Interval::Daily => Some(VelocityControlIntervalType::Daily),
+        _ => None,
+    }
+}
+
+// (bucket_interval secs, num_buckets) for the other windows
+pub fn interval_buckets(int: Interval) -> (u32, usize) {
     match int {
-        Interval::Hourly => VelocityControlIntervalType::Hourly,
-        Interval::Daily => VelocityControlIntervalType::Daily,
+        Interval::Weekly => (6 * 60 * 60, 28),
+        Interval::Monthly => (24 * 60 * 60, 30),
+        _ => {
+            let secs = int.seconds().max(1);
+            let num = secs.min(24);
+            let bucket = (secs + num - 1) / num;
+            (bucket.min(u32::MAX as u64) as u32, num as usize)
+        }
     }
 }
 
@@ -178,6 +282,7 @@ pub fn handle_init(
 // returns the VLS return msg and the muts
 fn handle_inner(
     root_handler: &RootHandler,
+    approver: &SphinxApprover,
     #[cfg(feature = "lowmemory")] mut bytes: MsgBytes,
     #[cfg(not(feature = "lowmemory"))] bytes: MsgBytes,
     expected_sequence: Option<u16>,
@@ -204,6 +309,9 @@ fn handle_inner(
     if do_log {
         log::info!("VLS: => {}", &cmd);
     }
+    if let Err(e) = check_htlc_limit(&message, approver.htlc_limit_msat()) {
+        return Err(VlsHandlerError::ClientHandle(dbid, e));
+    }
     let reply = if dbid > 0 {
         let handler = root_handler.for_new_client(dbid, PubKey(peer_id), dbid);
         match handler.handle(message) {
@@ -231,25 +339,27 @@ fn handle_inner(
 
 pub fn handle(
     root_handler: &RootHandler,
+    approver: &SphinxApprover,
     bytes: MsgBytes,
     expected_sequence: Option<u16>,
     do_log: bool,
 ) -> Result<(Vec<u8>, u16), VlsHandlerError> {
     let (out_bytes, _muts, sequence, _cmd) =
-        handle_inner(root_handler, bytes, expected_sequence, do_log)?;
+        handle_inner(root_handler, approver, bytes, expected_sequence, do_log)?;
     Ok((out_bytes, sequence))
 }
 
 #[allow(clippy::type_complexity)]
 pub fn handle_with_lss(
     root_handler: &RootHandler,
+    approver: &SphinxApprover,
     lss_signer: &LssSigner,
     bytes: MsgBytes,
     expected_sequence: Option<u16>,
     do_log: bool,
 ) -> Result<(Vec<u8>, Vec<u8>, u16, String, Option<[u8; 32]>), VlsHandlerError> {
     let (out_bytes, mutations, sequence, cmd) =
-        handle_inner(root_handler, bytes, expected_sequence, do_log)?;
+        handle_inner(root_handler, approver, bytes, expected_sequence, do_log)?;
     let mut server_hmac = None;
     let lss_bytes = if mutations.is_empty() {
         Vec::new()
@@ -398,3 +508,114 @@ fn vls_cmd(msg: &Message) -> String {
     };
     m.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vls_handler_error_code() {
+        use sphinx_glyph::error::Error as GlyphError;
+        let e: GlyphError = VlsHandlerError::BadSequence(3, 2).into();
+        assert_eq!(e.code, ErrorCode::BadSequence);
+        assert_eq!(e.message, "invalid sequence: 3, expected 2");
+        let e = VlsHandlerError::ClientHandle(1, "policy failure: htlc limit".to_string());
+        assert_eq!(e.code(), ErrorCode::PolicyViolation);
+        let e = VlsHandlerError::MsgRead("eof".to_string());
+        assert_eq!(e.code(), ErrorCode::Vls);
+    }
+
+    #[test]
+    fn test_interval_buckets() {
+        for int in [Interval::Weekly, Interval::Monthly, Interval::Custom(600)] {
+            let (bucket, num) = interval_buckets(int);
+            assert_eq!(bucket as u64 * num as u64, int.seconds());
+        }
+        assert_eq!(interval_buckets(Interval::Custom(1)), (1, 1));
+        assert_eq!(interval_buckets(Interval::Custom(100)), (5, 24));
+        assert_eq!(interval_buckets(Interval::Custom(u64::MAX)), (u32::MAX, 24));
+        assert!(policy_interval(Interval::Custom(3600)).is_none());
+    }
+
+    fn commitment_msg(amounts: &[u64]) -> Message {
+        use vls_protocol::model::Sha256;
+        let htlcs: Vec<Htlc> = amounts
+            .iter()
+            .map(|amount| Htlc {
+                side: Htlc::LOCAL,
+                amount: *amount,
+                payment_hash: Sha256([1; 32]),
+                ctlv_expiry: 0,
+            })
+            .collect();
+        Message::SignRemoteCommitmentTx2(msgs::SignRemoteCommitmentTx2 {
+            remote_per_commitment_point: PubKey([2; 33]),
+            commitment_number: 1,
+            feerate: 253,
+            to_local_value_sat: 100_000,
+            to_remote_value_sat: 100_000,
+            htlcs: htlcs.into(),
+        })
+    }
+
+    #[test]
+    fn test_check_htlc_limit() {
+        let limit = 50_000_000;
+        // an htlc of exactly the limit passes
+        assert!(check_htlc_limit(&commitment_msg(&[1_000, limit]), limit).is_ok());
+        // the limit is per htlc, not the total in flight
+        assert!(check_htlc_limit(&commitment_msg(&[limit, limit, limit]), limit).is_ok());
+        let e = check_htlc_limit(&commitment_msg(&[1_000, limit + 1]), limit)
+            .expect_err("over the limit");
+        // the broker gets it as a policy violation
+        let e = VlsHandlerError::ClientHandle(1, e);
+        assert_eq!(e.code(), ErrorCode::PolicyViolation);
+        // VLS's own in-flight cap is left as it was
+        let p = make_policy(Network::Regtest, &Policy::default(), &Vec::new());
+        let vls = make_simple_policy(Network::Regtest);
+        assert_eq!(p.max_htlc_value_sat, vls.max_htlc_value_sat);
+    }
+
+    #[test]
+    fn test_updated_htlc_limit() {
+        use lightning_signer::util::clock::ManualClock;
+        use std::time::Duration;
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000_000)));
+        let po = Policy {
+            htlc_limit_msat: 50_000_000,
+            ..Default::default()
+        };
+        let approver = create_approver(clock, po.clone(), None, Vec::new());
+        let msg = commitment_msg(&[20_000_000]);
+        assert!(check_htlc_limit(&msg, approver.htlc_limit_msat()).is_ok());
+        // UpdatePolicy lowers the limit without a restart
+        let lowered = Policy {
+            htlc_limit_msat: 10_000_000,
+            ..po
+        };
+        let mut store = sphinx_glyph::control::DummyPersister;
+        crate::policy::set_approver_policy(&approver, lowered, &mut store)
+            .expect("set policy failed");
+        assert_eq!(approver.htlc_limit_msat(), 10_000_000);
+        assert!(check_htlc_limit(&msg, approver.htlc_limit_msat()).is_err());
+    }
+
+    #[test]
+    fn test_make_policy_filter() {
+        let pf = vec![types::FilterRule {
+            tag: "policy-routing".to_string(),
+            action: FilterAction::Warn,
+            is_prefix: true,
+        }];
+        let p = make_policy(Network::Regtest, &Policy::default(), &pf);
+        assert!(matches!(
+            p.filter.filter("policy-routing-balanced"),
+            FilterResult::Warn
+        ));
+        // tags without a rule are errors with a non-empty filter
+        assert!(matches!(
+            p.filter.filter("policy-commitment-htlc-cltv-range"),
+            FilterResult::Error
+        ));
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{parse, Result, SphinxError};
-use sphinx_crypter::secp256k1::SecretKey;
+use sphinx::serde_json;
+use sphinx_crypter::secp256k1::{PublicKey, SecretKey};
 use sphinx_glyph::control::{
-    build_control_msg, control_msg_from_json, parse_control_response_to_json,
+    build_control_msg, control_msg_from_json, decrypt_control_response,
+    parse_control_response_to_json, verify_control_response,
 };
 use sphinx_glyph::sphinx_auther;
-use sphinx_glyph::types::ControlMessage;
+use sphinx_glyph::types::{ControlMessage, ControlResponse};
 
 pub fn build_request(msg: String, secret: String, nonce: u64) -> Result<String> {
     let cm = control_msg_from_json(msg.as_bytes()).map_err(|e| SphinxError::BadRequest {
@@ -23,6 +25,42 @@ pub fn parse_response(inp: String) -> Result<String> {
     Ok(r)
 }
 
+// res is signed by the signer pubkey, for this request from build_request
+pub fn parse_signed_response(res: String, signer: String, request: String) -> Result<String> {
+    let v = hex::decode(res).map_err(|e| SphinxError::BadResponse {
+        r: format!("{:?}", e),
+    })?;
+    let req = hex::decode(request).map_err(|e| SphinxError::BadRequest {
+        r: format!("{:?}", e),
+    })?;
+    let pk = parse_public_key(signer)?;
+    let r = verify_control_response(&v, &pk, &req).map_err(|e| SphinxError::BadResponse {
+        r: format!("{:?}", e),
+    })?;
+    response_to_json(&r)
+}
+
+// like parse_signed_response, for a res encrypted to this secret
+pub fn parse_encrypted_response(
+    res: String,
+    signer: String,
+    secret: String,
+    request: String,
+) -> Result<String> {
+    let v = hex::decode(res).map_err(|e| SphinxError::BadResponse {
+        r: format!("{:?}", e),
+    })?;
+    let req = hex::decode(request).map_err(|e| SphinxError::BadRequest {
+        r: format!("{:?}", e),
+    })?;
+    let pk = parse_public_key(signer)?;
+    let sk = parse_secret_key(secret)?;
+    let r = decrypt_control_response(&v, &pk, &sk, &req).map_err(|e| SphinxError::Decrypt {
+        r: format!("{:?}", e),
+    })?;
+    response_to_json(&r)
+}
+
 pub fn make_auth_token(ts: u32, secret: String) -> Result<String> {
     let sk = parse_secret_key(secret)?;
     let t = sphinx_auther::token::Token::new_with_time(ts);
@@ -43,6 +81,20 @@ fn build_msg(msg: ControlMessage, nonce: u64, secret: String) -> Result<String>
     Ok(hex::encode(r))
 }
 
+fn response_to_json(r: &ControlResponse) -> Result<String> {
+    serde_json::to_string(r).map_err(|e| SphinxError::BadResponse {
+        r: format!("{:?}", e),
+    })
+}
+
+fn parse_public_key(pubkey: String) -> Result<PublicKey> {
+    let pk = parse::parse_public_key_string(pubkey)?;
+    let pk = PublicKey::from_slice(&pk[..]).map_err(|e| SphinxError::BadPubkey {
+        r: format!("{:?}", e),
+    })?;
+    Ok(pk)
+}
+
 fn parse_secret_key(secret: String) -> Result<SecretKey> {
     let secret_key = parse::parse_secret_string(secret)?;
     let sk = SecretKey::from_slice(&secret_key[..]).map_err(|e| SphinxError::BadSecret {
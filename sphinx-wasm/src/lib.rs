@@ -30,6 +30,30 @@ pub fn parse_control_response(msg: &str) -> Result<String> {
     Ok(cy::parse_response(msg.to_string())?)
 }
 
+#[wasm_bindgen]
+pub fn parse_signed_control_response(msg: &str, signer: &str, request: &str) -> Result<String> {
+    Ok(cy::parse_signed_response(
+        msg.to_string(),
+        signer.to_string(),
+        request.to_string(),
+    )?)
+}
+
+#[wasm_bindgen]
+pub fn parse_encrypted_control_response(
+    msg: &str,
+    signer: &str,
+    secret: &str,
+    request: &str,
+) -> Result<String> {
+    Ok(cy::parse_encrypted_response(
+        msg.to_string(),
+        signer.to_string(),
+        secret.to_string(),
+        request.to_string(),
+    )?)
+}
+
 #[wasm_bindgen(getter_with_clone)]
 pub struct Keys {
     pub secret: String,
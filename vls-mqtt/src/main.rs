@@ -8,7 +8,7 @@ use crate::routes::{ChannelReply, ChannelRequest};
 use anyhow::{Error, Result};
 use dotenv::dotenv;
 use glyph::control::{ControlPersist, Controller};
-use glyph::ser::{serialize_controlresponse, ByteBuf};
+use glyph::ota::OtaVerifier;
 use lss::init_lss;
 use rand::RngCore;
 use rocket::tokio::sync::{broadcast, mpsc, oneshot};
@@ -16,16 +16,17 @@ use sphinx_signer::kvv::{fs::FsKVVStore, CloudKVVStore, KVVPersister, RmpFormat}
 use sphinx_signer::lightning_signer::bitcoin::Network;
 use sphinx_signer::lightning_signer::persist::Persist;
 use sphinx_signer::lightning_signer::wallet::Wallet;
-use sphinx_signer::policy::update_controls;
+use sphinx_signer::policy::{check_controls, signer_features, update_controls};
 use sphinx_signer::Handler;
 use sphinx_signer::{self, approver::SphinxApprover, root, sphinx_glyph as glyph, RootHandler};
 use std::env;
 use std::process::exit;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub const ROOT_STORE: &str = "teststore";
+pub const HEARTBEAT_SECS: u64 = 60;
 
 // requests from incoming VLS messages
 #[derive(Debug)]
@@ -84,6 +85,19 @@ impl LssChanMsg {
     }
 }
 
+// requests from incoming PROXY messages
+#[derive(Debug)]
+pub struct ProxyChanMsg {
+    pub message: Vec<u8>,
+    pub reply_tx: oneshot::Sender<Result<Vec<u8>>>,
+}
+impl ProxyChanMsg {
+    pub fn new(message: Vec<u8>) -> (Self, oneshot::Receiver<Result<Vec<u8>>>) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        (Self { message, reply_tx }, reply_rx)
+    }
+}
+
 #[rocket::launch]
 async fn rocket() -> _ {
     dotenv().ok();
@@ -102,9 +116,19 @@ async fn rocket() -> _ {
 
     let ctrlr_db = persist::ControlPersister::new("vls_mqtt_data");
     let initial_policy = ctrlr_db.read_policy().unwrap_or_default();
+    let initial_filter = ctrlr_db.read_policy_filter().unwrap_or_default();
     let initial_velocity = ctrlr_db.read_velocity().ok();
+    let initial_pending = ctrlr_db.read_pending().unwrap_or_default();
     let ctrlr_db_mutex = Arc::new(Mutex::new(ctrlr_db));
     let mut ctrlr = Controller::new_with_persister(sk, pk, ctrlr_db_mutex.clone());
+    ctrlr.set_node_info(pk, signer_features());
+    if let Ok(size) = env::var("NONCE_WINDOW") {
+        ctrlr.set_nonce_window(size.parse().expect("invalid NONCE_WINDOW"));
+    }
+    // without it, Ota control msgs are refused
+    if let Ok(address) = env::var("OTA_ADDRESS") {
+        ctrlr.set_ota_verifier(OtaVerifier::new(&address));
+    }
     let node_id = ctrlr.pubkey();
 
     let seed32: [u8; 32] = seed.try_into().expect("invalid seed");
@@ -149,8 +173,10 @@ async fn rocket() -> _ {
         seed32,
         network,
         initial_policy,
+        initial_filter,
         initial_allowlist,
         initial_velocity,
+        initial_pending,
         persister,
     )
     .expect("failed to init signer");
@@ -161,15 +187,47 @@ async fn rocket() -> _ {
     let lss_tx_ = lss_tx.clone();
     let (commit_tx, mut commit_rx) = mpsc::channel::<()>(1000);
     let commit_tx_ = commit_tx.clone();
+    let (heartbeat_tx, heartbeat_rx) = mpsc::channel::<glyph::types::Heartbeat>(10);
+    let (proxy_tx, mut proxy_rx) = mpsc::channel::<ProxyChanMsg>(1000);
     let error_tx_ = error_tx.clone();
     rocket::tokio::spawn(async move {
         mqtt::start(
-            vls_tx_, &pk, &sk, &client_id, error_tx_, lss_tx_, commit_tx_,
+            vls_tx_,
+            &pk,
+            &sk,
+            &client_id,
+            error_tx_,
+            lss_tx_,
+            commit_tx_,
+            heartbeat_rx,
+            proxy_tx,
         )
         .await
         .expect("mqtt crash");
     });
 
+    // PROXY requests are signed with the node key, if the proxy policy allows
+    let ctrldb_proxy = ctrlr.persister();
+    rocket::tokio::spawn(async move {
+        while let Some(msg) = proxy_rx.recv().await {
+            let policy = ctrldb_proxy
+                .lock()
+                .unwrap()
+                .read_proxy_policy()
+                .unwrap_or_default();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let res =
+                glyph::proxy::handle_proxy(&msg.message, &sk, &policy, now).map(|(req, res)| {
+                    log::info!("PROXY: {}", req.kind());
+                    res
+                });
+            let _ = msg.reply_tx.send(res);
+        }
+    });
+
     // LSS initialization
     let (root_handler, lss_signer) = init_lss(handler_builder, lss_rx).await.unwrap();
 
@@ -181,13 +239,21 @@ async fn rocket() -> _ {
     let rh_ = rh.clone();
     let approver_ = approver.clone();
     let ctrldb_ = ctrlr.persister();
+    let pending_db = ctrlr_db_mutex.clone();
     rocket::tokio::spawn(async move {
         while let Some(msg) = vls_rx.recv().await {
             let s1 = approver.control().get_state();
+            let p1 = approver.pending().list();
             println!("RUN NOW: {:?}", &msg.expected_sequence);
-            let res_res =
-                root::handle_with_lss(&rh_, &lss_signer, msg.message, msg.expected_sequence, false)
-                    .map_err(Error::msg);
+            let res_res = root::handle_with_lss(
+                &rh_,
+                &approver,
+                &lss_signer,
+                msg.message,
+                msg.expected_sequence,
+                false,
+            )
+            .map_err(Error::msg);
             let s2 = approver.control().get_state();
             if s1 != s2 {
                 log::info!("===> VelocityApprover state updated");
@@ -197,6 +263,9 @@ async fn rocket() -> _ {
                 }
                 drop(ctrldb_);
             }
+            if p1 != approver.pending().list() {
+                persist_pending(&pending_db, &approver);
+            }
             let _ = msg.reply_tx.send(res_res);
         }
     });
@@ -212,14 +281,29 @@ async fn rocket() -> _ {
         }
     });
 
-    rocket::tokio::spawn(
-        async move { listen_for_commands(&mut ctrlr, ctrl_rx, &rh, &approver_).await },
-    );
+    let rh_hb = rh.clone();
+    let approver_hb = approver_.clone();
+    let ctrldb_hb = ctrlr.persister();
+    rocket::tokio::spawn(async move {
+        listen_for_commands(&mut ctrlr, ctrl_rx, &rh, &approver_, &ctrlr_db_mutex).await
+    });
 
+    let started = Instant::now();
     rocket::tokio::spawn(async move {
         loop {
-            rocket::tokio::time::sleep(Duration::from_secs(60)).await;
-            // let _ = rh.node().get_heartbeat();
+            rocket::tokio::time::sleep(Duration::from_secs(HEARTBEAT_SECS)).await;
+            let velocity = approver_hb.control().get_state();
+            let policy = ctrldb_hb.lock().unwrap().read_policy().unwrap_or_default();
+            // sequence and lss_synced are filled in by the mqtt listener
+            let hb = glyph::types::Heartbeat {
+                node_id: hex::encode(rh_hb.node().get_id().serialize()),
+                velocity_msat: velocity.1.iter().fold(0u64, |a, b| a.saturating_add(*b)),
+                msat_per_interval: policy.msat_per_interval,
+                channels: rh_hb.node().channels().len() as u64,
+                uptime: started.elapsed().as_secs(),
+                ..Default::default()
+            };
+            let _ = heartbeat_tx.send(hb).await;
         }
     });
 
@@ -231,21 +315,47 @@ async fn listen_for_commands(
     mut ctrl_rx: mpsc::Receiver<ChannelRequest>,
     rh: &RootHandler,
     approver: &SphinxApprover,
+    pending_db: &Mutex<persist::ControlPersister>,
 ) {
+    // responses are signed, and encrypted to the admin if ENCRYPT_RESPONSES is set
+    let encrypt = env::var("ENCRYPT_RESPONSES").is_ok();
     while let Some(msg) = ctrl_rx.recv().await {
-        match ctrlr.handle(&msg.message) {
+        // msgs the signer can't apply are refused before the store is changed
+        let check = |m: &glyph::control::ControlMessage| check_controls(rh, approver, m);
+        match ctrlr.handle_checked(&msg.message, check) {
             Ok((cmsg, cres)) => {
-                let (res2, muts) = update_controls(rh, cmsg, cres, approver);
+                let p1 = approver.pending().list();
+                let store = ctrlr.persister();
+                let mut store = store.lock().unwrap();
+                let (res2, muts) = update_controls(rh, cmsg, cres, approver, &mut *store);
+                drop(store);
+                if p1 != approver.pending().list() {
+                    persist_pending(pending_db, approver);
+                }
                 if muts.is_some() {
                     log::warn!("some mutations that need to be sent to LSS!");
                 }
-                let mut bb = ByteBuf::new();
-                serialize_controlresponse(&mut bb, &res2).expect("failed serialize_lssresponse");
-                let _ = msg.reply_tx.send(ChannelReply {
-                    reply: bb.into_vec(),
-                });
+                let reply = if encrypt {
+                    ctrlr.build_encrypted_response(res2, &msg.message)
+                } else {
+                    ctrlr.build_signed_response(res2, &msg.message)
+                };
+                match reply {
+                    Ok(reply) => {
+                        let _ = msg.reply_tx.send(ChannelReply { reply });
+                    }
+                    Err(e) => log::warn!("failed to build ctrl response {:?}", e),
+                }
             }
             Err(e) => log::warn!("error parsing ctrl msg {:?}", e),
         };
     }
 }
+
+// the queue is read under the db lock, so the last write is the newest queue
+fn persist_pending(db: &Mutex<persist::ControlPersister>, approver: &SphinxApprover) {
+    let mut db = db.lock().unwrap();
+    if let Err(e) = db.write_pending(approver.pending().list()) {
+        log::error!("failed to persist pending payments {:?}", e);
+    }
+}
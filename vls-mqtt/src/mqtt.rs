@@ -1,7 +1,11 @@
-use crate::{LssChanMsg, VlsChanMsg};
+use crate::{LssChanMsg, ProxyChanMsg, VlsChanMsg};
 use anyhow::Result;
 use sphinx_auther::secp256k1::{PublicKey, SecretKey};
 use sphinx_auther::token::Token;
+use sphinx_signer::root::VlsHandlerError;
+use sphinx_signer::sphinx_glyph::error::{Error as GlyphError, ErrorCode};
+use sphinx_signer::sphinx_glyph::ser::{serialize_heartbeat, ByteBuf};
+use sphinx_signer::sphinx_glyph::types::Heartbeat;
 use sphinx_signer::sphinx_glyph::{sphinx_auther, topics};
 
 use rocket::tokio::sync::{broadcast, mpsc};
@@ -11,6 +15,7 @@ use std::error::Error;
 use std::process::exit;
 use std::time::Duration;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start(
     vls_tx: mpsc::Sender<VlsChanMsg>,
     pubkey: &PublicKey,
@@ -19,6 +24,8 @@ pub async fn start(
     error_tx: broadcast::Sender<Vec<u8>>,
     lss_tx: mpsc::Sender<LssChanMsg>,
     commit_tx: mpsc::Sender<()>,
+    mut heartbeat_rx: mpsc::Receiver<Heartbeat>,
+    proxy_tx: mpsc::Sender<ProxyChanMsg>,
 ) -> Result<(), Box<dyn Error>> {
     // alternate between "reconnection" and "handler"
     loop {
@@ -81,11 +88,14 @@ pub async fn start(
             client_id,
             lss_tx.clone(),
             commit_tx.clone(),
+            &mut heartbeat_rx,
+            proxy_tx.clone(),
         )
         .await;
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn main_listener(
     vls_tx: mpsc::Sender<VlsChanMsg>,
     mut eventloop: EventLoop,
@@ -94,14 +104,26 @@ async fn main_listener(
     client_id: &str,
     lss_tx: mpsc::Sender<LssChanMsg>,
     commit_tx: mpsc::Sender<()>,
+    heartbeat_rx: &mut mpsc::Receiver<Heartbeat>,
+    proxy_tx: mpsc::Sender<ProxyChanMsg>,
 ) {
     // say hello to start
     publish(client, client_id, topics::HELLO, &[]).await;
 
     let mut expected_sequence: Option<u16> = None;
+    let mut last_sequence: Option<u16> = None;
     let mut msgs: Option<(Vec<u8>, [u8; 32])> = None;
     loop {
-        match eventloop.poll().await {
+        let polled = rocket::tokio::select! {
+            polled = eventloop.poll() => polled,
+            Some(mut hb) = heartbeat_rx.recv() => {
+                hb.sequence = last_sequence;
+                hb.lss_synced = msgs.is_none();
+                publish_heartbeat(client, client_id, &hb).await;
+                continue;
+            }
+        };
+        match polled {
             Ok(event) => {
                 if let Some((topic, msg_bytes)) = incoming_bytes(event) {
                     let (return_topic, bytes, sequence) = got_msg(
@@ -111,14 +133,15 @@ async fn main_listener(
                         &vls_tx,
                         &lss_tx,
                         &commit_tx,
+                        &proxy_tx,
                         &mut msgs,
                     )
                     .await;
                     if return_topic == topics::ERROR {
                         let _ = error_tx.send(bytes.clone());
-                        let error_msg = String::from_utf8(bytes.clone()).unwrap();
-                        log::error!("ERROR {}", error_msg);
-                        if error_msg.starts_with("invalid sequence") {
+                        let error = GlyphError::from_slice(&bytes);
+                        log::error!("ERROR {}", error);
+                        if error.code == ErrorCode::BadSequence {
                             exit(0);
                         }
                         // if error_msg.contains("PutConflict") {
@@ -126,6 +149,7 @@ async fn main_listener(
                         // }
                     } else if let Some(seq) = sequence {
                         expected_sequence = Some(seq + 1);
+                        last_sequence = Some(seq);
                     }
                     // println!("publish back to broker! {}", &return_topic);
                     publish(client, client_id, &return_topic, &bytes).await;
@@ -147,6 +171,7 @@ async fn main_listener(
 // VLS->(vls handle)->LSS_RES (lss_bytes)
 // LSS_MSG->(lss check hmac)->VLS_RETURN (vls_bytes)
 
+#[allow(clippy::too_many_arguments)]
 async fn got_msg(
     topic: &str,
     msg_bytes: &[u8],
@@ -154,6 +179,7 @@ async fn got_msg(
     vls_tx: &mpsc::Sender<VlsChanMsg>,
     lss_tx: &mpsc::Sender<LssChanMsg>,
     commit_tx: &mpsc::Sender<()>,
+    proxy_tx: &mpsc::Sender<ProxyChanMsg>,
     msgs: &mut Option<(Vec<u8>, [u8; 32])>,
 ) -> (String, Vec<u8>, Option<u16>) {
     // println!("GOT MSG on {} {:?}", topic, msg_bytes);
@@ -177,11 +203,7 @@ async fn got_msg(
             }
             Err(e) => {
                 println!("ERROR: {:?}", e);
-                (
-                    topics::ERROR.to_string(),
-                    e.to_string().as_bytes().to_vec(),
-                    None,
-                )
+                (topics::ERROR.to_string(), error_bytes(&e), None)
             }
         }
     } else if topic.ends_with(topics::LSS_MSG)
@@ -202,16 +224,41 @@ async fn got_msg(
                 }
                 (ret_topic, payload.to_vec(), None)
             }
-            Err(e) => (
-                topics::ERROR.to_string(),
-                e.to_string().as_bytes().to_vec(),
-                None,
-            ),
+            Err(e) => (topics::ERROR.to_string(), error_bytes(&e), None),
+        }
+    } else if topic.ends_with(topics::PROXY) {
+        let (proxy_msg, reply_rx) = ProxyChanMsg::new(msg_bytes.to_vec());
+        let _ = proxy_tx.send(proxy_msg).await;
+        // the proxy task dropped the request
+        let reply = reply_rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("no reply from the proxy task")));
+        match reply {
+            Ok(res) => (topics::PROXY_RES.to_string(), res, None),
+            Err(e) => (topics::ERROR.to_string(), error_bytes(&e), None),
         }
     } else {
         log::warn!("unrecognized topic {}", topic);
         let err = format!("=> bad topic {}", topic);
-        (topics::ERROR.to_string(), err.as_bytes().to_vec(), None)
+        let err = GlyphError::with_code(ErrorCode::Unidentified, &err);
+        (topics::ERROR.to_string(), err.to_vec(), None)
+    }
+}
+
+// the ERROR payload is a glyph Error: the message followed by the code byte
+fn error_bytes(e: &anyhow::Error) -> Vec<u8> {
+    let err = match e.downcast_ref::<VlsHandlerError>() {
+        Some(vls_err) => GlyphError::with_code(vls_err.code(), &vls_err.to_string()),
+        None => GlyphError::from_anyhow(e, ErrorCode::Vls),
+    };
+    err.to_vec()
+}
+
+async fn publish_heartbeat(client: &AsyncClient, client_id: &str, hb: &Heartbeat) {
+    let mut buff = ByteBuf::new();
+    match serialize_heartbeat(&mut buff, None, hb) {
+        Ok(()) => publish(client, client_id, topics::HEARTBEAT, buff.as_slice()).await,
+        Err(e) => log::warn!("failed to serialize heartbeat {:?}", e),
     }
 }
 
@@ -1,6 +1,9 @@
 use anyhow::Result;
 use fsdb::{Bucket, Fsdb};
-use sphinx_signer::sphinx_glyph::control::{Config, ControlPersist, Policy, Velocity};
+use sphinx_signer::sphinx_glyph::control::{
+    Admin, AuditEntry, Config, ControlPersist, NonceWindowState, Pending, Policy, PolicyFilter,
+    ProxyPolicy, Velocity,
+};
 
 pub struct ControlPersister {
     nonce: Bucket<[u8; 8]>,
@@ -9,6 +12,16 @@ pub struct ControlPersister {
     id: Bucket<[u8; 16]>,
     policy: Bucket<Policy>,
     velocity: Bucket<Velocity>,
+    filter: Bucket<PolicyFilter>,
+    allowlist: Bucket<Vec<String>>,
+    ota_version: Bucket<[u8; 8]>,
+    admins: Bucket<Vec<Admin>>,
+    nonce_windows: Bucket<Vec<NonceWindowState>>,
+    proxy_policy: Bucket<ProxyPolicy>,
+    // one entry per seq, and the newest one under "last"
+    audit: Bucket<AuditEntry>,
+    // payments waiting for approval, kept by the signer not the controller
+    pending: Bucket<Vec<Pending>>,
 }
 
 impl ControlPersister {
@@ -21,8 +34,24 @@ impl ControlPersister {
             id: db.bucket("id", None).expect("fail id db"),
             policy: db.bucket("policy", None).expect("fail policy db"),
             velocity: db.bucket("velocity", None).expect("fail velocity db"),
+            filter: db.bucket("filter", None).expect("fail filter db"),
+            allowlist: db.bucket("allowlist", None).expect("fail allowlist db"),
+            ota_version: db.bucket("otaversion", None).expect("fail otaversion db"),
+            admins: db.bucket("admins", None).expect("fail admins db"),
+            nonce_windows: db
+                .bucket("noncewindows", None)
+                .expect("fail noncewindows db"),
+            proxy_policy: db.bucket("proxypolicy", None).expect("fail proxypolicy db"),
+            audit: db.bucket("audit", None).expect("fail audit db"),
+            pending: db.bucket("pending", None).expect("fail pending db"),
         }
     }
+    pub fn read_pending(&self) -> Result<Vec<Pending>> {
+        Ok(self.pending.get("pending")?)
+    }
+    pub fn write_pending(&mut self, pending: Vec<Pending>) -> Result<()> {
+        Ok(self.pending.put("pending", &pending)?)
+    }
 }
 
 impl ControlPersist for ControlPersister {
@@ -72,4 +101,62 @@ impl ControlPersist for ControlPersister {
     fn write_velocity(&mut self, v: Velocity) -> Result<()> {
         Ok(self.velocity.put("velocity", &v)?)
     }
+    fn read_policy_filter(&self) -> Result<PolicyFilter> {
+        Ok(self.filter.get("filter")?)
+    }
+    fn write_policy_filter(&mut self, f: PolicyFilter) -> Result<()> {
+        Ok(self.filter.put("filter", &f)?)
+    }
+    fn remove_policy_filter(&mut self) -> Result<()> {
+        Ok(self.filter.remove("filter")?)
+    }
+    fn read_allowlist(&self) -> Result<Vec<String>> {
+        Ok(self.allowlist.get("allowlist")?)
+    }
+    fn write_allowlist(&mut self, al: Vec<String>) -> Result<()> {
+        Ok(self.allowlist.put("allowlist", &al)?)
+    }
+    fn read_ota_version(&self) -> Result<u64> {
+        let r = self.ota_version.get("otaversion")?;
+        Ok(u64::from_be_bytes(r))
+    }
+    fn write_ota_version(&mut self, version: u64) -> Result<()> {
+        Ok(self.ota_version.put("otaversion", &version.to_be_bytes())?)
+    }
+    fn read_admins(&self) -> Result<Vec<Admin>> {
+        Ok(self.admins.get("admins")?)
+    }
+    fn write_admins(&mut self, admins: Vec<Admin>) -> Result<()> {
+        Ok(self.admins.put("admins", &admins)?)
+    }
+    fn read_nonce_windows(&self) -> Result<Vec<NonceWindowState>> {
+        Ok(self.nonce_windows.get("noncewindows")?)
+    }
+    fn write_nonce_windows(&mut self, windows: Vec<NonceWindowState>) -> Result<()> {
+        Ok(self.nonce_windows.put("noncewindows", &windows)?)
+    }
+    fn read_proxy_policy(&self) -> Result<ProxyPolicy> {
+        Ok(self.proxy_policy.get("proxypolicy")?)
+    }
+    fn write_proxy_policy(&mut self, p: ProxyPolicy) -> Result<()> {
+        Ok(self.proxy_policy.put("proxypolicy", &p)?)
+    }
+    fn append_audit(&mut self, entry: AuditEntry) -> Result<()> {
+        self.audit.put(&entry.seq.to_string(), &entry)?;
+        Ok(self.audit.put("last", &entry)?)
+    }
+    fn read_audit(&self, since: u64, limit: u32) -> Result<Vec<AuditEntry>> {
+        let last = match self.read_last_audit()? {
+            Some(e) => e.seq,
+            None => return Ok(Vec::new()),
+        };
+        let mut entries = Vec::new();
+        for seq in (since..=last).take(limit as usize) {
+            entries.push(self.audit.get(&seq.to_string())?);
+        }
+        Ok(entries)
+    }
+    fn read_last_audit(&self) -> Result<Option<AuditEntry>> {
+        Ok(self.audit.get("last").ok())
+    }
 }